@@ -0,0 +1,233 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::history::{self, Segment};
+
+// Keeps one request from building an unbounded response
+pub const MAX_BUCKETS: u64 = 10_000;
+
+// ========== Aggregation Request ==========
+#[derive(Clone, Copy)]
+pub enum Stat {
+    Count,
+    Min,
+    Max,
+    Mean,
+    Last,
+    /// `pNN`, 0–100, linearly interpolated between the nearest values.
+    Percentile(f64),
+}
+
+/// Parses one of `count`, `min`, `max`, `mean`, `last` or `p0`–`p100`.
+pub fn parse_stat(text: &str) -> Result<Stat, String> {
+    Ok(match text {
+        "count" => Stat::Count,
+        "min" => Stat::Min,
+        "max" => Stat::Max,
+        "mean" | "avg" => Stat::Mean,
+        "last" => Stat::Last,
+        _ => match text.strip_prefix('p').and_then(|p| p.parse::<f64>().ok()) {
+            Some(p) if (0.0..=100.0).contains(&p) => Stat::Percentile(p),
+            _ => return Err(format!("unknown statistic '{}'", text)),
+        },
+    })
+}
+
+/// Parses a bucket width such as `30s`, `1m`, `15m`, `1h` or `1d` into milliseconds.
+pub fn parse_bucket(text: &str) -> Result<u64, String> {
+    let split = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+    let (amount, unit) = text.split_at(split);
+    let unit_ms = match unit {
+        "s" => 1000,
+        "m" => 60_000,
+        "h" => 3_600_000,
+        "d" => 86_400_000,
+        _ => return Err(format!("bucket '{}' needs a unit of s, m, h or d", text)),
+    };
+    match amount.parse::<u64>().ok().filter(|&amount| amount > 0) {
+        Some(amount) => amount.checked_mul(unit_ms).ok_or_else(|| format!("bucket '{}' is too wide", text)),
+        None => Err(format!("bucket '{}' needs a positive whole amount", text)),
+    }
+}
+
+pub struct AggregateQuery {
+    /// Unix milliseconds, `from` inclusive and `to` exclusive.
+    pub from: u64,
+    pub to: u64,
+    pub bucket_ms: u64,
+    pub columns: Vec<String>,
+    /// Statistics with the names they are reported under.
+    pub stats: Vec<(String, Stat)>,
+}
+
+// ========== Aggregation ==========
+#[derive(Default)]
+struct Accumulator {
+    count: u64,
+    min: f64,
+    max: f64,
+    sum: f64,
+    last: f64,
+    /// Only kept when a percentile is requested.
+    values: Vec<f64>,
+}
+
+impl Accumulator {
+    fn add(&mut self, value: f64, keep: bool) {
+        if self.count == 0 {
+            self.min = value;
+            self.max = value;
+        }
+        self.count += 1;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.sum += value;
+        self.last = value;
+        if keep {
+            self.values.push(value);
+        }
+    }
+
+    fn stat(&mut self, stat: Stat) -> serde_json::Value {
+        match stat {
+            Stat::Count => self.count.into(),
+            Stat::Min => self.min.into(),
+            Stat::Max => self.max.into(),
+            Stat::Mean => (self.sum / self.count as f64).into(),
+            Stat::Last => self.last.into(),
+            Stat::Percentile(p) => {
+                self.values.sort_by(f64::total_cmp);
+                let rank = p / 100.0 * (self.values.len() - 1) as f64;
+                let (low, high) = (self.values[rank.floor() as usize], self.values[rank.ceil() as usize]);
+                (low + (high - low) * rank.fract()).into()
+            }
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Bucket {
+    /// Unix milliseconds; buckets are aligned to multiples of their width.
+    pub start: u64,
+    /// Statistics per column, for columns with at least one numeric reading.
+    pub columns: BTreeMap<String, serde_json::Map<String, serde_json::Value>>,
+}
+
+/// Buckets the numeric readings of the requested columns; non-numeric cells
+/// are ignored and empty buckets left out. Fails once more than
+/// `MAX_BUCKETS` buckets hold data. Blocking.
+pub fn aggregate(segments: &[Segment], query: &AggregateQuery) -> Result<Vec<Bucket>, String> {
+    let keep = query.stats.iter().any(|(_, s)| matches!(s, Stat::Percentile(_)));
+    let mut buckets: BTreeMap<u64, Vec<Accumulator>> = BTreeMap::new();
+    let mut too_many = false;
+    history::scan(segments, 0, Some(query.from), Some(query.to), |record| {
+        let start = record.time - record.time % query.bucket_ms;
+        if !buckets.contains_key(&start) && buckets.len() as u64 >= MAX_BUCKETS {
+            too_many = true;
+            return false;
+        }
+        let accumulators = buckets
+            .entry(start)
+            .or_insert_with(|| query.columns.iter().map(|_| Accumulator::default()).collect());
+        for (column, accumulator) in query.columns.iter().zip(accumulators.iter_mut()) {
+            if let Some(value) = record.values.get(column).and_then(|v| v.trim().parse::<f64>().ok()).filter(|v| v.is_finite()) {
+                accumulator.add(value, keep);
+            }
+        }
+        true
+    });
+    if too_many {
+        return Err(format!("more than {} buckets; widen the bucket or narrow the range", MAX_BUCKETS));
+    }
+    let buckets = buckets
+        .into_iter()
+        .map(|(start, accumulators)| {
+            let columns = query
+                .columns
+                .iter()
+                .zip(accumulators)
+                .filter(|(_, a)| a.count > 0)
+                .map(|(column, mut a)| {
+                    let stats = query.stats.iter().map(|(name, stat)| (name.clone(), a.stat(*stat))).collect();
+                    (column.clone(), stats)
+                })
+                .collect();
+            Bucket { start, columns }
+        })
+        .filter(|b| !b.columns.is_empty())
+        .collect();
+    Ok(buckets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::{HistoryConfig, HistoryStore};
+    use std::fs;
+
+    /// Segments holding `(time, value)` readings of column `t`, loaded the way a restart would.
+    fn segments(name: &str, readings: impl Iterator<Item = (u64, String)>) -> (String, Vec<Segment>) {
+        let dir = std::env::temp_dir().join(format!("aggregate-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let lines: String = readings
+            .enumerate()
+            .map(|(i, (time, value))| serde_json::json!({"seq": i + 1, "time": time, "values": {"t": value}}).to_string() + "\n")
+            .collect();
+        fs::write(dir.join(format!("{:020}.jsonl", 1)), lines).unwrap();
+        let config = HistoryConfig { dir: dir.to_string_lossy().into_owned(), ..HistoryConfig::default() };
+        // Retention never drops the segment being written to, however old
+        let segments = HistoryStore::open(&config).unwrap().segments();
+        (config.dir, segments)
+    }
+
+    fn query(from: u64, to: u64, bucket_ms: u64, stats: &[&str]) -> AggregateQuery {
+        AggregateQuery {
+            from,
+            to,
+            bucket_ms,
+            columns: vec!["t".to_string()],
+            stats: stats.iter().map(|s| (s.to_string(), parse_stat(s).unwrap())).collect(),
+        }
+    }
+
+    #[test]
+    fn buckets_and_stats_parse() {
+        assert_eq!(parse_bucket("30s"), Ok(30_000));
+        assert_eq!(parse_bucket("15m"), Ok(900_000));
+        assert_eq!(parse_bucket("1d"), Ok(86_400_000));
+        assert!(parse_bucket("0h").is_err());
+        assert!(parse_bucket("1w").is_err());
+        assert!(parse_bucket("99999999999999999d").is_err());
+        assert!(parse_stat("p101").is_err());
+        assert!(parse_stat("median").is_err());
+    }
+
+    #[test]
+    fn readings_are_bucketed_with_interpolated_percentiles() {
+        let readings = [(1_000, "1"), (2_000, "2"), (3_000, "3"), (4_000, "4"), (5_000, "n/a"), (61_000, "10"), (200_000, "99")];
+        let (dir, segments) = segments("stats", readings.iter().map(|&(t, v)| (t, v.to_string())));
+        let buckets = aggregate(&segments, &query(0, 120_000, 60_000, &["count", "min", "max", "mean", "last", "p50", "p90"])).unwrap();
+
+        assert_eq!(buckets.iter().map(|b| b.start).collect::<Vec<_>>(), [0, 60_000]);
+        let first = &buckets[0].columns["t"];
+        assert_eq!(first["count"], 4);
+        assert_eq!((first["min"].as_f64(), first["max"].as_f64()), (Some(1.0), Some(4.0)));
+        assert_eq!(first["mean"].as_f64(), Some(2.5));
+        assert_eq!(first["last"].as_f64(), Some(4.0));
+        assert_eq!(first["p50"].as_f64(), Some(2.5));
+        assert!((first["p90"].as_f64().unwrap() - 3.7).abs() < 1e-9);
+        assert_eq!(buckets[1].columns["t"]["count"], 1);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn too_many_buckets_is_an_error() {
+        let readings = (0..=MAX_BUCKETS).map(|i| (i * 1000, "1".to_string()));
+        let (dir, segments) = segments("limit", readings);
+        let to = (MAX_BUCKETS + 1) * 1000;
+        assert!(aggregate(&segments, &query(0, to, 1000, &["count"])).is_err());
+        assert_eq!(aggregate(&segments, &query(0, to, 2000, &["count"])).unwrap().len() as u64, MAX_BUCKETS / 2 + 1);
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,365 @@
+use actix_web::web;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::bacnet::{BacnetBackend, BacnetConfig};
+use crate::coap::{CoapBackend, CoapConfig};
+use crate::modbus::{ModbusBackend, ModbusRtuConfig, ModbusTcpConfig};
+use crate::mqtt::{MqttBackend, MqttConfig};
+use crate::opcua::{OpcUaBackend, OpcUaConfig};
+use crate::raw::{RawBackend, RawConfig};
+use crate::safety::SafetyViolation;
+use crate::snmp::{SnmpBackend, SnmpConfig};
+use crate::config::TelemetryDefinition;
+use crate::{AppState, CommandRequest};
+
+// ========== Row Model ==========
+pub type Row = Vec<String>;
+
+pub fn unix_timestamp() -> String {
+    SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs().to_string()
+}
+
+/// Lays out named values in header order; a `timestamp` header defaults to now.
+pub fn assemble_row(headers: &[String], values: &HashMap<String, String>) -> Row {
+    headers
+        .iter()
+        .map(|h| match values.get(h) {
+            Some(v) => v.clone(),
+            None if h == "timestamp" => unix_timestamp(),
+            None => String::new(),
+        })
+        .collect()
+}
+
+// ========== Payload Decoding ==========
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PayloadFormat {
+    /// JSON when the payload starts with `{` or `[`, CSV otherwise.
+    #[default]
+    Auto,
+    Csv,
+    Json,
+    Cbor,
+}
+
+/// Splits one CSV line into a row, requiring exactly one field per header.
+/// An empty `timestamp` field is filled with the time of receipt.
+pub fn parse_csv_line(headers: &[String], line: &str) -> Result<Row, String> {
+    let mut fields: Row = line.split(',').map(|f| f.trim().to_string()).collect();
+    if fields.len() != headers.len() {
+        return Err(format!("expected {} columns, got {}", headers.len(), fields.len()));
+    }
+    for (field, header) in fields.iter_mut().zip(headers) {
+        if header == "timestamp" && field.is_empty() {
+            *field = unix_timestamp();
+        }
+    }
+    Ok(fields)
+}
+
+/// Maps a JSON object keyed by header names into a row; unknown keys are ignored.
+pub fn parse_json_object(headers: &[String], value: &serde_json::Value) -> Result<Row, String> {
+    let object = value.as_object().ok_or("expected a JSON object")?;
+    let values = object
+        .iter()
+        .filter(|(k, _)| headers.contains(k))
+        .map(|(k, v)| {
+            let cell = match v {
+                serde_json::Value::String(s) => s.clone(),
+                serde_json::Value::Null => String::new(),
+                other => other.to_string(),
+            };
+            (k.clone(), cell)
+        })
+        .collect::<HashMap<_, _>>();
+    if values.is_empty() {
+        return Err("JSON object has no configured columns".to_string());
+    }
+    Ok(assemble_row(headers, &values))
+}
+
+fn decode_json(headers: &[String], value: serde_json::Value) -> Result<Vec<Row>, String> {
+    match value {
+        serde_json::Value::Array(items) => items.iter().map(|v| parse_json_object(headers, v)).collect(),
+        value => Ok(vec![parse_json_object(headers, &value)?]),
+    }
+}
+
+/// Decodes a message into rows: one per CSV line, JSON/CBOR object or array element.
+pub fn decode_payload(headers: &[String], payload: &[u8], format: PayloadFormat) -> Result<Vec<Row>, String> {
+    if let PayloadFormat::Cbor = format {
+        let value = ciborium::from_reader(payload).map_err(|e| e.to_string())?;
+        return decode_json(headers, value);
+    }
+    let text = std::str::from_utf8(payload).map_err(|e| e.to_string())?.trim();
+    let is_json = match format {
+        PayloadFormat::Auto => text.starts_with('{') || text.starts_with('['),
+        PayloadFormat::Csv => false,
+        PayloadFormat::Json | PayloadFormat::Cbor => true,
+    };
+    if is_json {
+        decode_json(headers, serde_json::from_str(text).map_err(|e| e.to_string())?)
+    } else {
+        text.lines()
+            .filter(|l| !l.trim().is_empty())
+            .map(|l| parse_csv_line(headers, l))
+            .collect()
+    }
+}
+
+// ========== Backend Errors ==========
+#[derive(Debug)]
+pub enum BackendError {
+    Io(std::io::Error),
+    Protocol(String),
+    NotConnected,
+    UnknownCommand(String),
+    InvalidParams(String),
+    /// Refused by the command's safety rules.
+    Unsafe(SafetyViolation),
+}
+
+impl fmt::Display for BackendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackendError::Io(e) => write!(f, "I/O error: {}", e),
+            BackendError::Protocol(msg) => write!(f, "protocol error: {}", msg),
+            BackendError::NotConnected => write!(f, "backend is not connected"),
+            BackendError::UnknownCommand(name) => write!(f, "Unknown command: {}", name),
+            BackendError::InvalidParams(msg) => write!(f, "{}", msg),
+            BackendError::Unsafe(violation) => write!(f, "{}", violation),
+        }
+    }
+}
+
+impl std::error::Error for BackendError {}
+
+impl From<SafetyViolation> for BackendError {
+    fn from(violation: SafetyViolation) -> Self {
+        BackendError::Unsafe(violation)
+    }
+}
+
+impl From<std::io::Error> for BackendError {
+    fn from(e: std::io::Error) -> Self {
+        BackendError::Io(e)
+    }
+}
+
+// ========== Device Backend Trait ==========
+/// A device protocol backend feeding `CsvData`.
+///
+/// `poll` returns the rows read since the previous call (possibly none), and
+/// `execute` returns a row to append when the command itself yields telemetry.
+pub trait DeviceBackend: Send {
+    fn connect(&mut self) -> Result<(), BackendError>;
+    fn poll(&mut self) -> Result<Vec<Row>, BackendError>;
+    fn execute(&mut self, command: &CommandRequest) -> Result<Option<Row>, BackendError>;
+    fn disconnect(&mut self);
+
+    /// Backend-specific counters reported by `GET /stats`.
+    fn stats(&self) -> serde_json::Value {
+        serde_json::json!({})
+    }
+}
+
+// ========== Simulated Backend ==========
+/// In-memory device: reports its current readings on every poll, `write` sets
+/// columns from its params and `reset` restores the initial readings.
+pub struct SimulatedBackend {
+    headers: Vec<String>,
+    initial: HashMap<String, String>,
+    values: HashMap<String, String>,
+}
+
+impl SimulatedBackend {
+    pub fn new(headers: &[String], temperature: f64) -> Self {
+        let initial = HashMap::from([
+            ("temperature".to_string(), format!("{:.2}", temperature)),
+            ("status".to_string(), "ok".to_string()),
+        ]);
+        SimulatedBackend {
+            headers: headers.to_vec(),
+            values: initial.clone(),
+            initial,
+        }
+    }
+
+    fn reading(&self) -> Row {
+        assemble_row(&self.headers, &self.values)
+    }
+
+    fn write(&mut self, params: Option<&serde_json::Value>) -> Result<(), BackendError> {
+        let params = params
+            .and_then(|p| p.as_object())
+            .filter(|p| !p.is_empty())
+            .ok_or_else(|| BackendError::InvalidParams("write needs at least one column value".to_string()))?;
+        for (column, value) in params {
+            if !self.headers.contains(column) || column == "timestamp" {
+                return Err(BackendError::InvalidParams(format!("Unknown column {}", column)));
+            }
+            let cell = match value {
+                serde_json::Value::Number(n) => format!("{:.2}", n.as_f64().unwrap_or_default()),
+                serde_json::Value::String(s) => s.clone(),
+                serde_json::Value::Null => return Err(BackendError::InvalidParams(format!("Missing {} parameter", column))),
+                other => other.to_string(),
+            };
+            self.values.insert(column.clone(), cell);
+        }
+        Ok(())
+    }
+}
+
+impl DeviceBackend for SimulatedBackend {
+    fn connect(&mut self) -> Result<(), BackendError> {
+        Ok(())
+    }
+
+    fn poll(&mut self) -> Result<Vec<Row>, BackendError> {
+        Ok(vec![self.reading()])
+    }
+
+    fn execute(&mut self, command: &CommandRequest) -> Result<Option<Row>, BackendError> {
+        match command.command.as_str() {
+            "write" => self.write(command.params.as_ref())?,
+            "reset" => self.values = self.initial.clone(),
+            other => return Err(BackendError::UnknownCommand(other.to_string())),
+        }
+        Ok(Some(self.reading()))
+    }
+
+    fn disconnect(&mut self) {}
+}
+
+// ========== Backend Selection ==========
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BackendConfig {
+    Simulated {
+        #[serde(default = "default_temperature")]
+        temperature: f64,
+    },
+    ModbusTcp(ModbusTcpConfig),
+    ModbusRtu(ModbusRtuConfig),
+    Mqtt(MqttConfig),
+    #[serde(rename = "opcua")]
+    OpcUa(OpcUaConfig),
+    Raw(RawConfig),
+    Coap(CoapConfig),
+    Bacnet(BacnetConfig),
+    Snmp(SnmpConfig),
+}
+
+fn default_temperature() -> f64 {
+    25.0
+}
+
+impl Default for BackendConfig {
+    fn default() -> Self {
+        BackendConfig::Simulated {
+            temperature: default_temperature(),
+        }
+    }
+}
+
+impl BackendConfig {
+    pub fn build(self, headers: &[String]) -> Result<Box<dyn DeviceBackend>, String> {
+        Ok(match self {
+            BackendConfig::Simulated { temperature } => Box::new(SimulatedBackend::new(headers, temperature)),
+            BackendConfig::ModbusTcp(config) => Box::new(ModbusBackend::tcp(config, headers)?),
+            BackendConfig::ModbusRtu(config) => Box::new(ModbusBackend::rtu(config, headers)?),
+            BackendConfig::Mqtt(config) => Box::new(MqttBackend::new(config, headers)?),
+            BackendConfig::OpcUa(config) => Box::new(OpcUaBackend::new(config, headers)?),
+            BackendConfig::Raw(config) => Box::new(RawBackend::new(config, headers)?),
+            BackendConfig::Coap(config) => Box::new(CoapBackend::new(config, headers)?),
+            BackendConfig::Bacnet(config) => Box::new(BacnetBackend::new(config, headers)?),
+            BackendConfig::Snmp(config) => Box::new(SnmpBackend::new(config, headers)?),
+        })
+    }
+}
+
+// ========== Poller ==========
+/// Drives `connect`/`poll` on a background thread, reconnecting after errors
+/// and whenever a config reload swaps the backend.
+pub fn spawn_poller(state: web::Data<AppState>) {
+    thread::spawn(move || {
+        let mut connected = false;
+        let mut generation = state.settings().generation;
+        loop {
+            let settings = state.settings();
+            if settings.generation != generation {
+                generation = settings.generation;
+                connected = false;
+            }
+            if !connected {
+                match state.backend.lock().unwrap().connect() {
+                    Ok(()) => connected = true,
+                    Err(e) => log::warn!("backend connect failed: {}", e),
+                }
+            }
+            if connected {
+                // Holding the backend until the rows are pushed keeps a reload
+                // from changing the column layout in between
+                let mut device = state.backend.lock().unwrap();
+                match device.poll() {
+                    // Rows polled just before a reload still have the old layout
+                    Ok(_) if state.settings().generation != generation => {}
+                    Ok(rows) => {
+                        let mut csv_data = state.csv_data.lock().unwrap();
+                        for row in rows {
+                            csv_data.push_row(row);
+                        }
+                    }
+                    Err(e) => {
+                        log::warn!("backend poll failed: {}", e);
+                        device.disconnect();
+                        connected = false;
+                    }
+                }
+            }
+            thread::sleep(settings.poll_interval);
+        }
+    });
+}
+
+/// Calls a telemetry's instruction on its own interval, recording any row it
+/// returns, until a config reload replaces the settings it was started with.
+pub fn spawn_telemetry(state: web::Data<AppState>, telemetry: TelemetryDefinition, generation: u64) {
+    thread::spawn(move || {
+        thread::sleep(Duration::from_millis(telemetry.initial_delay_ms));
+        loop {
+            let settings = state.settings();
+            if settings.generation != generation {
+                return;
+            }
+            let request = CommandRequest {
+                command: telemetry.instruction.clone(),
+                params: None,
+                request_id: None,
+            };
+            let result = settings.commands.resolve(request).and_then(|resolved| {
+                let mut device = state.backend.lock().unwrap();
+                // A reload may have swapped the backend while we waited for the lock
+                if state.settings().generation != generation {
+                    return Ok(false);
+                }
+                // Pushed before the backend is released, as in the poller
+                if let Some(row) = resolved.run(device.as_mut(), &state)?.row {
+                    state.csv_data.lock().unwrap().push_row(row);
+                }
+                Ok(true)
+            });
+            match result {
+                Ok(true) => {}
+                Ok(false) => return,
+                Err(e) => log::warn!("telemetry {} failed: {}", telemetry.name, e),
+            }
+            thread::sleep(Duration::from_millis(telemetry.interval_ms));
+        }
+    });
+}
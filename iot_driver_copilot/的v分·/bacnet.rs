@@ -0,0 +1,804 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
+
+use crate::backend::{assemble_row, BackendError, DeviceBackend, Row};
+use crate::CommandRequest;
+
+const BVLC_TYPE: u8 = 0x81;
+const BVLC_ORIGINAL_UNICAST: u8 = 0x0A;
+const NPDU_VERSION: u8 = 0x01;
+
+const PDU_CONFIRMED_REQUEST: u8 = 0x0;
+const PDU_SIMPLE_ACK: u8 = 0x2;
+const PDU_COMPLEX_ACK: u8 = 0x3;
+const PDU_ERROR: u8 = 0x5;
+const PDU_REJECT: u8 = 0x6;
+const PDU_ABORT: u8 = 0x7;
+
+const SERVICE_READ_PROPERTY: u8 = 12;
+const SERVICE_READ_PROPERTY_MULTIPLE: u8 = 14;
+const SERVICE_WRITE_PROPERTY: u8 = 15;
+
+const PROPERTY_PRESENT_VALUE: u32 = 85;
+// Max APDU 1476 octets, no segmentation
+const MAX_APDU_1476: u8 = 0x05;
+
+// ========== BACnet Config ==========
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum ObjectType {
+    AnalogInput,
+    AnalogOutput,
+    AnalogValue,
+    BinaryInput,
+    BinaryOutput,
+    BinaryValue,
+    MultiStateInput,
+    MultiStateOutput,
+    MultiStateValue,
+}
+
+impl ObjectType {
+    fn code(self) -> u32 {
+        match self {
+            ObjectType::AnalogInput => 0,
+            ObjectType::AnalogOutput => 1,
+            ObjectType::AnalogValue => 2,
+            ObjectType::BinaryInput => 3,
+            ObjectType::BinaryOutput => 4,
+            ObjectType::BinaryValue => 5,
+            ObjectType::MultiStateInput => 13,
+            ObjectType::MultiStateOutput => 14,
+            ObjectType::MultiStateValue => 19,
+        }
+    }
+}
+
+fn default_property() -> u32 {
+    PROPERTY_PRESENT_VALUE
+}
+
+fn default_timeout_ms() -> u64 {
+    3000
+}
+
+fn default_retries() -> u32 {
+    3
+}
+
+fn default_bind() -> String {
+    "0.0.0.0:0".to_string()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Deserialize, Clone)]
+pub struct BacnetPoint {
+    pub column: String,
+    pub object_type: ObjectType,
+    pub instance: u32,
+    #[serde(default = "default_property")]
+    pub property: u32,
+    pub precision: Option<usize>,
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum BacnetValueType {
+    #[default]
+    Real,
+    Unsigned,
+    Enumerated,
+    Boolean,
+}
+
+/// Maps a `/cmd` command onto a WriteProperty of `params[param]`; a null param relinquishes the priority slot.
+#[derive(Deserialize, Clone)]
+pub struct BacnetCommand {
+    pub name: String,
+    pub object_type: ObjectType,
+    pub instance: u32,
+    #[serde(default = "default_property")]
+    pub property: u32,
+    pub param: String,
+    #[serde(default)]
+    pub value_type: BacnetValueType,
+    /// Command priority 1-16; omitted for properties without a priority array.
+    pub priority: Option<u8>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct BacnetConfig {
+    /// Device `ip:port`, usually port 47808.
+    pub address: String,
+    #[serde(default = "default_bind")]
+    pub bind: String,
+    /// Remote network number and MAC (hex) when the device sits behind a BACnet router.
+    pub network: Option<u16>,
+    pub mac_address: Option<String>,
+    #[serde(default = "default_true")]
+    pub read_property_multiple: bool,
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+    pub points: Vec<BacnetPoint>,
+    #[serde(default)]
+    pub commands: Vec<BacnetCommand>,
+}
+
+// ========== Tag Encoding ==========
+fn object_id(object_type: ObjectType, instance: u32) -> u32 {
+    (object_type.code() << 22) | (instance & 0x3F_FFFF)
+}
+
+/// Shortest big-endian encoding of an unsigned value.
+fn unsigned_bytes(value: u32) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let skip = bytes.iter().take(3).take_while(|&&b| b == 0).count();
+    bytes[skip..].to_vec()
+}
+
+fn push_tag(out: &mut Vec<u8>, number: u8, context: bool, data: &[u8]) {
+    let class = if context { 0x08 } else { 0x00 };
+    if data.len() < 5 {
+        out.push((number << 4) | class | data.len() as u8);
+    } else {
+        out.push((number << 4) | class | 5);
+        out.push(data.len() as u8);
+    }
+    out.extend_from_slice(data);
+}
+
+fn push_context_unsigned(out: &mut Vec<u8>, number: u8, value: u32) {
+    push_tag(out, number, true, &unsigned_bytes(value));
+}
+
+fn push_context_object_id(out: &mut Vec<u8>, number: u8, id: u32) {
+    push_tag(out, number, true, &id.to_be_bytes());
+}
+
+fn push_opening(out: &mut Vec<u8>, number: u8) {
+    out.push((number << 4) | 0x0E);
+}
+
+fn push_closing(out: &mut Vec<u8>, number: u8) {
+    out.push((number << 4) | 0x0F);
+}
+
+fn push_application_value(out: &mut Vec<u8>, value_type: BacnetValueType, value: &serde_json::Value) -> Result<(), BackendError> {
+    let invalid = || BackendError::InvalidParams(format!("cannot write {} as a BACnet value", value));
+    if value.is_null() {
+        out.push(0x00); // application NULL relinquishes the slot
+        return Ok(());
+    }
+    match value_type {
+        BacnetValueType::Real => {
+            let v = value.as_f64().ok_or_else(invalid)? as f32;
+            push_tag(out, 4, false, &v.to_be_bytes());
+        }
+        BacnetValueType::Unsigned => {
+            let v = value.as_u64().and_then(|v| u32::try_from(v).ok()).ok_or_else(invalid)?;
+            push_tag(out, 2, false, &unsigned_bytes(v));
+        }
+        BacnetValueType::Enumerated => {
+            let v = value.as_u64().and_then(|v| u32::try_from(v).ok()).ok_or_else(invalid)?;
+            push_tag(out, 9, false, &unsigned_bytes(v));
+        }
+        BacnetValueType::Boolean => {
+            let v = value.as_bool().ok_or_else(invalid)?;
+            out.push(0x10 | v as u8); // boolean value lives in the length bits
+        }
+    }
+    Ok(())
+}
+
+// ========== Tag Decoding ==========
+struct Tag {
+    number: u8,
+    context: bool,
+    /// Data length, or the boolean value for an application boolean tag.
+    len: u32,
+    opening: bool,
+    closing: bool,
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+fn malformed() -> BackendError {
+    BackendError::Protocol("malformed BACnet APDU".to_string())
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], BackendError> {
+        let slice = self.buf.get(self.pos..self.pos + n).ok_or_else(malformed)?;
+        self.pos += n;
+        Ok(slice)
+    }
+
+    fn peek_tag(&mut self) -> Result<Tag, BackendError> {
+        let start = self.pos;
+        let tag = self.tag();
+        self.pos = start;
+        tag
+    }
+
+    fn tag(&mut self) -> Result<Tag, BackendError> {
+        let first = self.take(1)?[0];
+        let mut number = first >> 4;
+        if number == 0x0F {
+            number = self.take(1)?[0];
+        }
+        let context = first & 0x08 != 0;
+        let lvt = (first & 0x07) as u32;
+        if context && lvt == 6 {
+            return Ok(Tag { number, context, len: 0, opening: true, closing: false });
+        }
+        if context && lvt == 7 {
+            return Ok(Tag { number, context, len: 0, opening: false, closing: true });
+        }
+        let length = match lvt {
+            5 => match self.take(1)?[0] {
+                254 => u16::from_be_bytes(self.take(2)?.try_into().unwrap()) as u32,
+                255 => u32::from_be_bytes(self.take(4)?.try_into().unwrap()),
+                n => n as u32,
+            },
+            n => n,
+        };
+        Ok(Tag { number, context, len: length, opening: false, closing: false })
+    }
+
+    fn unsigned(&mut self, len: u32) -> Result<u32, BackendError> {
+        Ok(self.take(len as usize)?.iter().fold(0u32, |acc, &b| (acc << 8) | b as u32))
+    }
+
+    fn expect_context(&mut self, number: u8) -> Result<Tag, BackendError> {
+        let tag = self.tag()?;
+        if !tag.context || tag.number != number {
+            return Err(malformed());
+        }
+        Ok(tag)
+    }
+
+    /// Skips a primitive tag or a whole constructed (opening..closing) value.
+    fn skip_value(&mut self) -> Result<(), BackendError> {
+        let tag = self.tag()?;
+        if tag.opening {
+            while !self.peek_tag()?.closing {
+                self.skip_value()?;
+            }
+            self.tag()?;
+        } else if tag.number != 1 || tag.context {
+            self.take(tag.len as usize)?;
+        }
+        Ok(())
+    }
+
+    /// Renders one application-tagged value as a CSV cell.
+    fn application_value(&mut self, precision: Option<usize>) -> Result<String, BackendError> {
+        let tag = self.tag()?;
+        if tag.context {
+            return Err(BackendError::Protocol("unexpected context tag in property value".to_string()));
+        }
+        let len = tag.len;
+        Ok(match tag.number {
+            0 => String::new(),
+            1 => (len != 0).to_string(),
+            2 | 9 => self.unsigned(len)?.to_string(),
+            3 => {
+                if len == 0 {
+                    return Err(BackendError::Protocol("zero-length BACnet signed integer".to_string()));
+                }
+                let raw = self.unsigned(len)?;
+                let shift = 32 - 8 * len.min(4);
+                (raw.wrapping_shl(shift) as i32).wrapping_shr(shift).to_string()
+            }
+            4 => {
+                let v = f32::from_be_bytes(self.take(4)?.try_into().unwrap()) as f64;
+                format!("{:.*}", precision.unwrap_or(2), v)
+            }
+            5 => {
+                let v = f64::from_be_bytes(self.take(8)?.try_into().unwrap());
+                format!("{:.*}", precision.unwrap_or(2), v)
+            }
+            7 => {
+                let data = self.take(len as usize)?;
+                // First octet is the character set; only UTF-8/ANSI X3.4 (0) is decoded
+                String::from_utf8_lossy(data.get(1..).unwrap_or_default()).into_owned()
+            }
+            8 => {
+                let data = self.take(len as usize)?;
+                let unused = *data.first().unwrap_or(&0) as usize;
+                let bits: String = data.iter().skip(1).flat_map(|b| (0..8).rev().map(move |i| if b >> i & 1 == 1 { '1' } else { '0' })).collect();
+                bits[..bits.len().saturating_sub(unused)].to_string()
+            }
+            _ => self.take(len as usize)?.iter().map(|b| format!("{:02x}", b)).collect(),
+        })
+    }
+}
+
+fn error_class_code(reader: &mut Reader) -> String {
+    let mut read_enum = || -> Result<u32, BackendError> {
+        let tag = reader.tag()?;
+        reader.unsigned(tag.len)
+    };
+    match (read_enum(), read_enum()) {
+        (Ok(class), Ok(code)) => format!("error class {} code {}", class, code),
+        _ => "unknown error".to_string(),
+    }
+}
+
+// ========== BACnet Backend ==========
+/// Outcome of a confirmed request that reached the device.
+enum Reply {
+    /// Service data of a Simple- or Complex-ACK.
+    Ack(Vec<u8>),
+    /// The device turned the service down with a Reject or Abort PDU.
+    Refused(String),
+}
+
+pub struct BacnetBackend {
+    config: BacnetConfig,
+    headers: Vec<String>,
+    peer: SocketAddr,
+    route: Option<(u16, Vec<u8>)>,
+    socket: Option<UdpSocket>,
+    invoke_id: u8,
+    use_rpm: bool,
+}
+
+impl BacnetBackend {
+    pub fn new(config: BacnetConfig, headers: &[String]) -> Result<Self, String> {
+        let peer = config
+            .address
+            .to_socket_addrs()
+            .map_err(|e| format!("cannot resolve {}: {}", config.address, e))?
+            .next()
+            .ok_or_else(|| format!("cannot resolve {}", config.address))?;
+        let route = match (config.network, &config.mac_address) {
+            (Some(network), Some(mac)) => {
+                let bytes = (0..mac.len())
+                    .step_by(2)
+                    .map(|i| mac.get(i..i + 2).and_then(|h| u8::from_str_radix(h, 16).ok()))
+                    .collect::<Option<Vec<u8>>>()
+                    .ok_or_else(|| format!("invalid BACnet mac_address '{}'", mac))?;
+                Some((network, bytes))
+            }
+            (None, None) => None,
+            _ => return Err("BACnet routing needs both network and mac_address".to_string()),
+        };
+        for point in &config.points {
+            if !headers.contains(&point.column) {
+                return Err(format!("bacnet point column '{}' is not a CSV header", point.column));
+            }
+        }
+        for command in &config.commands {
+            if matches!(command.priority, Some(p) if !(1..=16).contains(&p)) {
+                return Err(format!("bacnet command '{}' priority must be 1-16", command.name));
+            }
+        }
+        Ok(BacnetBackend {
+            use_rpm: config.read_property_multiple,
+            config,
+            headers: headers.to_vec(),
+            peer,
+            route,
+            socket: None,
+            invoke_id: 0,
+        })
+    }
+
+    fn frame(&self, apdu: &[u8]) -> Vec<u8> {
+        let mut npdu = vec![NPDU_VERSION];
+        match &self.route {
+            Some((network, mac)) => {
+                npdu.push(0x24); // destination specifier + expecting reply
+                npdu.extend_from_slice(&network.to_be_bytes());
+                npdu.push(mac.len() as u8);
+                npdu.extend_from_slice(mac);
+                npdu.push(0xFF); // hop count
+            }
+            None => npdu.push(0x04),
+        }
+        let length = 4 + npdu.len() + apdu.len();
+        let mut frame = vec![BVLC_TYPE, BVLC_ORIGINAL_UNICAST];
+        frame.extend_from_slice(&(length as u16).to_be_bytes());
+        frame.extend_from_slice(&npdu);
+        frame.extend_from_slice(apdu);
+        frame
+    }
+
+    /// Strips BVLC and NPDU headers, returning the APDU of a reply.
+    fn unwrap_frame(datagram: &[u8]) -> Option<&[u8]> {
+        if datagram.len() < 6 || datagram[0] != BVLC_TYPE || datagram[4] != NPDU_VERSION {
+            return None;
+        }
+        let control = datagram[5];
+        if control & 0x80 != 0 {
+            return None; // network layer message
+        }
+        let mut pos = 6;
+        if control & 0x20 != 0 {
+            let dlen = *datagram.get(pos + 2)? as usize;
+            pos += 3 + dlen;
+        }
+        if control & 0x08 != 0 {
+            let slen = *datagram.get(pos + 2)? as usize;
+            pos += 3 + slen;
+        }
+        if control & 0x20 != 0 {
+            pos += 1; // hop count
+        }
+        datagram.get(pos..)
+    }
+
+    /// Sends a confirmed request and returns the service data of its Simple- or Complex-ACK.
+    fn confirmed_request(&mut self, service: u8, data: &[u8]) -> Result<Vec<u8>, BackendError> {
+        match self.exchange(service, data)? {
+            Reply::Ack(data) => Ok(data),
+            Reply::Refused(reason) => Err(BackendError::Protocol(reason)),
+        }
+    }
+
+    fn exchange(&mut self, service: u8, data: &[u8]) -> Result<Reply, BackendError> {
+        self.invoke_id = self.invoke_id.wrapping_add(1);
+        let invoke_id = self.invoke_id;
+        let mut apdu = vec![PDU_CONFIRMED_REQUEST << 4, MAX_APDU_1476, invoke_id, service];
+        apdu.extend_from_slice(data);
+        let frame = self.frame(&apdu);
+        let timeout = Duration::from_millis(self.config.timeout_ms);
+        let socket = self.socket.as_ref().ok_or(BackendError::NotConnected)?;
+
+        for _ in 0..=self.config.retries {
+            socket.send_to(&frame, self.peer)?;
+            let deadline = Instant::now() + timeout;
+            while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+                socket.set_read_timeout(Some(left.max(Duration::from_millis(1))))?;
+                let mut buf = [0u8; 1500];
+                let (n, from) = match socket.recv_from(&mut buf) {
+                    Ok(r) => r,
+                    Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => break,
+                    Err(e) => return Err(e.into()),
+                };
+                if from != self.peer {
+                    continue;
+                }
+                let Some(reply) = Self::unwrap_frame(&buf[..n]) else { continue };
+                if reply.len() < 3 || reply[1] != invoke_id {
+                    continue;
+                }
+                return match reply[0] >> 4 {
+                    PDU_SIMPLE_ACK => Ok(Reply::Ack(Vec::new())),
+                    PDU_COMPLEX_ACK if reply[0] & 0x08 != 0 => {
+                        Err(BackendError::Protocol("segmented BACnet responses are not supported".to_string()))
+                    }
+                    PDU_COMPLEX_ACK => Ok(Reply::Ack(reply.get(3..).unwrap_or_default().to_vec())),
+                    PDU_ERROR => {
+                        let mut reader = Reader { buf: reply, pos: 3 };
+                        Err(BackendError::Protocol(format!("BACnet {}", error_class_code(&mut reader))))
+                    }
+                    PDU_REJECT => Ok(Reply::Refused(format!("BACnet reject reason {}", reply[2]))),
+                    PDU_ABORT => Ok(Reply::Refused(format!("BACnet abort reason {}", reply[2]))),
+                    other => Err(BackendError::Protocol(format!("unexpected BACnet PDU type {}", other))),
+                };
+            }
+        }
+        Err(BackendError::Io(std::io::Error::new(ErrorKind::TimedOut, "BACnet request timed out")))
+    }
+
+    fn read_property(&mut self, point: &BacnetPoint) -> Result<String, BackendError> {
+        let mut data = Vec::new();
+        push_context_object_id(&mut data, 0, object_id(point.object_type, point.instance));
+        push_context_unsigned(&mut data, 1, point.property);
+        let ack = self.confirmed_request(SERVICE_READ_PROPERTY, &data)?;
+
+        let mut reader = Reader { buf: &ack, pos: 0 };
+        reader.expect_context(0).and_then(|t| reader.take(t.len as usize))?;
+        reader.expect_context(1).and_then(|t| reader.take(t.len as usize))?;
+        if reader.peek_tag()?.number == 2 && !reader.peek_tag()?.opening {
+            reader.skip_value()?; // array index
+        }
+        if !reader.tag()?.opening {
+            return Err(malformed());
+        }
+        reader.application_value(point.precision)
+    }
+
+    /// Reads every point in one request; `None` when the device refuses the service.
+    fn read_property_multiple(&mut self) -> Result<Option<HashMap<String, String>>, BackendError> {
+        let mut data = Vec::new();
+        for point in &self.config.points {
+            push_context_object_id(&mut data, 0, object_id(point.object_type, point.instance));
+            push_opening(&mut data, 1);
+            push_context_unsigned(&mut data, 0, point.property);
+            push_closing(&mut data, 1);
+        }
+        let ack = match self.exchange(SERVICE_READ_PROPERTY_MULTIPLE, &data)? {
+            Reply::Ack(ack) => ack,
+            Reply::Refused(reason) => {
+                log::warn!("ReadPropertyMultiple refused ({}); falling back to ReadProperty", reason);
+                return Ok(None);
+            }
+        };
+
+        // Results come back in request order, one object block per requested point
+        let mut values = HashMap::new();
+        let mut reader = Reader { buf: &ack, pos: 0 };
+        for point in &self.config.points {
+            reader.expect_context(0).and_then(|t| reader.take(t.len as usize))?;
+            if !reader.tag()?.opening {
+                return Err(malformed());
+            }
+            while !reader.peek_tag()?.closing {
+                let tag = reader.tag()?;
+                match (tag.number, tag.opening) {
+                    (2, false) | (3, false) => {
+                        reader.take(tag.len as usize)?;
+                    }
+                    (4, true) => {
+                        values.insert(point.column.clone(), reader.application_value(point.precision)?);
+                        while !reader.peek_tag()?.closing {
+                            reader.skip_value()?;
+                        }
+                        reader.tag()?;
+                    }
+                    (5, true) => {
+                        log::warn!("bacnet read of {} failed: {}", point.column, error_class_code(&mut reader));
+                        reader.tag()?;
+                    }
+                    _ => return Err(malformed()),
+                }
+            }
+            reader.tag()?;
+        }
+        Ok(Some(values))
+    }
+
+    fn write_property(&mut self, command: &BacnetCommand, value: &serde_json::Value) -> Result<(), BackendError> {
+        let mut data = Vec::new();
+        push_context_object_id(&mut data, 0, object_id(command.object_type, command.instance));
+        push_context_unsigned(&mut data, 1, command.property);
+        push_opening(&mut data, 3);
+        push_application_value(&mut data, command.value_type, value)?;
+        push_closing(&mut data, 3);
+        if let Some(priority) = command.priority {
+            push_context_unsigned(&mut data, 4, priority as u32);
+        }
+        self.confirmed_request(SERVICE_WRITE_PROPERTY, &data)?;
+        Ok(())
+    }
+}
+
+impl DeviceBackend for BacnetBackend {
+    fn connect(&mut self) -> Result<(), BackendError> {
+        self.socket = Some(UdpSocket::bind(&self.config.bind)?);
+        Ok(())
+    }
+
+    fn poll(&mut self) -> Result<Vec<Row>, BackendError> {
+        let values = if self.use_rpm {
+            match self.read_property_multiple()? {
+                Some(values) => values,
+                None => {
+                    self.use_rpm = false;
+                    return self.poll();
+                }
+            }
+        } else {
+            let mut values = HashMap::new();
+            for point in self.config.points.clone() {
+                match self.read_property(&point) {
+                    Ok(value) => {
+                        values.insert(point.column.clone(), value);
+                    }
+                    Err(BackendError::Protocol(e)) => log::warn!("bacnet read of {} failed: {}", point.column, e),
+                    Err(e) => return Err(e),
+                }
+            }
+            values
+        };
+        Ok(vec![assemble_row(&self.headers, &values)])
+    }
+
+    fn execute(&mut self, command: &CommandRequest) -> Result<Option<Row>, BackendError> {
+        let mapping = self
+            .config
+            .commands
+            .iter()
+            .find(|c| c.name == command.command)
+            .cloned()
+            .ok_or_else(|| BackendError::UnknownCommand(command.command.clone()))?;
+        let value = command
+            .params
+            .as_ref()
+            .and_then(|p| p.get(&mapping.param))
+            .cloned()
+            .ok_or_else(|| BackendError::InvalidParams(format!("Missing {} parameter", mapping.param)))?;
+        self.write_property(&mapping, &value)?;
+        Ok(None)
+    }
+
+    fn disconnect(&mut self) {
+        self.socket = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    type Objects = Arc<Mutex<HashMap<u32, Vec<u8>>>>;
+
+    /// Reads a context tag holding an object identifier or unsigned value.
+    fn context_unsigned(reader: &mut Reader, number: u8) -> u32 {
+        let tag = reader.expect_context(number).unwrap();
+        reader.unsigned(tag.len).unwrap()
+    }
+
+    fn ack(invoke_id: u8, service: u8, data: &[u8]) -> Vec<u8> {
+        let mut apdu = vec![PDU_COMPLEX_ACK << 4, invoke_id, service];
+        apdu.extend_from_slice(data);
+        apdu
+    }
+
+    /// Answers ReadProperty, WriteProperty and, unless `reject_rpm`, ReadPropertyMultiple
+    /// from a map of object ids to encoded present values.
+    fn serve(objects: Objects, reject_rpm: bool) -> String {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap().to_string();
+        thread::spawn(move || loop {
+            let mut buf = [0u8; 1500];
+            let (n, peer) = socket.recv_from(&mut buf).unwrap();
+            let apdu = BacnetBackend::unwrap_frame(&buf[..n]).unwrap().to_vec();
+            let (invoke_id, service) = (apdu[2], apdu[3]);
+            let mut reader = Reader { buf: &apdu, pos: 4 };
+            let mut objects = objects.lock().unwrap();
+            let reply = match service {
+                SERVICE_READ_PROPERTY_MULTIPLE if reject_rpm => vec![PDU_REJECT << 4, invoke_id, 9],
+                SERVICE_READ_PROPERTY_MULTIPLE => {
+                    let mut data = Vec::new();
+                    while reader.pos < apdu.len() {
+                        let id = context_unsigned(&mut reader, 0);
+                        reader.tag().unwrap();
+                        let property = context_unsigned(&mut reader, 0);
+                        reader.tag().unwrap();
+                        push_context_object_id(&mut data, 0, id);
+                        push_opening(&mut data, 1);
+                        push_context_unsigned(&mut data, 2, property);
+                        match objects.get(&id) {
+                            Some(value) => {
+                                push_opening(&mut data, 4);
+                                data.extend_from_slice(value);
+                                push_closing(&mut data, 4);
+                            }
+                            None => {
+                                push_opening(&mut data, 5);
+                                push_tag(&mut data, 9, false, &[1]); // class object
+                                push_tag(&mut data, 9, false, &[31]); // code unknown-object
+                                push_closing(&mut data, 5);
+                            }
+                        }
+                        push_closing(&mut data, 1);
+                    }
+                    ack(invoke_id, service, &data)
+                }
+                SERVICE_READ_PROPERTY => {
+                    let id = context_unsigned(&mut reader, 0);
+                    let property = context_unsigned(&mut reader, 1);
+                    let mut data = Vec::new();
+                    push_context_object_id(&mut data, 0, id);
+                    push_context_unsigned(&mut data, 1, property);
+                    push_opening(&mut data, 3);
+                    data.extend_from_slice(&objects[&id]);
+                    push_closing(&mut data, 3);
+                    ack(invoke_id, service, &data)
+                }
+                SERVICE_WRITE_PROPERTY => {
+                    let id = context_unsigned(&mut reader, 0);
+                    context_unsigned(&mut reader, 1);
+                    reader.tag().unwrap();
+                    let start = reader.pos;
+                    reader.skip_value().unwrap();
+                    objects.insert(id, apdu[start..reader.pos].to_vec());
+                    vec![PDU_SIMPLE_ACK << 4, invoke_id, service]
+                }
+                _ => vec![PDU_REJECT << 4, invoke_id, 9],
+            };
+            let mut frame = vec![BVLC_TYPE, BVLC_ORIGINAL_UNICAST];
+            frame.extend_from_slice(&(6 + reply.len() as u16).to_be_bytes());
+            frame.extend_from_slice(&[NPDU_VERSION, 0x00]);
+            frame.extend_from_slice(&reply);
+            socket.send_to(&frame, peer).unwrap();
+        });
+        address
+    }
+
+    fn objects() -> Objects {
+        let mut temperature = Vec::new();
+        push_tag(&mut temperature, 4, false, &21.5f32.to_be_bytes());
+        let mut setpoint = Vec::new();
+        push_tag(&mut setpoint, 4, false, &20.0f32.to_be_bytes());
+        let mut offset = Vec::new();
+        push_tag(&mut offset, 3, false, &[0xFB]);
+        Arc::new(Mutex::new(HashMap::from([
+            (object_id(ObjectType::AnalogInput, 1), temperature),
+            (object_id(ObjectType::AnalogValue, 2), setpoint),
+            (object_id(ObjectType::AnalogValue, 3), offset),
+        ])))
+    }
+
+    fn backend(address: String) -> BacnetBackend {
+        let config: BacnetConfig = serde_json::from_value(serde_json::json!({
+            "address": address,
+            "bind": "127.0.0.1:0",
+            "timeout_ms": 500,
+            "retries": 0,
+            "points": [
+                {"column": "temperature", "object_type": "analog_input", "instance": 1, "precision": 1},
+                {"column": "setpoint", "object_type": "analog_value", "instance": 2},
+                {"column": "offset", "object_type": "analog_value", "instance": 3},
+            ],
+            "commands": [{"name": "set_setpoint", "object_type": "analog_value", "instance": 2, "param": "value", "priority": 8}],
+        }))
+        .unwrap();
+        let headers = ["temperature", "setpoint", "offset"].map(String::from);
+        BacnetBackend::new(config, &headers).unwrap()
+    }
+
+    fn row(values: [&str; 3]) -> Vec<Row> {
+        vec![values.map(String::from).to_vec()]
+    }
+
+    #[test]
+    fn reads_multiple_properties_and_writes() {
+        let objects = objects();
+        let mut backend = backend(serve(objects.clone(), false));
+
+        backend.connect().unwrap();
+        assert_eq!(backend.poll().unwrap(), row(["21.5", "20.00", "-5"]));
+        let command = CommandRequest {
+            command: "set_setpoint".to_string(),
+            params: Some(serde_json::json!({"value": 22.5})),
+            request_id: None,
+        };
+        backend.execute(&command).unwrap();
+        assert_eq!(backend.poll().unwrap(), row(["21.5", "22.50", "-5"]));
+        assert!(backend.use_rpm);
+    }
+
+    #[test]
+    fn falls_back_to_read_property_when_rejected() {
+        let mut backend = backend(serve(objects(), true));
+
+        backend.connect().unwrap();
+        assert_eq!(backend.poll().unwrap(), row(["21.5", "20.00", "-5"]));
+        assert!(!backend.use_rpm);
+    }
+
+    #[test]
+    fn keeps_read_property_multiple_after_other_errors() {
+        let objects = objects();
+        objects.lock().unwrap().insert(object_id(ObjectType::AnalogInput, 1), vec![0x30]);
+        let mut backend = backend(serve(objects, false));
+
+        backend.connect().unwrap();
+        assert!(backend.poll().is_err());
+        assert!(backend.use_rpm);
+    }
+
+    #[test]
+    fn zero_length_signed_integer_is_a_protocol_error() {
+        let mut reader = Reader { buf: &[0x30], pos: 0 };
+        assert!(matches!(reader.application_value(None), Err(BackendError::Protocol(_))));
+    }
+}
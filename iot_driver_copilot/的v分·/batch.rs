@@ -0,0 +1,301 @@
+use serde::{Deserialize, Serialize};
+
+use crate::backend::BackendError;
+use crate::commands::{CommandRegistry, ResolvedCommand};
+use crate::jobs::row_object;
+use crate::safety::SafetyViolation;
+use crate::verify::Verification;
+use crate::{AppState, CommandRequest};
+
+// Keeps one batch from monopolising the backend for too long
+pub const MAX_STEPS: usize = 100;
+
+// ========== Batch Model ==========
+#[derive(Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchMode {
+    /// Stop at the first failure and run the rollbacks of the steps that succeeded.
+    #[default]
+    StopOnError,
+    /// Run every step regardless of failures; no rollback.
+    BestEffort,
+}
+
+#[derive(Deserialize)]
+pub struct BatchStep {
+    #[serde(flatten)]
+    pub request: CommandRequest,
+    /// Undoes this step if a later step fails in `stop_on_error` mode.
+    pub rollback: Option<CommandRequest>,
+}
+
+#[derive(Deserialize)]
+pub struct BatchRequest {
+    pub steps: Vec<BatchStep>,
+    #[serde(default)]
+    pub mode: BatchMode,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum StepStatus {
+    Succeeded,
+    Failed,
+    Skipped,
+    RolledBack,
+}
+
+#[derive(Serialize)]
+pub struct StepResult {
+    pub index: usize,
+    pub command: String,
+    pub status: StepStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verification: Option<Verification>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub violation: Option<SafetyViolation>,
+}
+
+impl StepResult {
+    fn new(index: usize, command: &str, status: StepStatus) -> Self {
+        StepResult {
+            index,
+            command: command.to_string(),
+            status,
+            result: None,
+            verification: None,
+            error: None,
+            violation: None,
+        }
+    }
+
+    fn succeeded(index: usize, command: &str, (result, verification): StepOutput) -> Self {
+        StepResult { result, verification, ..StepResult::new(index, command, StepStatus::Succeeded) }
+    }
+
+    fn failed(index: usize, command: &str, e: BackendError) -> Self {
+        let error = Some(e.to_string());
+        let violation = match e {
+            BackendError::Unsafe(violation) => Some(violation),
+            _ => None,
+        };
+        StepResult { error, violation, ..StepResult::new(index, command, StepStatus::Failed) }
+    }
+}
+
+#[derive(Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchStatus {
+    Succeeded,
+    /// `best_effort` finished with at least one failed step.
+    Partial,
+    /// A step failed and every completed step was rolled back.
+    RolledBack,
+    /// A step failed and some completed steps could not be (or were not) undone.
+    Failed,
+}
+
+#[derive(Serialize)]
+pub struct BatchResult {
+    pub status: BatchStatus,
+    pub steps: Vec<StepResult>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub rollback: Vec<StepResult>,
+}
+
+/// The recorded row of a step and its read-back, if any.
+type StepOutput = (Option<serde_json::Value>, Option<Verification>);
+
+/// A command name with its resolved form.
+type NamedCommand = (String, ResolvedCommand);
+
+/// A batch whose steps and rollbacks all passed the registry.
+pub struct ResolvedBatch {
+    mode: BatchMode,
+    steps: Vec<(String, ResolvedCommand, Option<NamedCommand>)>,
+}
+
+/// Prefixes an error with the step it came from, keeping safety rejections intact.
+fn in_step(context: String, e: BackendError) -> BackendError {
+    match e {
+        BackendError::Unsafe(mut violation) => {
+            violation.message = format!("{}: {}", context, violation.message);
+            BackendError::Unsafe(violation)
+        }
+        e => BackendError::InvalidParams(format!("{}: {}", context, e)),
+    }
+}
+
+/// Validates every step and rollback up front so nothing runs if any is invalid.
+pub fn resolve(registry: &CommandRegistry, request: BatchRequest) -> Result<ResolvedBatch, BackendError> {
+    if request.steps.is_empty() {
+        return Err(BackendError::InvalidParams("a batch needs at least one step".to_string()));
+    }
+    if request.steps.len() > MAX_STEPS {
+        return Err(BackendError::InvalidParams(format!("a batch may have at most {} steps", MAX_STEPS)));
+    }
+    let mut steps = Vec::new();
+    for (index, step) in request.steps.into_iter().enumerate() {
+        let name = step.request.command.clone();
+        let resolved = registry.resolve(step.request).map_err(|e| in_step(format!("step {}", index), e))?;
+        let rollback = match step.rollback {
+            Some(rollback) => {
+                let rollback_name = rollback.command.clone();
+                let resolved = registry.resolve(rollback).map_err(|e| in_step(format!("step {} rollback", index), e))?;
+                Some((rollback_name, resolved))
+            }
+            None => None,
+        };
+        steps.push((name, resolved, rollback));
+    }
+    Ok(ResolvedBatch { mode: request.mode, steps })
+}
+
+// ========== Batch Execution ==========
+/// Runs the steps in order while holding the backend, so no other command
+/// interleaves with the batch. Blocking; call from a blocking context.
+pub fn run(state: &AppState, batch: ResolvedBatch) -> BatchResult {
+    let mut backend = state.backend.lock().unwrap();
+    let headers = state.csv_data.lock().unwrap().headers.clone();
+    let mut run_one = |resolved: &ResolvedCommand| -> Result<StepOutput, BackendError> {
+        let execution = resolved.run(backend.as_mut(), state)?;
+        let result = execution.row.map(|row| {
+            let result = row_object(&headers, &row);
+            state.csv_data.lock().unwrap().push_row(row);
+            result
+        });
+        Ok((result, execution.verification))
+    };
+
+    let mut steps = Vec::new();
+    let mut failed = false;
+    for (index, (name, resolved, _)) in batch.steps.iter().enumerate() {
+        if failed && batch.mode == BatchMode::StopOnError {
+            steps.push(StepResult::new(index, name, StepStatus::Skipped));
+            continue;
+        }
+        let step = match run_one(resolved) {
+            Ok(output) => StepResult::succeeded(index, name, output),
+            Err(e) => {
+                failed = true;
+                StepResult::failed(index, name, e)
+            }
+        };
+        steps.push(step);
+    }
+
+    let mut rollback = Vec::new();
+    let status = if !failed {
+        BatchStatus::Succeeded
+    } else if batch.mode == BatchMode::BestEffort {
+        BatchStatus::Partial
+    } else {
+        // Undo completed steps newest first
+        let mut complete = true;
+        for (index, (_, _, undo)) in batch.steps.iter().enumerate().rev() {
+            if steps[index].status != StepStatus::Succeeded {
+                continue;
+            }
+            let Some((name, resolved)) = undo else {
+                complete = false;
+                continue;
+            };
+            match run_one(resolved) {
+                Ok(output) => {
+                    steps[index].status = StepStatus::RolledBack;
+                    rollback.push(StepResult::succeeded(index, name, output));
+                }
+                Err(e) => {
+                    complete = false;
+                    log::error!("rollback of batch step {} failed: {}", index, e);
+                    rollback.push(StepResult::failed(index, name, e));
+                }
+            }
+        }
+        if complete { BatchStatus::RolledBack } else { BatchStatus::Failed }
+    };
+    BatchResult { status, steps, rollback }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+columns: [{name: temperature}, {name: status}]
+commands:
+  - {name: set_temp, params: [temperature], action: {command: write, params: {temperature: $temperature}}}
+  - {name: set_status, params: [status], action: {command: write, params: {status: $status}}}
+  - {name: jam, action: {command: jam}}
+"#;
+
+    fn batch(json: serde_json::Value) -> BatchRequest {
+        serde_json::from_value(json).unwrap()
+    }
+
+    fn temperature(state: &AppState) -> String {
+        state.csv_data.lock().unwrap().latest_value("temperature").unwrap().to_string()
+    }
+
+    #[test]
+    fn failure_rolls_back_completed_steps_newest_first() {
+        let state = AppState::for_test(CONFIG);
+        let request = batch(serde_json::json!({"steps": [
+            {"command": "set_temp", "params": {"temperature": 30}, "rollback": {"command": "set_temp", "params": {"temperature": 25}}},
+            {"command": "set_status", "params": {"status": "busy"}, "rollback": {"command": "set_status", "params": {"status": "ok"}}},
+            {"command": "jam"},
+            {"command": "set_temp", "params": {"temperature": 40}}
+        ]}));
+        let result = run(&state, resolve(&state.settings().commands, request).unwrap());
+
+        assert!(result.status == BatchStatus::RolledBack);
+        let statuses: Vec<StepStatus> = result.steps.iter().map(|s| s.status).collect();
+        assert!(statuses == [StepStatus::RolledBack, StepStatus::RolledBack, StepStatus::Failed, StepStatus::Skipped]);
+        assert_eq!(result.rollback.iter().map(|s| s.index).collect::<Vec<_>>(), [1, 0]);
+        assert_eq!(temperature(&state), "25.00");
+    }
+
+    #[test]
+    fn a_step_without_rollback_leaves_the_batch_failed() {
+        let state = AppState::for_test(CONFIG);
+        let request = batch(serde_json::json!({"steps": [
+            {"command": "set_temp", "params": {"temperature": 30}},
+            {"command": "jam"}
+        ]}));
+        let result = run(&state, resolve(&state.settings().commands, request).unwrap());
+        assert!(result.status == BatchStatus::Failed);
+        assert_eq!(temperature(&state), "30.00");
+    }
+
+    #[test]
+    fn best_effort_runs_every_step() {
+        let state = AppState::for_test(CONFIG);
+        let request = batch(serde_json::json!({"mode": "best_effort", "steps": [
+            {"command": "jam"},
+            {"command": "set_temp", "params": {"temperature": 35}}
+        ]}));
+        let result = run(&state, resolve(&state.settings().commands, request).unwrap());
+        assert!(result.status == BatchStatus::Partial);
+        assert!(result.steps[1].status == StepStatus::Succeeded && result.rollback.is_empty());
+        assert_eq!(temperature(&state), "35.00");
+    }
+
+    #[test]
+    fn invalid_steps_are_refused_before_anything_runs() {
+        let state = AppState::for_test(CONFIG);
+        let request = batch(serde_json::json!({"steps": [
+            {"command": "set_temp", "params": {"temperature": 30}},
+            {"command": "set_temp", "params": {}, "rollback": {"command": "nope"}}
+        ]}));
+        let Err(BackendError::InvalidParams(message)) = resolve(&state.settings().commands, request) else {
+            panic!("expected the batch to be refused");
+        };
+        assert!(message.starts_with("step 1"));
+        assert!(state.csv_data.lock().unwrap().rows.is_empty());
+        assert!(resolve(&state.settings().commands, batch(serde_json::json!({"steps": []}))).is_err());
+    }
+}
@@ -0,0 +1,594 @@
+use serde::Deserialize;
+use std::io::ErrorKind;
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::backend::{decode_payload, BackendError, DeviceBackend, PayloadFormat, Row};
+use crate::CommandRequest;
+
+const VERSION: u8 = 1;
+const TYPE_CON: u8 = 0;
+const TYPE_ACK: u8 = 2;
+const TYPE_RST: u8 = 3;
+
+const CODE_EMPTY: u8 = 0x00;
+const CODE_GET: u8 = 0x01;
+const CODE_POST: u8 = 0x02;
+const CODE_PUT: u8 = 0x03;
+
+const OPTION_OBSERVE: u16 = 6;
+const OPTION_URI_PATH: u16 = 11;
+const OPTION_CONTENT_FORMAT: u16 = 12;
+const OPTION_URI_QUERY: u16 = 15;
+
+const CONTENT_FORMAT_TEXT: u16 = 0;
+const CONTENT_FORMAT_JSON: u16 = 50;
+const CONTENT_FORMAT_CBOR: u16 = 60;
+
+// How long a poll waits for pending observe notifications
+const NOTIFICATION_SLICE: Duration = Duration::from_millis(50);
+
+// ========== CoAP Config ==========
+fn default_timeout_ms() -> u64 {
+    2000
+}
+
+fn default_max_retransmit() -> u32 {
+    4
+}
+
+fn default_observe_timeout_secs() -> u64 {
+    300
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum CoapMethod {
+    #[default]
+    Put,
+    Post,
+}
+
+/// Maps a `/cmd` command onto a PUT/POST of its params to `path`.
+#[derive(Deserialize, Clone)]
+pub struct CoapCommand {
+    pub name: String,
+    pub path: String,
+    #[serde(default)]
+    pub method: CoapMethod,
+    /// Body encoding: `json` (default) or `cbor` of the params object.
+    #[serde(default)]
+    pub payload_format: PayloadFormat,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct CoapConfig {
+    /// Device `host:port`, usually port 5683.
+    pub address: String,
+    /// Resource path, optionally with a `?query`.
+    pub path: String,
+    #[serde(default)]
+    pub observe: bool,
+    #[serde(default)]
+    pub payload_format: PayloadFormat,
+    /// Initial retransmission timeout for confirmable requests (ACK_TIMEOUT).
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+    #[serde(default = "default_max_retransmit")]
+    pub max_retransmit: u32,
+    /// Re-register the observation when no notification arrives for this long.
+    #[serde(default = "default_observe_timeout_secs")]
+    pub observe_timeout_secs: u64,
+    #[serde(default)]
+    pub commands: Vec<CoapCommand>,
+}
+
+// ========== Message Codec ==========
+struct Message {
+    kind: u8,
+    code: u8,
+    message_id: u16,
+    token: Vec<u8>,
+    options: Vec<(u16, Vec<u8>)>,
+    payload: Vec<u8>,
+}
+
+fn option_nibble(value: usize, ext: &mut Vec<u8>) -> u8 {
+    match value {
+        0..=12 => value as u8,
+        13..=268 => {
+            ext.push((value - 13) as u8);
+            13
+        }
+        _ => {
+            ext.extend_from_slice(&((value - 269) as u16).to_be_bytes());
+            14
+        }
+    }
+}
+
+/// Minimal big-endian encoding of a uint option value.
+fn uint_option(value: u32) -> Vec<u8> {
+    value.to_be_bytes().iter().copied().skip_while(|&b| b == 0).collect()
+}
+
+fn parse_uint(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0u32, |acc, &b| (acc << 8) | b as u32)
+}
+
+impl Message {
+    fn encode(&self) -> Vec<u8> {
+        let mut out = vec![(VERSION << 6) | (self.kind << 4) | self.token.len() as u8, self.code];
+        out.extend_from_slice(&self.message_id.to_be_bytes());
+        out.extend_from_slice(&self.token);
+
+        let mut options = self.options.clone();
+        options.sort_by_key(|(number, _)| *number);
+        let mut last = 0u16;
+        for (number, value) in &options {
+            let mut ext = Vec::new();
+            let delta = option_nibble((number - last) as usize, &mut ext);
+            let length = option_nibble(value.len(), &mut ext);
+            out.push((delta << 4) | length);
+            out.extend_from_slice(&ext);
+            out.extend_from_slice(value);
+            last = *number;
+        }
+        if !self.payload.is_empty() {
+            out.push(0xFF);
+            out.extend_from_slice(&self.payload);
+        }
+        out
+    }
+
+    fn decode(buf: &[u8]) -> Result<Self, String> {
+        if buf.len() < 4 || buf[0] >> 6 != VERSION {
+            return Err("not a CoAP message".to_string());
+        }
+        let token_len = (buf[0] & 0x0F) as usize;
+        if token_len > 8 || buf.len() < 4 + token_len {
+            return Err("invalid CoAP token length".to_string());
+        }
+        let mut message = Message {
+            kind: (buf[0] >> 4) & 0x03,
+            code: buf[1],
+            message_id: u16::from_be_bytes([buf[2], buf[3]]),
+            token: buf[4..4 + token_len].to_vec(),
+            options: Vec::new(),
+            payload: Vec::new(),
+        };
+
+        let mut pos = 4 + token_len;
+        let mut number = 0u16;
+        let extended = |nibble: u8, pos: &mut usize| -> Result<usize, String> {
+            match nibble {
+                0..=12 => Ok(nibble as usize),
+                13 => {
+                    let v = *buf.get(*pos).ok_or("truncated CoAP option")? as usize + 13;
+                    *pos += 1;
+                    Ok(v)
+                }
+                14 => {
+                    let b = buf.get(*pos..*pos + 2).ok_or("truncated CoAP option")?;
+                    *pos += 2;
+                    Ok(u16::from_be_bytes([b[0], b[1]]) as usize + 269)
+                }
+                _ => Err("reserved CoAP option nibble".to_string()),
+            }
+        };
+        while pos < buf.len() {
+            let byte = buf[pos];
+            pos += 1;
+            if byte == 0xFF {
+                message.payload = buf[pos..].to_vec();
+                break;
+            }
+            let delta = extended(byte >> 4, &mut pos)?;
+            let length = extended(byte & 0x0F, &mut pos)?;
+            number = number.checked_add(delta as u16).ok_or("CoAP option number overflow")?;
+            let value = buf.get(pos..pos + length).ok_or("truncated CoAP option value")?;
+            message.options.push((number, value.to_vec()));
+            pos += length;
+        }
+        Ok(message)
+    }
+
+    fn option(&self, number: u16) -> Option<&[u8]> {
+        self.options.iter().find(|(n, _)| *n == number).map(|(_, v)| v.as_slice())
+    }
+
+    fn is_success(&self) -> bool {
+        self.code >> 5 == 2
+    }
+
+    fn code_string(&self) -> String {
+        format!("{}.{:02}", self.code >> 5, self.code & 0x1F)
+    }
+}
+
+fn path_options(path: &str) -> Vec<(u16, Vec<u8>)> {
+    let (path, query) = path.split_once('?').unwrap_or((path, ""));
+    let mut options: Vec<(u16, Vec<u8>)> = path
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .map(|s| (OPTION_URI_PATH, s.as_bytes().to_vec()))
+        .collect();
+    options.extend(query.split('&').filter(|s| !s.is_empty()).map(|s| (OPTION_URI_QUERY, s.as_bytes().to_vec())));
+    options
+}
+
+// ========== CoAP Backend ==========
+pub struct CoapBackend {
+    config: CoapConfig,
+    headers: Vec<String>,
+    socket: Option<UdpSocket>,
+    message_id: u16,
+    token_counter: u32,
+    observe_token: Option<Vec<u8>>,
+    last_notification: Instant,
+    pending: Vec<Row>,
+}
+
+impl CoapBackend {
+    pub fn new(config: CoapConfig, headers: &[String]) -> Result<Self, String> {
+        let seed = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().subsec_nanos();
+        Ok(CoapBackend {
+            config,
+            headers: headers.to_vec(),
+            socket: None,
+            message_id: seed as u16,
+            token_counter: seed,
+            observe_token: None,
+            last_notification: Instant::now(),
+            pending: Vec::new(),
+        })
+    }
+
+    fn next_token(&mut self) -> Vec<u8> {
+        self.token_counter = self.token_counter.wrapping_add(1);
+        self.token_counter.to_be_bytes().to_vec()
+    }
+
+    fn send(&self, message: &Message) -> Result<(), BackendError> {
+        self.socket.as_ref().ok_or(BackendError::NotConnected)?.send(&message.encode())?;
+        Ok(())
+    }
+
+    fn acknowledge(&self, message: &Message, kind: u8) -> Result<(), BackendError> {
+        self.send(&Message {
+            kind,
+            code: CODE_EMPTY,
+            message_id: message.message_id,
+            token: Vec::new(),
+            options: Vec::new(),
+            payload: Vec::new(),
+        })
+    }
+
+    fn receive(&self, timeout: Duration) -> Result<Option<Message>, BackendError> {
+        let socket = self.socket.as_ref().ok_or(BackendError::NotConnected)?;
+        socket.set_read_timeout(Some(timeout.max(Duration::from_millis(1))))?;
+        let mut buf = [0u8; 2048];
+        match socket.recv(&mut buf) {
+            Ok(n) => match Message::decode(&buf[..n]) {
+                Ok(message) => Ok(Some(message)),
+                Err(e) => {
+                    log::warn!("ignoring datagram: {}", e);
+                    Ok(None)
+                }
+            },
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Turns an observe notification into rows; other unsolicited messages are rejected.
+    fn handle_unsolicited(&mut self, message: Message) -> Result<(), BackendError> {
+        if message.kind == TYPE_ACK || message.kind == TYPE_RST {
+            return Ok(());
+        }
+        if Some(&message.token) != self.observe_token.as_ref() {
+            if message.kind == TYPE_CON {
+                self.acknowledge(&message, TYPE_RST)?;
+            }
+            return Ok(());
+        }
+        if message.kind == TYPE_CON {
+            self.acknowledge(&message, TYPE_ACK)?;
+        }
+        self.last_notification = Instant::now();
+        if message.is_success() {
+            self.decode_into_pending(&message);
+        } else {
+            // The server ended the observation; re-register on the next poll
+            log::warn!("observation cancelled by server with {}", message.code_string());
+            self.observe_token = None;
+        }
+        Ok(())
+    }
+
+    fn decode_into_pending(&mut self, message: &Message) {
+        let format = match message.option(OPTION_CONTENT_FORMAT).map(parse_uint) {
+            Some(f) if f == CONTENT_FORMAT_JSON as u32 => PayloadFormat::Json,
+            Some(f) if f == CONTENT_FORMAT_CBOR as u32 => PayloadFormat::Cbor,
+            _ => self.config.payload_format,
+        };
+        match decode_payload(&self.headers, &message.payload, format) {
+            Ok(rows) => self.pending.extend(rows),
+            Err(e) => log::warn!("dropping malformed coap payload: {}", e),
+        }
+    }
+
+    /// Sends a confirmable request with retransmission and returns the (possibly separate) response.
+    fn exchange(&mut self, code: u8, mut options: Vec<(u16, Vec<u8>)>, path: &str, payload: Vec<u8>) -> Result<Message, BackendError> {
+        self.message_id = self.message_id.wrapping_add(1);
+        let token = self.next_token();
+        options.extend(path_options(path));
+        let request = Message {
+            kind: TYPE_CON,
+            code,
+            message_id: self.message_id,
+            token: token.clone(),
+            options,
+            payload,
+        };
+
+        let mut timeout = Duration::from_millis(self.config.timeout_ms);
+        let mut acked = false;
+        for _ in 0..=self.config.max_retransmit {
+            if !acked {
+                self.send(&request)?;
+            }
+            let deadline = Instant::now() + timeout;
+            while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+                let Some(message) = self.receive(left)? else { continue };
+                let matches_request = message.message_id == request.message_id;
+                if message.kind == TYPE_RST && matches_request {
+                    return Err(BackendError::Protocol("CoAP request reset by server".to_string()));
+                }
+                if message.kind == TYPE_ACK && matches_request && message.code == CODE_EMPTY {
+                    // Empty ACK: the response will follow as a separate message
+                    acked = true;
+                    continue;
+                }
+                if message.token == token && message.code != CODE_EMPTY {
+                    if message.kind == TYPE_CON {
+                        self.acknowledge(&message, TYPE_ACK)?;
+                    }
+                    return Ok(message);
+                }
+                self.handle_unsolicited(message)?;
+            }
+            timeout *= 2;
+        }
+        Err(BackendError::Io(std::io::Error::new(ErrorKind::TimedOut, "CoAP request timed out")))
+    }
+
+    fn register_observation(&mut self) -> Result<(), BackendError> {
+        let path = self.config.path.clone();
+        let response = self.exchange(CODE_GET, vec![(OPTION_OBSERVE, uint_option(0))], &path, Vec::new())?;
+        if !response.is_success() {
+            return Err(BackendError::Protocol(format!("CoAP observe failed with {}", response.code_string())));
+        }
+        if response.option(OPTION_OBSERVE).is_none() {
+            log::warn!("{} is not observable; falling back to polling", path);
+            self.config.observe = false;
+        } else {
+            self.observe_token = Some(response.token.clone());
+        }
+        self.last_notification = Instant::now();
+        self.decode_into_pending(&response);
+        Ok(())
+    }
+}
+
+impl DeviceBackend for CoapBackend {
+    fn connect(&mut self) -> Result<(), BackendError> {
+        let peer = self
+            .config
+            .address
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| BackendError::Protocol(format!("cannot resolve {}", self.config.address)))?;
+        let local = if peer.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(peer)?;
+        self.socket = Some(socket);
+        if self.config.observe {
+            self.register_observation()?;
+        }
+        Ok(())
+    }
+
+    fn poll(&mut self) -> Result<Vec<Row>, BackendError> {
+        if self.config.observe {
+            let expired = self.last_notification.elapsed() > Duration::from_secs(self.config.observe_timeout_secs);
+            if self.observe_token.is_none() || expired {
+                self.register_observation()?;
+            }
+            while let Some(message) = self.receive(NOTIFICATION_SLICE)? {
+                self.handle_unsolicited(message)?;
+            }
+        } else {
+            let path = self.config.path.clone();
+            let response = self.exchange(CODE_GET, Vec::new(), &path, Vec::new())?;
+            if !response.is_success() {
+                return Err(BackendError::Protocol(format!("CoAP GET failed with {}", response.code_string())));
+            }
+            self.decode_into_pending(&response);
+        }
+        Ok(std::mem::take(&mut self.pending))
+    }
+
+    fn execute(&mut self, command: &CommandRequest) -> Result<Option<Row>, BackendError> {
+        let mapping = self
+            .config
+            .commands
+            .iter()
+            .find(|c| c.name == command.command)
+            .cloned()
+            .ok_or_else(|| BackendError::UnknownCommand(command.command.clone()))?;
+        let params = command.params.clone().unwrap_or(serde_json::Value::Null);
+        let (format, payload) = match mapping.payload_format {
+            PayloadFormat::Cbor => {
+                let mut body = Vec::new();
+                ciborium::into_writer(&params, &mut body).map_err(|e| BackendError::InvalidParams(e.to_string()))?;
+                (CONTENT_FORMAT_CBOR, body)
+            }
+            PayloadFormat::Csv => {
+                let text = match &params {
+                    serde_json::Value::String(s) => s.clone(),
+                    other => other.to_string(),
+                };
+                (CONTENT_FORMAT_TEXT, text.into_bytes())
+            }
+            PayloadFormat::Auto | PayloadFormat::Json => (CONTENT_FORMAT_JSON, params.to_string().into_bytes()),
+        };
+        let code = match mapping.method {
+            CoapMethod::Put => CODE_PUT,
+            CoapMethod::Post => CODE_POST,
+        };
+        let response = self.exchange(code, vec![(OPTION_CONTENT_FORMAT, uint_option(format as u32))], &mapping.path, payload)?;
+        if !response.is_success() {
+            return Err(BackendError::Protocol(format!("CoAP {} failed with {}", mapping.path, response.code_string())));
+        }
+        Ok(None)
+    }
+
+    fn disconnect(&mut self) {
+        // Dropping the socket makes the server's next CON notification fail, ending the observation
+        self.socket = None;
+        self.observe_token = None;
+        self.pending.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::{self, Receiver};
+    use std::thread;
+
+    fn cbor(value: serde_json::Value) -> Vec<u8> {
+        let mut body = Vec::new();
+        ciborium::into_writer(&value, &mut body).unwrap();
+        body
+    }
+
+    fn reply(socket: &UdpSocket, peer: std::net::SocketAddr, message: Message) {
+        socket.send_to(&message.encode(), peer).unwrap();
+    }
+
+    /// An observable `/sensors/chiller` resource plus a `/cmd` resource that
+    /// drops the first PUT, then answers with an empty ACK and a separate response.
+    fn serve() -> (String, Receiver<String>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap().to_string();
+        let (events, received) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buf = [0u8; 2048];
+            let mut puts = 0;
+            loop {
+                let (n, peer) = socket.recv_from(&mut buf).unwrap();
+                let request = Message::decode(&buf[..n]).unwrap();
+                let content_format = (OPTION_CONTENT_FORMAT, uint_option(CONTENT_FORMAT_CBOR as u32));
+                match (request.kind, request.code) {
+                    (TYPE_CON, CODE_GET) => {
+                        assert_eq!(request.option(OPTION_URI_PATH), Some(&b"sensors"[..]));
+                        reply(&socket, peer, Message {
+                            kind: TYPE_ACK,
+                            code: 0x45, // 2.05 Content
+                            message_id: request.message_id,
+                            token: request.token.clone(),
+                            options: vec![(OPTION_OBSERVE, uint_option(1)), content_format.clone()],
+                            payload: cbor(serde_json::json!({"temperature": 21.5, "status": "ok"})),
+                        });
+                        reply(&socket, peer, Message {
+                            kind: TYPE_CON,
+                            code: 0x45,
+                            message_id: 0x7000,
+                            token: request.token,
+                            options: vec![(OPTION_OBSERVE, uint_option(2)), content_format],
+                            payload: cbor(serde_json::json!({"temperature": 22.0, "status": "ok"})),
+                        });
+                    }
+                    (TYPE_CON, CODE_PUT) => {
+                        puts += 1;
+                        if puts == 1 {
+                            continue;
+                        }
+                        events.send(String::from_utf8(request.payload.clone()).unwrap()).unwrap();
+                        reply(&socket, peer, Message {
+                            kind: TYPE_ACK,
+                            code: CODE_EMPTY,
+                            message_id: request.message_id,
+                            token: Vec::new(),
+                            options: Vec::new(),
+                            payload: Vec::new(),
+                        });
+                        reply(&socket, peer, Message {
+                            kind: TYPE_CON,
+                            code: 0x44, // 2.04 Changed
+                            message_id: 0x7001,
+                            token: request.token,
+                            options: Vec::new(),
+                            payload: Vec::new(),
+                        });
+                    }
+                    (TYPE_ACK, CODE_EMPTY) => events.send(format!("ack {:#06x}", request.message_id)).unwrap(),
+                    _ => {}
+                }
+            }
+        });
+        (address, received)
+    }
+
+    #[test]
+    fn observes_resource_and_retransmits_commands() {
+        let (address, events) = serve();
+        let config: CoapConfig = serde_json::from_value(serde_json::json!({
+            "address": address,
+            "path": "/sensors/chiller",
+            "observe": true,
+            "timeout_ms": 100,
+            "commands": [{"name": "set_mode", "path": "/cmd"}],
+        }))
+        .unwrap();
+        let mut backend = CoapBackend::new(config, &["temperature".to_string(), "status".to_string()]).unwrap();
+
+        backend.connect().unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut rows = Vec::new();
+        while rows.len() < 2 && Instant::now() < deadline {
+            rows.extend(backend.poll().unwrap());
+        }
+        assert_eq!(rows, vec![vec!["21.5".to_string(), "ok".to_string()], vec!["22.0".to_string(), "ok".to_string()]]);
+        assert_eq!(events.recv_timeout(Duration::from_secs(5)).unwrap(), "ack 0x7000");
+
+        let command = CommandRequest {
+            command: "set_mode".to_string(),
+            params: Some(serde_json::json!({"mode": "eco"})),
+            request_id: None,
+        };
+        backend.execute(&command).unwrap();
+        assert_eq!(events.recv_timeout(Duration::from_secs(5)).unwrap(), r#"{"mode":"eco"}"#);
+        assert_eq!(events.recv_timeout(Duration::from_secs(5)).unwrap(), "ack 0x7001");
+    }
+
+    #[test]
+    fn options_round_trip_through_the_codec() {
+        let message = Message {
+            kind: TYPE_CON,
+            code: CODE_GET,
+            message_id: 0x1234,
+            token: vec![1, 2, 3, 4],
+            options: path_options("/a/very-long-resource-name-that-needs-an-extended-length?x=1"),
+            payload: b"hi".to_vec(),
+        };
+        let decoded = Message::decode(&message.encode()).unwrap();
+        assert_eq!(decoded.message_id, 0x1234);
+        assert_eq!(decoded.token, vec![1, 2, 3, 4]);
+        assert_eq!(decoded.options, message.options);
+        assert_eq!(decoded.payload, b"hi");
+    }
+}
@@ -0,0 +1,439 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::backend::{assemble_row, BackendError, DeviceBackend, Row};
+use crate::safety::{Interlock, SafetyCheck, SafetyRules, SetpointLimit};
+use crate::shifu::RESERVED_ROUTES;
+use crate::verify::{self, ReadBack, Verification, VerifyRule};
+use crate::{AppState, CommandRequest};
+
+// ========== Command Definitions ==========
+/// One parameter of a command; a bare string in the config is a required
+/// parameter with no constraints.
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(from = "ParamSpec")]
+pub struct ParamDefinition {
+    pub name: String,
+    pub required: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unit: Option<String>,
+    /// Accepted values, for enumerations such as modes.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub allowed: Vec<serde_json::Value>,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub description: String,
+}
+
+fn default_required() -> bool {
+    true
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ParamSpec {
+    Name(String),
+    Detailed {
+        name: String,
+        #[serde(default = "default_required")]
+        required: bool,
+        min: Option<f64>,
+        max: Option<f64>,
+        unit: Option<String>,
+        #[serde(default)]
+        allowed: Vec<serde_json::Value>,
+        #[serde(default)]
+        description: String,
+    },
+}
+
+impl From<ParamSpec> for ParamDefinition {
+    fn from(spec: ParamSpec) -> Self {
+        match spec {
+            ParamSpec::Name(name) => ParamDefinition {
+                name,
+                required: true,
+                min: None,
+                max: None,
+                unit: None,
+                allowed: Vec::new(),
+                description: String::new(),
+            },
+            ParamSpec::Detailed { name, required, min, max, unit, allowed, description } => {
+                ParamDefinition { name, required, min, max, unit, allowed, description }
+            }
+        }
+    }
+}
+
+/// The backend command a registry entry maps to. String values of the form
+/// `$name` are replaced with the request parameter `name`.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct CommandAction {
+    pub command: String,
+    #[serde(default, skip_serializing_if = "serde_json::Map::is_empty")]
+    pub params: serde_json::Map<String, serde_json::Value>,
+}
+
+/// A command accepted by `/cmd`; requests for undeclared commands are rejected
+/// before reaching the backend once any command is declared.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct CommandDefinition {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub description: String,
+    #[serde(default)]
+    pub params: Vec<ParamDefinition>,
+    /// Fixed parameters sent with every call; request params take precedence.
+    #[serde(default, skip_serializing_if = "serde_json::Map::is_empty")]
+    pub defaults: serde_json::Map<String, serde_json::Value>,
+    /// JSON Schema the merged `params` object must satisfy.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<serde_json::Value>,
+    /// Backend command to run; the request is passed through unchanged when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub action: Option<CommandAction>,
+    /// Row recorded after success, as column → literal or `$param`; the backend's
+    /// own row (if any) is recorded when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub telemetry: Option<HashMap<String, serde_json::Value>>,
+    /// Overrides `jobs.default_timeout_ms` for this command.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+    /// Limits and interlocks enforced before the command reaches the device.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub safety: Option<SafetyRules>,
+    /// Read-back confirming the write took effect on the device.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verify: Option<VerifyRule>,
+}
+
+impl CommandDefinition {
+    pub fn named(name: String) -> Self {
+        CommandDefinition {
+            name,
+            description: String::new(),
+            params: Vec::new(),
+            defaults: serde_json::Map::new(),
+            schema: None,
+            action: None,
+            telemetry: None,
+            timeout_ms: None,
+            safety: None,
+            verify: None,
+        }
+    }
+}
+
+/// Replaces `$name` strings with the named request parameter.
+fn substitute(value: &serde_json::Value, params: &serde_json::Map<String, serde_json::Value>) -> serde_json::Value {
+    match value.as_str().and_then(|s| s.strip_prefix('$')) {
+        Some(name) => params.get(name).cloned().unwrap_or(serde_json::Value::Null),
+        None => value.clone(),
+    }
+}
+
+// ========== Command Registry ==========
+/// Whether a command can be served as `/{name}` without shadowing a fixed route.
+pub fn is_routable(name: &str) -> bool {
+    !(name.is_empty() || name.contains('/') || RESERVED_ROUTES.contains(&name))
+}
+
+/// A command ready for the backend, with what is needed to record its telemetry.
+pub struct ResolvedCommand {
+    pub request: CommandRequest,
+    pub telemetry: Option<HashMap<String, String>>,
+    pub timeout: Option<Duration>,
+    pub safety: Option<SafetyCheck>,
+    pub verify: Option<ReadBack>,
+}
+
+/// What running a command produced.
+pub struct Execution {
+    /// Row to record: the declared telemetry if any, else the backend's own.
+    pub row: Option<Row>,
+    pub verification: Option<Verification>,
+}
+
+impl ResolvedCommand {
+    /// Checks the safety rules against the latest readings, executes the
+    /// command, then reads the result back if the command asks for it.
+    pub fn run(&self, backend: &mut dyn DeviceBackend, state: &AppState) -> Result<Execution, BackendError> {
+        let headers = {
+            let csv_data = state.csv_data.lock().unwrap();
+            if let Some(check) = &self.safety {
+                state.safety.check(check, &csv_data)?;
+            }
+            csv_data.headers.clone()
+        };
+        let row = backend.execute(&self.request)?;
+        if let Some(check) = &self.safety {
+            state.safety.record(check);
+        }
+        let verification = self.verify.as_ref().map(|check| verify::read_back(check, backend, state));
+        let row = match &self.telemetry {
+            Some(values) => Some(assemble_row(&headers, values)),
+            None => row,
+        };
+        Ok(Execution { row, verification })
+    }
+}
+
+pub struct CommandRegistry {
+    definitions: Vec<CommandDefinition>,
+    validators: HashMap<String, jsonschema::Validator>,
+}
+
+impl CommandRegistry {
+    pub fn new(definitions: Vec<CommandDefinition>) -> Result<Self, String> {
+        let mut validators = HashMap::new();
+        for definition in &definitions {
+            if !is_routable(&definition.name) {
+                return Err(format!("command '{}' cannot be served as a route", definition.name));
+            }
+            if let Some(schema) = &definition.schema {
+                let validator = jsonschema::validator_for(schema)
+                    .map_err(|e| format!("command '{}' has an invalid schema: {}", definition.name, e))?;
+                validators.insert(definition.name.clone(), validator);
+            }
+            for param in &definition.params {
+                if let (Some(min), Some(max)) = (param.min, param.max) {
+                    if min > max {
+                        return Err(format!("command '{}' param '{}' has min above max", definition.name, param.name));
+                    }
+                }
+            }
+            if let Some(safety) = &definition.safety {
+                safety.validate(&definition.name)?;
+            }
+            if let Some(verify) = &definition.verify {
+                verify.validate(&definition.name)?;
+            }
+        }
+        Ok(CommandRegistry { definitions, validators })
+    }
+
+    pub fn definitions(&self) -> &[CommandDefinition] {
+        &self.definitions
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.definitions.iter().any(|c| c.name == name)
+    }
+
+    /// Checks a request against its definition and maps it to the backend
+    /// command; anything is passed through when no commands are declared.
+    pub fn resolve(&self, mut request: CommandRequest) -> Result<ResolvedCommand, BackendError> {
+        if self.definitions.is_empty() {
+            return Ok(ResolvedCommand { request, telemetry: None, timeout: None, safety: None, verify: None });
+        }
+        let definition = self
+            .definitions
+            .iter()
+            .find(|c| c.name == request.command)
+            .ok_or_else(|| BackendError::UnknownCommand(request.command.clone()))?;
+
+        let mut params = definition.defaults.clone();
+        match request.params.take() {
+            Some(serde_json::Value::Object(given)) => params.extend(given),
+            Some(serde_json::Value::Null) | None => {}
+            Some(_) => return Err(BackendError::InvalidParams("params must be a JSON object".to_string())),
+        }
+        for param in &definition.params {
+            let Some(value) = params.get(&param.name) else {
+                if param.required {
+                    return Err(BackendError::InvalidParams(format!("Missing {} parameter", param.name)));
+                }
+                continue;
+            };
+            check_param(param, value)?;
+        }
+        let params = serde_json::Value::Object(params);
+        if let Some(validator) = self.validators.get(&definition.name) {
+            if let Err(e) = validator.validate(&params) {
+                let path = e.instance_path.to_string();
+                let at = if path.is_empty() { String::new() } else { format!(" at {}", path) };
+                return Err(BackendError::InvalidParams(format!("Invalid params{}: {}", at, e)));
+            }
+        }
+        let serde_json::Value::Object(params) = params else { unreachable!() };
+        let safety = match &definition.safety {
+            Some(rules) => Some(rules.check_request(&definition.name, &params)?),
+            None => None,
+        };
+
+        let telemetry = definition.telemetry.as_ref().map(|columns| {
+            columns
+                .iter()
+                .map(|(column, template)| {
+                    let cell = match substitute(template, &params) {
+                        serde_json::Value::String(s) => s,
+                        serde_json::Value::Null => String::new(),
+                        other => other.to_string(),
+                    };
+                    (column.clone(), cell)
+                })
+                .collect()
+        });
+        let verify = definition.verify.as_ref().map(|rule| rule.expect(substitute(&rule.value, &params)));
+        let request = match &definition.action {
+            Some(action) => CommandRequest {
+                command: action.command.clone(),
+                params: Some(serde_json::Value::Object(
+                    action.params.iter().map(|(k, v)| (k.clone(), substitute(v, &params))).collect(),
+                )),
+                request_id: None,
+            },
+            None => CommandRequest {
+                command: request.command,
+                params: Some(serde_json::Value::Object(params)),
+                request_id: None,
+            },
+        };
+        let timeout = definition.timeout_ms.map(Duration::from_millis);
+        Ok(ResolvedCommand { request, telemetry, timeout, safety, verify })
+    }
+}
+
+fn check_param(param: &ParamDefinition, value: &serde_json::Value) -> Result<(), BackendError> {
+    if !param.allowed.is_empty() && !param.allowed.contains(value) {
+        return Err(BackendError::InvalidParams(format!(
+            "{} must be one of {}",
+            param.name,
+            serde_json::Value::Array(param.allowed.clone())
+        )));
+    }
+    if param.min.is_none() && param.max.is_none() {
+        return Ok(());
+    }
+    let unit = param.unit.as_deref().map(|u| format!(" {}", u)).unwrap_or_default();
+    let number = value
+        .as_f64()
+        .ok_or_else(|| BackendError::InvalidParams(format!("{} must be a number", param.name)))?;
+    if let Some(min) = param.min.filter(|&min| number < min) {
+        return Err(BackendError::InvalidParams(format!("{} must be at least {}{}", param.name, min, unit)));
+    }
+    if let Some(max) = param.max.filter(|&max| number > max) {
+        return Err(BackendError::InvalidParams(format!("{} must be at most {}{}", param.name, max, unit)));
+    }
+    Ok(())
+}
+
+/// Registry used when the simulated backend runs without declared commands.
+pub fn simulated_commands() -> Vec<CommandDefinition> {
+    let set_temp = CommandDefinition {
+        description: "Set the simulated temperature".to_string(),
+        params: vec![ParamDefinition {
+            unit: Some("°C".to_string()),
+            ..ParamSpec::Name("temperature".to_string()).into()
+        }],
+        schema: Some(serde_json::json!({
+            "type": "object",
+            "properties": {"temperature": {"type": "number"}},
+            "required": ["temperature"]
+        })),
+        action: Some(CommandAction {
+            command: "write".to_string(),
+            params: serde_json::Map::from_iter([("temperature".to_string(), serde_json::json!("$temperature"))]),
+        }),
+        safety: Some(SafetyRules {
+            setpoints: vec![SetpointLimit {
+                param: "temperature".to_string(),
+                column: None,
+                min: Some(-20.0),
+                max: Some(60.0),
+                max_step: None,
+            }],
+            min_interval_ms: None,
+            interlocks: vec![Interlock {
+                column: "status".to_string(),
+                equals: "ok".to_string(),
+            }],
+        }),
+        ..CommandDefinition::named("set_temp".to_string())
+    };
+    let reset = CommandDefinition {
+        description: "Restore the initial simulated readings".to_string(),
+        action: Some(CommandAction {
+            command: "reset".to_string(),
+            params: serde_json::Map::new(),
+        }),
+        ..CommandDefinition::named("reset".to_string())
+    };
+    vec![set_temp, reset]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry(yaml: &str) -> Result<CommandRegistry, String> {
+        CommandRegistry::new(serde_yaml::from_str(yaml).unwrap())
+    }
+
+    fn request(command: &str, params: serde_json::Value) -> CommandRequest {
+        CommandRequest { command: command.to_string(), params: Some(params), request_id: None }
+    }
+
+    fn invalid(result: Result<ResolvedCommand, BackendError>) -> String {
+        match result {
+            Err(BackendError::InvalidParams(message)) => message,
+            _ => panic!("expected invalid params"),
+        }
+    }
+
+    const SET_FLOW: &str = r#"
+- name: set_flow
+  params:
+    - {name: flow, min: 0, max: 120, unit: "l/min"}
+    - {name: mode, required: false, allowed: [auto, manual]}
+  defaults: {pump: 1}
+  schema: {type: object, properties: {pump: {type: integer, maximum: 2}}}
+  action: {command: write_register, params: {address: 40001, value: $flow, unit: $pump}}
+  telemetry: {flow_sp: $flow, source: api}
+  timeout_ms: 500
+"#;
+
+    #[test]
+    fn maps_a_request_onto_its_backend_action() {
+        let registry = registry(SET_FLOW).unwrap();
+        let resolved = registry.resolve(request("set_flow", serde_json::json!({"flow": 80}))).unwrap();
+        assert_eq!(resolved.request.command, "write_register");
+        assert_eq!(resolved.request.params.unwrap(), serde_json::json!({"address": 40001, "value": 80, "unit": 1}));
+        let telemetry = resolved.telemetry.unwrap();
+        assert_eq!((telemetry["flow_sp"].as_str(), telemetry["source"].as_str()), ("80", "api"));
+        assert_eq!(resolved.timeout, Some(Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn rejects_requests_outside_the_definition() {
+        let registry = registry(SET_FLOW).unwrap();
+        let resolve = |params| registry.resolve(request("set_flow", params));
+        assert_eq!(invalid(resolve(serde_json::json!({}))), "Missing flow parameter");
+        assert_eq!(invalid(resolve(serde_json::json!({"flow": 150}))), "flow must be at most 120 l/min");
+        assert_eq!(invalid(resolve(serde_json::json!({"flow": "fast"}))), "flow must be a number");
+        assert!(invalid(resolve(serde_json::json!({"flow": 1, "mode": "eco"}))).starts_with("mode must be one of"));
+        assert!(invalid(resolve(serde_json::json!({"flow": 1, "pump": 3}))).starts_with("Invalid params at /pump"));
+        assert!(invalid(resolve(serde_json::json!([1]))).contains("JSON object"));
+        assert!(matches!(registry.resolve(request("drain", serde_json::json!({}))), Err(BackendError::UnknownCommand(_))));
+    }
+
+    #[test]
+    fn passes_anything_through_when_nothing_is_declared() {
+        let resolved = registry("[]").unwrap().resolve(request("anything", serde_json::json!({"x": 1}))).unwrap();
+        assert_eq!(resolved.request.command, "anything");
+    }
+
+    #[test]
+    fn refuses_invalid_definitions() {
+        assert!(registry("[{name: x, params: [{name: a, min: 2, max: 1}]}]").is_err());
+        assert!(registry("[{name: x, schema: {type: 12}}]").is_err());
+        for name in ["info", "data", "stats", "", "a/b"] {
+            assert!(registry(&format!("[{{name: '{}'}}]", name)).is_err(), "'{}' was accepted", name);
+        }
+    }
+}
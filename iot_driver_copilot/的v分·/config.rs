@@ -0,0 +1,394 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::env;
+use std::path::Path;
+
+use crate::backend::BackendConfig;
+use crate::commands::{self, CommandDefinition};
+use crate::history::HistoryConfig;
+use crate::jobs::JobsConfig;
+use crate::schedules::SchedulesConfig;
+
+// ========== Device Metadata ==========
+#[derive(Deserialize, Serialize, Clone)]
+pub struct DeviceConfig {
+    #[serde(default = "default_device_name")]
+    pub device_name: String,
+    #[serde(default = "default_device_model")]
+    pub device_model: String,
+    #[serde(default = "default_manufacturer")]
+    pub manufacturer: String,
+    #[serde(default = "default_device_type")]
+    pub device_type: String,
+}
+
+fn default_device_name() -> String {
+    "的v分·".to_string()
+}
+
+fn default_device_model() -> String {
+    "个人".to_string()
+}
+
+fn default_manufacturer() -> String {
+    "拰发·".to_string()
+}
+
+fn default_device_type() -> String {
+    " 为服务".to_string()
+}
+
+impl Default for DeviceConfig {
+    fn default() -> Self {
+        DeviceConfig {
+            device_name: default_device_name(),
+            device_model: default_device_model(),
+            manufacturer: default_manufacturer(),
+            device_type: default_device_type(),
+        }
+    }
+}
+
+// ========== Columns ==========
+#[derive(Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ColumnType {
+    #[default]
+    String,
+    Integer,
+    Float,
+    Boolean,
+    Timestamp,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct ColumnConfig {
+    pub name: String,
+    #[serde(rename = "type", default)]
+    pub data_type: ColumnType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unit: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+impl ColumnConfig {
+    fn named(name: &str) -> Self {
+        let data_type = match name {
+            "timestamp" => ColumnType::Timestamp,
+            "temperature" => ColumnType::Float,
+            _ => ColumnType::String,
+        };
+        ColumnConfig {
+            name: name.to_string(),
+            data_type,
+            unit: None,
+            description: None,
+        }
+    }
+}
+
+fn default_columns() -> Vec<ColumnConfig> {
+    ["timestamp", "temperature", "status"].iter().map(|c| ColumnConfig::named(c)).collect()
+}
+
+// ========== Telemetries ==========
+/// Runs a declared command periodically and records any row it returns.
+#[derive(Deserialize, Clone)]
+pub struct TelemetryDefinition {
+    pub name: String,
+    pub instruction: String,
+    #[serde(default)]
+    pub initial_delay_ms: u64,
+    pub interval_ms: u64,
+}
+
+// ========== Server Options ==========
+#[derive(Deserialize, Clone)]
+pub struct ServerConfig {
+    #[serde(default = "default_host")]
+    pub host: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    #[serde(default = "default_poll_interval_ms")]
+    pub poll_interval_ms: u64,
+    /// Most recent rows also kept in memory for `/data` and `/stream`; the
+    /// full series is in the on-disk history.
+    #[serde(default = "default_max_rows")]
+    pub max_rows: usize,
+    /// How often the config files are checked for changes; 0 disables reloading.
+    #[serde(default = "default_reload_interval_ms")]
+    pub reload_interval_ms: u64,
+}
+
+fn default_host() -> String {
+    "0.0.0.0".to_string()
+}
+
+fn default_port() -> u16 {
+    8080
+}
+
+fn default_poll_interval_ms() -> u64 {
+    1000
+}
+
+fn default_max_rows() -> usize {
+    10
+}
+
+fn default_reload_interval_ms() -> u64 {
+    2000
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            host: default_host(),
+            port: default_port(),
+            poll_interval_ms: default_poll_interval_ms(),
+            max_rows: default_max_rows(),
+            reload_interval_ms: default_reload_interval_ms(),
+        }
+    }
+}
+
+// ========== Driver Config ==========
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DriverConfig {
+    #[serde(default)]
+    pub device: DeviceConfig,
+    #[serde(default)]
+    pub server: ServerConfig,
+    #[serde(default = "default_columns")]
+    pub columns: Vec<ColumnConfig>,
+    #[serde(default)]
+    pub backend: BackendConfig,
+    #[serde(default)]
+    pub commands: Vec<CommandDefinition>,
+    #[serde(default)]
+    pub telemetries: Vec<TelemetryDefinition>,
+    #[serde(default)]
+    pub jobs: JobsConfig,
+    #[serde(default)]
+    pub schedules: SchedulesConfig,
+    #[serde(default)]
+    pub history: HistoryConfig,
+}
+
+impl Default for DriverConfig {
+    fn default() -> Self {
+        DriverConfig {
+            device: DeviceConfig::default(),
+            server: ServerConfig::default(),
+            columns: default_columns(),
+            backend: BackendConfig::default(),
+            commands: Vec::new(),
+            telemetries: Vec::new(),
+            jobs: JobsConfig::default(),
+            schedules: SchedulesConfig::default(),
+            history: HistoryConfig::default(),
+        }
+    }
+}
+
+impl DriverConfig {
+    /// Reads a `.json` file as JSON and anything else as YAML.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let raw = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let is_json = Path::new(path).extension().is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+        let config: DriverConfig = if is_json {
+            serde_json::from_str(&raw).map_err(|e| format!("{}: {}", path, e))?
+        } else {
+            serde_yaml::from_str(&raw).map_err(|e| format!("{}: {}", path, e))?
+        };
+        config.validate().map_err(|e| format!("{}: {}", path, e))?;
+        Ok(config)
+    }
+
+    /// Legacy setup from `CSV_HEADERS`, `POLL_INTERVAL_MS` and a JSON `BACKEND_CONFIG` file.
+    pub fn from_env() -> Result<Self, String> {
+        let mut config = DriverConfig::default();
+        if let Ok(headers) = env::var("CSV_HEADERS") {
+            config.columns = headers.split(',').map(|h| ColumnConfig::named(h.trim())).collect();
+        }
+        if let Some(interval) = env::var("POLL_INTERVAL_MS").ok().and_then(|v| v.parse().ok()) {
+            config.server.poll_interval_ms = interval;
+        }
+        if let Ok(path) = env::var("BACKEND_CONFIG") {
+            let raw = std::fs::read_to_string(&path).map_err(|e| format!("{}: {}", path, e))?;
+            config.backend = serde_json::from_str(&raw).map_err(|e| format!("{}: {}", path, e))?;
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// `SERVER_HOST`/`SERVER_PORT` keep working as overrides on top of the file.
+    pub fn apply_env_overrides(&mut self) -> Result<(), String> {
+        if let Ok(host) = env::var("SERVER_HOST") {
+            self.server.host = host;
+        }
+        if let Ok(port) = env::var("SERVER_PORT") {
+            self.server.port = port.parse().map_err(|_| format!("SERVER_PORT '{}' is not a valid port", port))?;
+        }
+        Ok(())
+    }
+
+    /// Gives the simulated backend its built-in commands when none are declared.
+    /// Legacy `CSV_HEADERS` may lack the columns their safety rules read, so
+    /// those rules are left out rather than refusing every command.
+    pub fn fill_default_commands(&mut self) {
+        if self.commands.is_empty() && matches!(self.backend, BackendConfig::Simulated { .. }) {
+            let headers = self.headers();
+            self.commands = commands::simulated_commands();
+            for safety in self.commands.iter_mut().filter_map(|c| c.safety.as_mut()) {
+                safety.retain_columns(|column| headers.iter().any(|h| h == column));
+            }
+        }
+    }
+
+    pub fn headers(&self) -> Vec<String> {
+        self.columns.iter().map(|c| c.name.clone()).collect()
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.columns.is_empty() {
+            return Err("at least one column is required".to_string());
+        }
+        let mut seen = HashSet::new();
+        for column in &self.columns {
+            if column.name.is_empty() || column.name.contains(',') {
+                return Err(format!("invalid column name '{}'", column.name));
+            }
+            if !seen.insert(column.name.as_str()) {
+                return Err(format!("duplicate column '{}'", column.name));
+            }
+        }
+        let mut seen = HashSet::new();
+        for command in &self.commands {
+            if !commands::is_routable(&command.name) {
+                return Err(format!("command '{}' cannot be served as a route", command.name));
+            }
+            if !seen.insert(command.name.as_str()) {
+                return Err(format!("duplicate command '{}'", command.name));
+            }
+        }
+        let known = |column: &str| self.columns.iter().any(|c| c.name == column);
+        for command in &self.commands {
+            if let Some(column) = command.safety.iter().flat_map(|s| s.columns()).find(|c| !known(c)) {
+                return Err(format!("command '{}' safety rules refer to unknown column '{}'", command.name, column));
+            }
+            if let Some(verify) = command.verify.as_ref().filter(|v| !known(&v.column)) {
+                return Err(format!("command '{}' verifies unknown column '{}'", command.name, verify.column));
+            }
+        }
+        for telemetry in &self.telemetries {
+            if !self.commands.iter().any(|c| c.name == telemetry.instruction) {
+                return Err(format!("telemetry '{}' refers to unknown command '{}'", telemetry.name, telemetry.instruction));
+            }
+            if telemetry.interval_ms == 0 {
+                return Err(format!("telemetry '{}' interval_ms must be greater than zero", telemetry.name));
+            }
+        }
+        if self.server.poll_interval_ms == 0 {
+            return Err("server.poll_interval_ms must be greater than zero".to_string());
+        }
+        if self.jobs.default_timeout_ms == 0 || self.commands.iter().any(|c| c.timeout_ms == Some(0)) {
+            return Err("command timeouts must be greater than zero".to_string());
+        }
+        if self.jobs.history == 0 {
+            return Err("jobs.history must be greater than zero".to_string());
+        }
+        if self.server.max_rows == 0 {
+            return Err("server.max_rows must be greater than zero".to_string());
+        }
+        if self.history.segment_bytes == 0 {
+            return Err("history.segment_bytes must be greater than zero".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Config path from `--config <path>` or the `DRIVER_CONFIG` environment variable.
+pub fn config_path() -> Option<String> {
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            return args.next();
+        }
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(path.to_string());
+        }
+    }
+    env::var("DRIVER_CONFIG").ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(yaml: &str) -> Result<DriverConfig, String> {
+        let config: DriverConfig = serde_yaml::from_str(yaml).map_err(|e| e.to_string())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    #[test]
+    fn loads_yaml_or_json_by_extension() {
+        let dir = std::env::temp_dir();
+        let yaml = dir.join(format!("driver-config-test-{}.yaml", std::process::id()));
+        let json = dir.join(format!("driver-config-test-{}.json", std::process::id()));
+        std::fs::write(&yaml, "device: {device_name: chiller}\ncolumns: [{name: supply_temp, type: float, unit: °C}]\n").unwrap();
+        std::fs::write(&json, r#"{"server": {"port": 9000}, "columns": [{"name": "pressure"}]}"#).unwrap();
+
+        let config = DriverConfig::load(yaml.to_str().unwrap()).unwrap();
+        assert_eq!(config.device.device_name, "chiller");
+        assert_eq!(config.headers(), ["supply_temp"]);
+        assert!(config.columns[0].data_type == ColumnType::Float);
+        let config = DriverConfig::load(json.to_str().unwrap()).unwrap();
+        assert_eq!((config.server.port, config.server.poll_interval_ms), (9000, 1000));
+        assert_eq!(config.headers(), ["pressure"]);
+        let _ = std::fs::remove_file(yaml);
+        let _ = std::fs::remove_file(json);
+    }
+
+    #[test]
+    fn defaults_match_the_legacy_driver() {
+        let config = parse("{}").unwrap();
+        assert_eq!(config.headers(), ["timestamp", "temperature", "status"]);
+        assert_eq!((config.server.host.as_str(), config.server.port), ("0.0.0.0", 8080));
+        assert!(matches!(config.backend, BackendConfig::Simulated { .. }));
+    }
+
+    #[test]
+    fn rejects_inconsistent_configs() {
+        assert!(parse("colums: []").is_err());
+        assert!(parse("columns: []").is_err());
+        assert!(parse("columns: [{name: a}, {name: a}]").is_err());
+        assert!(parse("columns: [{name: 'a,b'}]").is_err());
+        assert!(parse("commands: [{name: reset}, {name: reset}]").is_err());
+        assert!(parse("telemetries: [{name: t, instruction: missing, interval_ms: 1000}]").is_err());
+        assert!(parse("commands: [{name: reset}]\ntelemetries: [{name: t, instruction: reset, interval_ms: 0}]").is_err());
+        assert!(parse("server: {poll_interval_ms: 0}").is_err());
+        assert!(parse("commands: [{name: stats}]").is_err());
+        assert!(parse("jobs: {history: 0}").is_err());
+        assert!(parse("jobs: {default_timeout_ms: 0}").is_err());
+        assert!(parse("commands: [{name: reset}]\ntelemetries: [{name: t, instruction: reset, interval_ms: 1000}]").is_ok());
+    }
+
+    #[test]
+    fn built_in_commands_only_guard_columns_that_exist() {
+        let mut legacy = parse("columns: [{name: timestamp}, {name: temperature}]").unwrap();
+        legacy.fill_default_commands();
+        let safety = legacy.commands.iter().find(|c| c.name == "set_temp").unwrap().safety.as_ref().unwrap();
+        assert!(safety.interlocks.is_empty());
+        assert_eq!(safety.setpoints[0].max, Some(60.0));
+        legacy.validate().unwrap();
+
+        let mut default = parse("{}").unwrap();
+        default.fill_default_commands();
+        let safety = default.commands.iter().find(|c| c.name == "set_temp").unwrap().safety.as_ref().unwrap();
+        assert_eq!(safety.interlocks.len(), 1);
+    }
+}
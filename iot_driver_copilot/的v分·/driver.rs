@@ -1,26 +1,63 @@
-use actix_web::{web, App, HttpResponse, HttpServer, Responder, Result};
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer, Responder, Result};
 use actix_web::http::header;
 use serde::{Deserialize, Serialize};
-use std::env;
-use std::sync::Mutex;
+use std::io;
+use std::sync::{Arc, Mutex, RwLock};
 use std::collections::VecDeque;
-use std::time::{Duration, SystemTime};
+use std::time::Duration;
 use actix_web::middleware::Logger;
+use std::collections::HashMap;
 
-// ========== Static Device Info ==========
+mod aggregate;
+mod backend;
+mod bacnet;
+mod batch;
+mod coap;
+mod commands;
+mod config;
+mod history;
+mod jobs;
+mod modbus;
+mod mqtt;
+mod opcua;
+mod raw;
+mod schedules;
+mod reload;
+mod safety;
+mod shifu;
+mod snmp;
+mod stream;
+mod verify;
+mod ws;
+
+use backend::{BackendError, DeviceBackend};
+use commands::CommandRegistry;
+use config::{ColumnConfig, ColumnType, DriverConfig, TelemetryDefinition};
+use jobs::{JobStatus, JobStore, Submission};
+use safety::{SafetyGuard, SafetyViolation};
+use history::HistoryStore;
+use schedules::{ScheduleRequest, ScheduleStore};
+use stream::{Broadcaster, CsvStream, EventStream, LiveRow};
+
+// ========== Device Info ==========
 #[derive(Serialize)]
 struct DeviceInfo {
-    device_name: &'static str,
-    device_model: &'static str,
-    manufacturer: &'static str,
-    device_type: &'static str,
+    device_name: String,
+    device_model: String,
+    manufacturer: String,
+    device_type: String,
+    columns: Vec<ColumnConfig>,
 }
 
 // ========== CSV Data Point Model ==========
-#[derive(Clone)]
+/// Every row goes to the on-disk history and to live subscribers; the newest
+/// `max_rows` are also kept in memory.
 struct CsvData {
-    headers: Vec<&'static str>,
+    headers: Vec<String>,
     rows: VecDeque<Vec<String>>,
+    max_rows: usize,
+    history: HistoryStore,
+    live: Broadcaster,
 }
 
 impl CsvData {
@@ -31,6 +68,34 @@ impl CsvData {
         }
         csv
     }
+
+    /// Most recent non-empty reading of a column.
+    fn latest_value(&self, column: &str) -> Option<&str> {
+        let index = self.headers.iter().position(|h| h == column)?;
+        self.rows.iter().rev().filter_map(|row| row.get(index)).map(String::as_str).find(|v| !v.is_empty())
+    }
+
+    fn push_row(&mut self, row: Vec<String>) {
+        let seq = self.history.append(&self.headers, &row);
+        if self.live.count() > 0 {
+            self.live.publish(LiveRow { seq, values: jobs::row_object(&self.headers, &row) });
+        }
+        self.rows.push_back(row);
+        if self.rows.len() > self.max_rows { self.rows.pop_front(); }
+    }
+
+    /// Switches to a new column layout, carrying buffered rows over by column name.
+    fn reconfigure(&mut self, headers: Vec<String>, max_rows: usize) {
+        if headers != self.headers {
+            let old = std::mem::replace(&mut self.headers, headers);
+            for row in self.rows.iter_mut() {
+                let cells: HashMap<&String, &String> = old.iter().zip(row.iter()).collect();
+                *row = self.headers.iter().map(|h| cells.get(h).map(|c| c.to_string()).unwrap_or_default()).collect();
+            }
+        }
+        self.max_rows = max_rows;
+        while self.rows.len() > self.max_rows { self.rows.pop_front(); }
+    }
 }
 
 // ========== Command Model ==========
@@ -38,24 +103,110 @@ impl CsvData {
 struct CommandRequest {
     command: String,
     params: Option<serde_json::Value>,
+    /// Idempotency key, used when no `Idempotency-Key` header is sent.
+    #[serde(default)]
+    request_id: Option<String>,
+}
+
+// ========== Runtime Settings ==========
+/// Config-derived settings, replaced as a whole when the config is reloaded.
+/// `generation` tells the poller and telemetry threads that a swap happened.
+struct Settings {
+    generation: u64,
+    bind: (String, u16),
+    device_info: DeviceInfo,
+    commands: CommandRegistry,
+    telemetries: Vec<TelemetryDefinition>,
+    poll_interval: Duration,
+    command_timeout: Duration,
+    job_history: usize,
+    idempotency_window: Duration,
+}
+
+impl Settings {
+    fn from_config(config: DriverConfig, generation: u64) -> Result<Self, String> {
+        Ok(Settings {
+            generation,
+            bind: (config.server.host, config.server.port),
+            device_info: DeviceInfo {
+                device_name: config.device.device_name,
+                device_model: config.device.device_model,
+                manufacturer: config.device.manufacturer,
+                device_type: config.device.device_type,
+                columns: config.columns,
+            },
+            commands: CommandRegistry::new(config.commands)?,
+            telemetries: config.telemetries,
+            poll_interval: Duration::from_millis(config.server.poll_interval_ms),
+            command_timeout: Duration::from_millis(config.jobs.default_timeout_ms),
+            job_history: config.jobs.history,
+            idempotency_window: Duration::from_secs(config.jobs.idempotency_window_secs),
+        })
+    }
 }
 
 // ========== State ==========
 struct AppState {
-    device_info: DeviceInfo,
+    settings: RwLock<Arc<Settings>>,
     csv_data: Mutex<CsvData>,
+    backend: Mutex<Box<dyn DeviceBackend>>,
+    jobs: JobStore,
+    schedules: ScheduleStore,
+    safety: SafetyGuard,
 }
 
-// ========== ENV Utility ==========
-fn get_env(key: &str, default: &str) -> String {
-    env::var(key).unwrap_or_else(|_| default.to_owned())
+impl AppState {
+    fn settings(&self) -> Arc<Settings> {
+        self.settings.read().unwrap().clone()
+    }
+}
+
+// ========== Test Support ==========
+#[cfg(test)]
+impl CsvData {
+    /// Buffered rows with no history on disk.
+    fn for_test(headers: &[&str], rows: &[&[&str]]) -> Self {
+        CsvData {
+            headers: headers.iter().map(|h| h.to_string()).collect(),
+            rows: rows.iter().map(|row| row.iter().map(|c| c.to_string()).collect()).collect(),
+            max_rows: 10,
+            history: HistoryStore::in_memory(&history::HistoryConfig::default()),
+            live: Broadcaster::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl AppState {
+    /// State for a YAML driver config as `main` builds it, without the
+    /// background threads and with nothing written to disk.
+    fn for_test(yaml: &str) -> web::Data<AppState> {
+        let mut config: DriverConfig = serde_yaml::from_str(yaml).unwrap();
+        config.fill_default_commands();
+        config.validate().unwrap();
+        let headers: Vec<String> = config.headers();
+        let backend = std::mem::take(&mut config.backend).build(&headers).unwrap();
+        let mut csv_data = CsvData::for_test(&[], &[]);
+        csv_data.headers = headers;
+        let schedules_file = std::env::temp_dir().join(format!("driver-test-{}-schedules.json", std::process::id()));
+        let schedules = ScheduleStore::open(schedules_file.to_str().unwrap()).unwrap();
+        let settings = Arc::new(Settings::from_config(config, 0).unwrap());
+        web::Data::new(AppState {
+            jobs: JobStore::new(settings.job_history),
+            settings: RwLock::new(settings),
+            csv_data: Mutex::new(csv_data),
+            backend: Mutex::new(backend),
+            schedules,
+            safety: SafetyGuard::default(),
+        })
+    }
 }
 
 // ========== Handlers ==========
 
 // GET /info
 async fn info(data: web::Data<AppState>) -> impl Responder {
-    HttpResponse::Ok().json(&data.device_info)
+    HttpResponse::Ok().json(&data.settings().device_info)
 }
 
 // GET /data
@@ -66,48 +217,442 @@ async fn data(data: web::Data<AppState>) -> impl Responder {
         .body(csv)
 }
 
+#[derive(Deserialize)]
+struct HistoryParams {
+    from: Option<String>,
+    to: Option<String>,
+    limit: Option<usize>,
+    cursor: Option<String>,
+    columns: Option<String>,
+}
+
+const HISTORY_PAGE_LIMIT: usize = 1000;
+
+// GET /data/history?from=&to=&limit=&cursor=&columns=
+// Pages through recorded rows oldest first; `from`/`to` are Unix seconds or
+// RFC 3339 (to exclusive) and `next_cursor` continues after the last row returned
+async fn data_history(data: web::Data<AppState>, params: web::Query<HistoryParams>) -> impl Responder {
+    let params = params.into_inner();
+    let bad_request = |message: String| HttpResponse::BadRequest().json(serde_json::json!({"status": "error", "message": message}));
+    let from = match params.from.as_deref().map(history::parse_time).transpose() {
+        Ok(from) => from,
+        Err(e) => return bad_request(format!("from: {}", e)),
+    };
+    let to = match params.to.as_deref().map(history::parse_time).transpose() {
+        Ok(to) => to,
+        Err(e) => return bad_request(format!("to: {}", e)),
+    };
+    let after_seq = match params.cursor.as_deref().map(str::parse::<u64>).transpose() {
+        Ok(after_seq) => after_seq.unwrap_or(0),
+        Err(_) => return bad_request("cursor is not one returned by this endpoint".to_string()),
+    };
+    let limit = params.limit.unwrap_or(100).clamp(1, HISTORY_PAGE_LIMIT);
+
+    let (headers, segments) = {
+        let csv_data = data.csv_data.lock().unwrap();
+        (csv_data.headers.clone(), csv_data.history.segments())
+    };
+    let columns: Vec<String> = match &params.columns {
+        Some(columns) => columns.split(',').map(|c| c.trim().to_string()).filter(|c| !c.is_empty()).collect(),
+        None => headers.clone(),
+    };
+    if let Some(unknown) = columns.iter().find(|c| !headers.contains(c)) {
+        return bad_request(format!("Unknown column {}", unknown));
+    }
+
+    // One extra record tells whether another page follows
+    let query = history::Query { after_seq, from, to, limit: limit + 1 };
+    let mut records = match web::block(move || history::query(&segments, &query)).await {
+        Ok(records) => records,
+        Err(_) => return HttpResponse::InternalServerError().json(serde_json::json!({"status": "error", "message": "History read aborted"})),
+    };
+    let more = records.len() > limit;
+    records.truncate(limit);
+    let next_cursor = more.then(|| records.last().map(|r| r.seq.to_string())).flatten();
+    let rows: Vec<serde_json::Value> = records
+        .iter()
+        .map(|r| serde_json::json!({"seq": r.seq, "time": r.time, "values": r.row(&columns)}))
+        .collect();
+    HttpResponse::Ok().json(serde_json::json!({"columns": columns, "rows": rows, "next_cursor": next_cursor}))
+}
+
+#[derive(Deserialize)]
+struct AggregateParams {
+    from: Option<String>,
+    to: Option<String>,
+    bucket: Option<String>,
+    columns: Option<String>,
+    stats: Option<String>,
+}
+
+// GET /data/aggregate?from=&to=&bucket=&columns=&stats=
+// Per-bucket statistics over the history; defaults to hourly count/min/max/mean/last
+// of every non-timestamp, non-boolean column over the last 24 hours
+async fn data_aggregate(data: web::Data<AppState>, params: web::Query<AggregateParams>) -> impl Responder {
+    let params = params.into_inner();
+    let bad_request = |message: String| HttpResponse::BadRequest().json(serde_json::json!({"status": "error", "message": message}));
+    let to = match params.to.as_deref().map(history::parse_time).transpose() {
+        Ok(to) => to.unwrap_or_else(history::now_millis),
+        Err(e) => return bad_request(format!("to: {}", e)),
+    };
+    let from = match params.from.as_deref().map(history::parse_time).transpose() {
+        Ok(from) => from.unwrap_or(to.saturating_sub(24 * 3_600_000)),
+        Err(e) => return bad_request(format!("from: {}", e)),
+    };
+    let bucket_ms = match aggregate::parse_bucket(params.bucket.as_deref().unwrap_or("1h")) {
+        Ok(bucket_ms) => bucket_ms,
+        Err(e) => return bad_request(e),
+    };
+    if from >= to {
+        return bad_request("from must be before to".to_string());
+    }
+    let stats = match params
+        .stats
+        .as_deref()
+        .unwrap_or("count,min,max,mean,last")
+        .split(',')
+        .map(|s| aggregate::parse_stat(s.trim()).map(|stat| (s.trim().to_string(), stat)))
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(stats) => stats,
+        Err(e) => return bad_request(e),
+    };
+
+    let settings = data.settings();
+    let known = &settings.device_info.columns;
+    let columns: Vec<String> = match &params.columns {
+        Some(columns) => columns.split(',').map(|c| c.trim().to_string()).filter(|c| !c.is_empty()).collect(),
+        None => known
+            .iter()
+            .filter(|c| c.name != "timestamp" && !matches!(c.data_type, ColumnType::Timestamp | ColumnType::Boolean))
+            .map(|c| c.name.clone())
+            .collect(),
+    };
+    if let Some(unknown) = columns.iter().find(|c| !known.iter().any(|k| k.name == **c)) {
+        return bad_request(format!("Unknown column {}", unknown));
+    }
+    if columns.is_empty() {
+        return bad_request("no columns to aggregate; name them with columns=".to_string());
+    }
+
+    let segments = data.csv_data.lock().unwrap().history.segments();
+    let query = aggregate::AggregateQuery { from, to, bucket_ms, columns, stats };
+    let result = web::block(move || {
+        let buckets = aggregate::aggregate(&segments, &query)?;
+        Ok::<_, String>(serde_json::json!({"from": query.from, "to": query.to, "bucket_ms": query.bucket_ms, "buckets": buckets}))
+    });
+    match result.await {
+        Ok(Ok(body)) => HttpResponse::Ok().json(body),
+        Ok(Err(e)) => bad_request(e),
+        Err(_) => HttpResponse::InternalServerError().json(serde_json::json!({"status": "error", "message": "History read aborted"})),
+    }
+}
+
 // POST /cmd
+// Queues the command and answers 202 with the job to poll at /cmd/{id}
+// A retry with the same Idempotency-Key (or request_id) gets the original job back
 async fn cmd(
+    req: HttpRequest,
     data: web::Data<AppState>,
     payload: web::Json<CommandRequest>
 ) -> impl Responder {
-    // Simulate command execution
-    let mut csv_data = data.csv_data.lock().unwrap();
-    let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs();
-
-    match payload.command.as_str() {
-        "set_temp" => {
-            // Simulate setting temperature
-            if let Some(params) = &payload.params {
-                if let Some(temp) = params.get("temperature").and_then(|v| v.as_f64()) {
-                    let row = vec![
-                        now.to_string(),
-                        format!("{:.2}", temp),
-                        "ok".to_string()
-                    ];
-                    csv_data.rows.push_back(row);
-                    if csv_data.rows.len() > 10 { csv_data.rows.pop_front(); }
-                    return HttpResponse::Ok().json(serde_json::json!({"status": "success"}));
-                }
-            }
-            HttpResponse::BadRequest().json(serde_json::json!({"status": "error", "message": "Missing temperature parameter"}))
+    match submit_command(&data, payload.into_inner(), idempotency_key(&req)) {
+        Ok(Submission::Created(job)) => HttpResponse::Accepted()
+            .insert_header((header::LOCATION, format!("/cmd/{}", job.id)))
+            .json(job),
+        Ok(Submission::Replayed(job)) => {
+            let mut response = if job.status.is_finished() { HttpResponse::Ok() } else { HttpResponse::Accepted() };
+            response
+                .insert_header((header::LOCATION, format!("/cmd/{}", job.id)))
+                .insert_header(("Idempotent-Replayed", "true"))
+                .json(job)
+        }
+        Ok(Submission::Conflict) => idempotency_conflict(),
+        Err(e) => command_error(&e),
+    }
+}
+
+/// 400 for malformed commands; safety rejections answer 409 when the device
+/// state is in the way and 422 when the request itself breaks a limit.
+fn command_error(e: &BackendError) -> HttpResponse {
+    match e {
+        BackendError::Unsafe(violation) => safety_error(violation),
+        _ => HttpResponse::BadRequest().json(serde_json::json!({"status": "error", "message": e.to_string()})),
+    }
+}
+
+fn safety_error(violation: &SafetyViolation) -> HttpResponse {
+    let mut response = if violation.rule.is_conflict() { HttpResponse::Conflict() } else { HttpResponse::UnprocessableEntity() };
+    response.json(serde_json::json!({"status": "error", "message": violation.message, "violation": violation}))
+}
+
+fn idempotency_key(req: &HttpRequest) -> Option<String> {
+    req.headers().get("Idempotency-Key").and_then(|v| v.to_str().ok()).map(str::to_string)
+}
+
+fn idempotency_conflict() -> HttpResponse {
+    HttpResponse::UnprocessableEntity().json(serde_json::json!({
+        "status": "error",
+        "message": "Idempotency key was already used for a different request"
+    }))
+}
+
+// POST /cmd/batch
+// Runs the steps back to back and reports per-step results; the overall
+// outcome is in the body's `status`
+async fn cmd_batch(data: web::Data<AppState>, payload: web::Json<batch::BatchRequest>) -> impl Responder {
+    let resolved = match batch::resolve(&data.settings().commands, payload.into_inner()) {
+        Ok(resolved) => resolved,
+        Err(e) => return command_error(&e),
+    };
+    let state = data.clone();
+    match web::block(move || batch::run(&state, resolved)).await {
+        Ok(result) => HttpResponse::Ok().json(result),
+        Err(_) => HttpResponse::InternalServerError().json(serde_json::json!({"status": "error", "message": "Command execution aborted"})),
+    }
+}
+
+// GET /cmd/{id}
+async fn get_job(data: web::Data<AppState>, path: web::Path<String>) -> impl Responder {
+    match data.jobs.get(&path.into_inner()) {
+        Some(job) => HttpResponse::Ok().json(job),
+        None => HttpResponse::NotFound().json(serde_json::json!({"status": "error", "message": "Unknown job"})),
+    }
+}
+
+// DELETE /cmd/{id}
+async fn cancel_job(data: web::Data<AppState>, path: web::Path<String>) -> impl Responder {
+    match data.jobs.cancel(&path.into_inner()) {
+        Some(Ok(job)) => HttpResponse::Ok().json(job),
+        Some(Err(job)) => HttpResponse::Conflict().json(job),
+        None => HttpResponse::NotFound().json(serde_json::json!({"status": "error", "message": "Unknown job"})),
+    }
+}
+
+// GET|POST /{instruction}
+// Shifu-style route per declared command; params come from a JSON body or the query string
+async fn instruction(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<HashMap<String, String>>,
+    body: web::Bytes,
+) -> HttpResponse {
+    let name = path.into_inner();
+    if !data.settings().commands.contains(&name) {
+        return HttpResponse::NotFound().finish();
+    }
+    let params = if body.iter().all(|b| b.is_ascii_whitespace()) {
+        // Query values are strings; numbers and booleans are passed through typed
+        let params: serde_json::Map<String, serde_json::Value> = query
+            .into_inner()
+            .into_iter()
+            .map(|(k, v)| {
+                let value = serde_json::from_str(&v).unwrap_or(serde_json::Value::String(v));
+                (k, value)
+            })
+            .collect();
+        (!params.is_empty()).then_some(serde_json::Value::Object(params))
+    } else {
+        match serde_json::from_slice(&body) {
+            Ok(params) => Some(params),
+            Err(e) => return HttpResponse::BadRequest().json(serde_json::json!({"status": "error", "message": e.to_string()})),
         }
-        _ => HttpResponse::BadRequest().json(serde_json::json!({"status": "error", "message": "Unknown command"})),
+    };
+    let request = CommandRequest { command: name, params, request_id: None };
+    let job = match submit_command(&data, request, idempotency_key(&req)) {
+        Ok(Submission::Created(job)) | Ok(Submission::Replayed(job)) => job,
+        Ok(Submission::Conflict) => return idempotency_conflict(),
+        Err(e) => return command_error(&e),
+    };
+
+    // Instruction routes answer synchronously, like a deviceShifu
+    let state = data.clone();
+    let finished = match web::block(move || state.jobs.wait(&job.id)).await {
+        Ok(Some(job)) => job,
+        _ => return HttpResponse::InternalServerError().json(serde_json::json!({"status": "error", "message": "Command execution aborted"})),
+    };
+    if let Some(violation) = &finished.violation {
+        return safety_error(violation);
+    }
+    let message = finished.error.clone().unwrap_or_default();
+    match finished.status {
+        JobStatus::Succeeded => HttpResponse::Ok().json(finished),
+        JobStatus::Failed if finished.rejected => HttpResponse::BadRequest().json(serde_json::json!({"status": "error", "message": message})),
+        JobStatus::Failed => HttpResponse::BadGateway().json(serde_json::json!({"status": "error", "message": message})),
+        JobStatus::TimedOut => HttpResponse::GatewayTimeout().json(serde_json::json!({"status": "error", "message": message})),
+        _ => HttpResponse::Conflict().json(serde_json::json!({"status": "error", "message": "Command was cancelled"})),
+    }
+}
+
+/// Validates a command against the registry and queues it as a job, keyed by
+/// the header key or else the request's own `request_id`.
+fn submit_command(data: &AppState, mut command: CommandRequest, key: Option<String>) -> Result<Submission, BackendError> {
+    let settings = data.settings();
+    let name = command.command.clone();
+    let key = key.or_else(|| command.request_id.take()).map(|key| {
+        let fingerprint = serde_json::json!([command.command, command.params]).to_string();
+        (key, fingerprint)
+    });
+    let resolved = settings.commands.resolve(command)?;
+    // A duplicate gets its original job even if the device state has moved on since
+    if let Some(submission) = key.as_ref().and_then(|key| data.jobs.replay(key, settings.idempotency_window)) {
+        return Ok(submission);
+    }
+    // Refuse up front what the device state rules out; the check repeats when the job runs
+    if let Some(check) = &resolved.safety {
+        data.safety.check(check, &data.csv_data.lock().unwrap())?;
+    }
+    let timeout = resolved.timeout.unwrap_or(settings.command_timeout);
+    Ok(data.jobs.submit(&name, resolved, timeout, key, settings.idempotency_window))
+}
+
+// POST /schedules
+async fn create_schedule(data: web::Data<AppState>, payload: web::Json<ScheduleRequest>) -> impl Responder {
+    let request = payload.into_inner();
+    // Reject commands the registry would refuse now rather than at run time
+    let probe = CommandRequest {
+        command: request.command.clone(),
+        params: request.params.clone(),
+        request_id: None,
+    };
+    if let Err(e) = data.settings().commands.resolve(probe) {
+        return command_error(&e);
+    }
+    match data.schedules.create(request) {
+        Ok(schedule) => HttpResponse::Created()
+            .insert_header((header::LOCATION, format!("/schedules/{}", schedule.id)))
+            .json(schedule),
+        Err(e) => HttpResponse::BadRequest().json(serde_json::json!({"status": "error", "message": e})),
     }
 }
 
+// GET /schedules
+async fn list_schedules(data: web::Data<AppState>) -> impl Responder {
+    HttpResponse::Ok().json(data.schedules.list())
+}
+
+// GET /schedules/{id}
+async fn get_schedule(data: web::Data<AppState>, path: web::Path<String>) -> impl Responder {
+    match data.schedules.get(&path.into_inner()) {
+        Some(schedule) => HttpResponse::Ok().json(schedule),
+        None => HttpResponse::NotFound().json(serde_json::json!({"status": "error", "message": "Unknown schedule"})),
+    }
+}
+
+// DELETE /schedules/{id}
+async fn delete_schedule(data: web::Data<AppState>, path: web::Path<String>) -> impl Responder {
+    match data.schedules.remove(&path.into_inner()) {
+        Some(schedule) => HttpResponse::Ok().json(schedule),
+        None => HttpResponse::NotFound().json(serde_json::json!({"status": "error", "message": "Unknown schedule"})),
+    }
+}
+
+// GET /commands
+async fn list_commands(data: web::Data<AppState>) -> impl Responder {
+    HttpResponse::Ok().json(data.settings().commands.definitions())
+}
+
+// GET /stats
+async fn stats(data: web::Data<AppState>) -> impl Responder {
+    let backend = data.backend.lock().unwrap().stats();
+    let (rows_buffered, stream_subscribers) = {
+        let csv_data = data.csv_data.lock().unwrap();
+        (csv_data.rows.len(), csv_data.live.count())
+    };
+    HttpResponse::Ok().json(serde_json::json!({
+        "rows_buffered": rows_buffered,
+        "stream_subscribers": stream_subscribers,
+        "backend": backend
+    }))
+}
+
+#[derive(Deserialize)]
+struct StreamParams {
+    #[serde(default)]
+    follow: bool,
+    limit: Option<u64>,
+    idle_timeout_ms: Option<u64>,
+}
+
 // ====== Simulate Raw Protocol Fetch, Convert to HTTP CSV Stream ======
-async fn stream_csv(data: web::Data<AppState>) -> Result<HttpResponse> {
-    let csv_data = data.csv_data.lock().unwrap();
+// GET /stream?follow=&limit=&idle_timeout_ms=
+// The buffered rows as CSV; with follow=true the response stays open and new
+// rows are appended like `tail -f`, until `limit` more rows were written or
+// none arrived for `idle_timeout_ms`
+async fn stream_csv(data: web::Data<AppState>, params: web::Query<StreamParams>) -> Result<HttpResponse> {
+    let mut csv_data = data.csv_data.lock().unwrap();
     let mut stream = csv_data.headers.join(",") + "\n";
     for row in csv_data.rows.iter() {
         stream += &row.join(",");
         stream += "\n";
     }
+    if !params.follow {
+        return Ok(HttpResponse::Ok()
+            .insert_header((header::CONTENT_TYPE, "text/csv"))
+            .insert_header((header::CACHE_CONTROL, "no-cache"))
+            .body(stream));
+    }
+    // Subscribing under the same lock as the snapshot leaves no gap or overlap
+    let subscriber = csv_data.live.subscribe();
+    let idle_timeout = params.idle_timeout_ms.map(Duration::from_millis);
+    let body = CsvStream::new(subscriber, csv_data.headers.clone(), stream, params.limit, idle_timeout);
     Ok(HttpResponse::Ok()
         .insert_header((header::CONTENT_TYPE, "text/csv"))
         .insert_header((header::CACHE_CONTROL, "no-cache"))
-        .body(stream))
+        .insert_header(("X-Accel-Buffering", "no"))
+        .body(body))
+}
+
+// GET /stream/sse
+// Pushes each recorded row as an SSE `row` event whose id is its history
+// sequence; reconnecting with Last-Event-ID first replays the rows missed,
+// preceded by a `gap` event if there were too many to replay
+async fn stream_sse(req: HttpRequest, data: web::Data<AppState>) -> HttpResponse {
+    let last_id = req
+        .headers()
+        .get("Last-Event-ID")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<u64>().ok());
+    // Subscribing and noting the newest persisted row together leaves no gap or overlap
+    let (subscriber, headers, segments, newest) = {
+        let mut csv_data = data.csv_data.lock().unwrap();
+        let subscriber = csv_data.live.subscribe();
+        (subscriber, csv_data.headers.clone(), csv_data.history.segments(), csv_data.history.last_seq())
+    };
+    let missed = match last_id {
+        Some(after) if after < newest => web::block(move || {
+            let mut missed = VecDeque::new();
+            history::scan(&segments, after, None, None, |record| {
+                if record.seq > newest {
+                    return false;
+                }
+                if missed.len() == stream::MAX_RESUME {
+                    missed.pop_front();
+                }
+                missed.push_back(LiveRow::from_record(&record, &headers));
+                true
+            });
+            Vec::from(missed)
+        })
+        .await
+        .unwrap_or_default(),
+        _ => Vec::new(),
+    };
+    HttpResponse::Ok()
+        .insert_header((header::CONTENT_TYPE, "text/event-stream"))
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .insert_header(("X-Accel-Buffering", "no"))
+        .body(EventStream::new(subscriber, last_id, newest, missed))
+}
+
+// GET /ws
+// WebSocket for HMIs: `subscribe` frames pick columns and a minimum interval
+// for live rows; `command` frames run like POST /cmd and are answered in place
+async fn ws_connect(req: HttpRequest, body: web::Payload, data: web::Data<AppState>) -> Result<HttpResponse> {
+    let (response, session, messages) = actix_ws::handle(&req, body)?;
+    ws::serve(data, session, messages);
+    Ok(response)
 }
 
 // ========== Main ==========
@@ -115,46 +660,72 @@ async fn stream_csv(data: web::Data<AppState>) -> Result<HttpResponse> {
 async fn main() -> std::io::Result<()> {
     env_logger::init();
 
-    // Read environment variables
-    let server_host = get_env("SERVER_HOST", "0.0.0.0");
-    let server_port = get_env("SERVER_PORT", "8080");
+    // Driver config from --config/DRIVER_CONFIG, falling back to the legacy env setup
+    let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidInput, e);
+    let mut config = reload::load_config().map_err(invalid)?;
+    let reload_interval = config.server.reload_interval_ms;
 
-    // Simulated headers and data (can be replaced with real protocol fetch)
-    let csv_headers = vec!["timestamp", "temperature", "status"];
-    let mut csv_rows = VecDeque::new();
-    csv_rows.push_back(vec![
-        format!("{}", SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs()),
-        "25.0".to_string(),
-        "ok".to_string()
-    ]);
-
-    let device_info = DeviceInfo {
-        device_name: "的v分·",
-        device_model: "个人",
-        manufacturer: "拰发·",
-        device_type: " 为服务",
-    };
+    // Rows are filled by the device backend's polls
+    let csv_headers = config.headers();
+    let backend = std::mem::take(&mut config.backend).build(&csv_headers).map_err(invalid)?;
 
+    // Pick up where the last run left off
+    let history = HistoryStore::open(&config.history).unwrap_or_else(|e| {
+        log::error!("history unavailable, keeping rows in memory only: {}", e);
+        HistoryStore::in_memory(&config.history)
+    });
+    let rows = history.tail(config.server.max_rows).iter().map(|r| r.row(&csv_headers)).collect();
     let csv_data = CsvData {
         headers: csv_headers,
-        rows: csv_rows,
+        rows,
+        max_rows: config.server.max_rows,
+        history,
+        live: Broadcaster::default(),
     };
 
+    let schedules = ScheduleStore::open(&config.schedules.file).map_err(invalid)?;
+    let settings = Arc::new(Settings::from_config(config, 0).map_err(invalid)?);
+    let bind = settings.bind.clone();
     let state = web::Data::new(AppState {
-        device_info,
+        settings: RwLock::new(settings.clone()),
         csv_data: Mutex::new(csv_data),
+        backend: Mutex::new(backend),
+        jobs: JobStore::new(settings.job_history),
+        schedules,
+        safety: SafetyGuard::default(),
     });
 
+    backend::spawn_poller(state.clone());
+    jobs::spawn_worker(state.clone());
+    schedules::spawn_scheduler(state.clone());
+    for telemetry in &settings.telemetries {
+        backend::spawn_telemetry(state.clone(), telemetry.clone(), settings.generation);
+    }
+    if reload_interval > 0 {
+        reload::spawn_watcher(state.clone(), Duration::from_millis(reload_interval));
+    }
+
     HttpServer::new(move || {
         App::new()
             .app_data(state.clone())
             .wrap(Logger::default())
             .service(web::resource("/info").route(web::get().to(info)))
             .service(web::resource("/data").route(web::get().to(data)))
+            .service(web::resource("/data/history").route(web::get().to(data_history)))
+            .service(web::resource("/data/aggregate").route(web::get().to(data_aggregate)))
             .service(web::resource("/cmd").route(web::post().to(cmd)))
+            .service(web::resource("/cmd/batch").route(web::post().to(cmd_batch)))
+            .service(web::resource("/cmd/{id}").route(web::get().to(get_job)).route(web::delete().to(cancel_job)))
             .service(web::resource("/stream").route(web::get().to(stream_csv)))
+            .service(web::resource("/stream/sse").route(web::get().to(stream_sse)))
+            .service(web::resource("/ws").route(web::get().to(ws_connect)))
+            .service(web::resource("/schedules").route(web::get().to(list_schedules)).route(web::post().to(create_schedule)))
+            .service(web::resource("/schedules/{id}").route(web::get().to(get_schedule)).route(web::delete().to(delete_schedule)))
+            .service(web::resource("/commands").route(web::get().to(list_commands)))
+            .service(web::resource("/stats").route(web::get().to(stats)))
+            .service(web::resource("/{instruction}").route(web::get().to(instruction)).route(web::post().to(instruction)))
     })
-    .bind(format!("{}:{}", server_host, server_port))?
+    .bind(bind)?
     .run()
     .await
 }
\ No newline at end of file
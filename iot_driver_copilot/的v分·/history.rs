@@ -0,0 +1,465 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::backend::Row;
+
+// ========== History Config ==========
+fn default_dir() -> String {
+    "data/history".to_string()
+}
+
+fn default_segment_bytes() -> u64 {
+    4 * 1024 * 1024
+}
+
+fn default_max_age_secs() -> Option<u64> {
+    Some(7 * 24 * 3600)
+}
+
+fn default_max_bytes() -> Option<u64> {
+    Some(256 * 1024 * 1024)
+}
+
+#[derive(Deserialize, Clone)]
+pub struct HistoryConfig {
+    /// Directory holding the append-only segment files.
+    #[serde(default = "default_dir")]
+    pub dir: String,
+    /// A new segment is started once the current one reaches this size.
+    #[serde(default = "default_segment_bytes")]
+    pub segment_bytes: u64,
+    #[serde(default)]
+    pub retention: RetentionConfig,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        HistoryConfig {
+            dir: default_dir(),
+            segment_bytes: default_segment_bytes(),
+            retention: RetentionConfig::default(),
+        }
+    }
+}
+
+/// Limits on what is kept; the oldest segment is deleted as a whole once
+/// dropping it still leaves the history within every limit. `null` disables a limit.
+#[derive(Deserialize, Clone)]
+pub struct RetentionConfig {
+    #[serde(default)]
+    pub max_rows: Option<u64>,
+    #[serde(default = "default_max_age_secs")]
+    pub max_age_secs: Option<u64>,
+    #[serde(default = "default_max_bytes")]
+    pub max_bytes: Option<u64>,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        RetentionConfig {
+            max_rows: None,
+            max_age_secs: default_max_age_secs(),
+            max_bytes: default_max_bytes(),
+        }
+    }
+}
+
+// ========== Records ==========
+/// One recorded row, keyed by column so it survives column layout changes.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Record {
+    pub seq: u64,
+    /// Unix milliseconds when the row was recorded.
+    pub time: u64,
+    pub values: BTreeMap<String, String>,
+}
+
+impl Record {
+    /// Lays the values out in header order, blank where a column is missing.
+    pub fn row(&self, headers: &[String]) -> Row {
+        headers.iter().map(|h| self.values.get(h).cloned().unwrap_or_default()).collect()
+    }
+}
+
+pub fn now_millis() -> u64 {
+    SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis() as u64
+}
+
+// ========== Segment Store ==========
+/// A JSON-lines file of records, named after its first sequence number.
+#[derive(Clone)]
+pub struct Segment {
+    path: PathBuf,
+    rows: u64,
+    bytes: u64,
+    last_seq: u64,
+    last_time: u64,
+}
+
+/// Time series of every recorded row, stored as append-only segment files.
+pub struct HistoryStore {
+    config: HistoryConfig,
+    segments: VecDeque<Segment>,
+    file: Option<File>,
+    next_seq: u64,
+    /// False when the directory could not be opened; rows are then numbered but not stored.
+    persist: bool,
+}
+
+fn read_segment(path: &Path) -> std::io::Result<Vec<Record>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut records = Vec::new();
+    for line in reader.split(b'\n') {
+        // A damaged line is skipped rather than failing the segment
+        match serde_json::from_slice(&line?) {
+            Ok(record) => records.push(record),
+            Err(e) => log::warn!("skipping damaged history record in {}: {}", path.display(), e),
+        }
+    }
+    Ok(records)
+}
+
+/// Cuts off a final line left incomplete by a crash, so appends start on a fresh line.
+fn truncate_torn_tail(path: &Path) -> std::io::Result<()> {
+    let data = fs::read(path)?;
+    let keep = data.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+    if keep < data.len() {
+        log::warn!("truncating an incomplete history record at the end of {}", path.display());
+        OpenOptions::new().write(true).open(path)?.set_len(keep as u64)?;
+    }
+    Ok(())
+}
+
+impl HistoryStore {
+    pub fn open(config: &HistoryConfig) -> Result<Self, String> {
+        let dir = Path::new(&config.dir);
+        fs::create_dir_all(dir).map_err(|e| format!("{}: {}", config.dir, e))?;
+        let mut paths: Vec<(u64, PathBuf)> = fs::read_dir(dir)
+            .map_err(|e| format!("{}: {}", config.dir, e))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|p| p.extension().is_some_and(|ext| ext == "jsonl"))
+            .filter_map(|p| Some((p.file_stem()?.to_str()?.parse().ok()?, p)))
+            .collect();
+        paths.sort();
+        if let Some((_, path)) = paths.last() {
+            truncate_torn_tail(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        }
+
+        let mut segments = VecDeque::new();
+        let mut next_seq = 1;
+        for (first_seq, path) in paths {
+            let records = read_segment(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            let bytes = fs::metadata(&path).map(|m| m.len()).unwrap_or_default();
+            next_seq = next_seq.max(first_seq).max(records.last().map_or(0, |r| r.seq + 1));
+            segments.push_back(Segment {
+                path,
+                rows: records.len() as u64,
+                bytes,
+                last_seq: records.last().map_or(0, |r| r.seq),
+                last_time: records.last().map_or(0, |r| r.time),
+            });
+        }
+        let mut store = HistoryStore {
+            config: config.clone(),
+            segments,
+            file: None,
+            next_seq,
+            persist: true,
+        };
+        store.enforce_retention();
+        Ok(store)
+    }
+
+    /// A store that keeps nothing on disk, for when `open` fails.
+    pub fn in_memory(config: &HistoryConfig) -> Self {
+        HistoryStore {
+            config: config.clone(),
+            segments: VecDeque::new(),
+            file: None,
+            next_seq: 1,
+            persist: false,
+        }
+    }
+
+    /// Picks up retention changes; the directory and segment size are fixed until restart.
+    pub fn reconfigure(&mut self, config: &HistoryConfig) {
+        if config.dir != self.config.dir || config.segment_bytes != self.config.segment_bytes {
+            log::warn!("history dir/segment_bytes changes take effect after a restart");
+        }
+        self.config.retention = config.retention.clone();
+        self.enforce_retention();
+    }
+
+    /// Records a row, returning its sequence number; write failures are logged
+    /// and the row is then only kept in memory.
+    pub fn append(&mut self, headers: &[String], row: &Row) -> Option<u64> {
+        let record = Record {
+            seq: self.next_seq,
+            time: now_millis(),
+            values: headers.iter().cloned().zip(row.iter().cloned()).collect(),
+        };
+        match self.write(&record) {
+            Ok(()) => {
+                self.next_seq += 1;
+                self.enforce_retention();
+                Some(record.seq)
+            }
+            Err(e) => {
+                log::error!("failed to append to history in {}: {}", self.config.dir, e);
+                self.file = None;
+                None
+            }
+        }
+    }
+
+    fn write(&mut self, record: &Record) -> std::io::Result<()> {
+        if !self.persist {
+            return Ok(());
+        }
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        let full = self.segments.back().is_none_or(|s| s.bytes >= self.config.segment_bytes);
+        if full {
+            let path = Path::new(&self.config.dir).join(format!("{:020}.jsonl", record.seq));
+            self.segments.push_back(Segment { path, rows: 0, bytes: 0, last_seq: 0, last_time: 0 });
+            self.file = None;
+        }
+        let segment = self.segments.back_mut().expect("an open segment");
+        if self.file.is_none() {
+            self.file = Some(OpenOptions::new().create(true).append(true).open(&segment.path)?);
+        }
+        self.file.as_mut().expect("an open segment file").write_all(&line)?;
+        segment.rows += 1;
+        segment.bytes += line.len() as u64;
+        segment.last_seq = record.seq;
+        segment.last_time = record.time;
+        Ok(())
+    }
+
+    fn enforce_retention(&mut self) {
+        let retention = &self.config.retention;
+        let oldest_allowed = retention.max_age_secs.map(|age| now_millis().saturating_sub(age * 1000));
+        let mut rows: u64 = self.segments.iter().map(|s| s.rows).sum();
+        let mut bytes: u64 = self.segments.iter().map(|s| s.bytes).sum();
+        // The segment being written to is never dropped
+        while self.segments.len() > 1 {
+            let oldest = &self.segments[0];
+            let expired = oldest_allowed.is_some_and(|t| oldest.last_time < t);
+            let too_many = retention.max_rows.is_some_and(|max| rows - oldest.rows >= max);
+            let too_big = retention.max_bytes.is_some_and(|max| bytes > max);
+            if !(expired || too_many || too_big) {
+                break;
+            }
+            let oldest = self.segments.pop_front().expect("a segment to drop");
+            if let Err(e) = fs::remove_file(&oldest.path) {
+                log::warn!("failed to delete history segment {}: {}", oldest.path.display(), e);
+            }
+            rows -= oldest.rows;
+            bytes -= oldest.bytes;
+        }
+    }
+
+    /// Sequence number of the newest record, 0 if there is none.
+    pub fn last_seq(&self) -> u64 {
+        self.next_seq - 1
+    }
+
+    /// The current segments, for reading without holding up appends.
+    pub fn segments(&self) -> Vec<Segment> {
+        self.segments.iter().cloned().collect()
+    }
+
+    /// The newest `count` records, oldest first.
+    pub fn tail(&self, count: usize) -> Vec<Record> {
+        let mut records = VecDeque::new();
+        for segment in self.segments.iter().rev() {
+            if records.len() >= count {
+                break;
+            }
+            match read_segment(&segment.path) {
+                Ok(segment_records) => {
+                    for record in segment_records.into_iter().rev() {
+                        if records.len() >= count {
+                            break;
+                        }
+                        records.push_front(record);
+                    }
+                }
+                Err(e) => log::warn!("failed to read history segment {}: {}", segment.path.display(), e),
+            }
+        }
+        records.into()
+    }
+}
+
+// ========== Queries ==========
+/// A page of history: records after `after_seq` recorded in `[from, to)`
+/// (Unix milliseconds), oldest first.
+pub struct Query {
+    pub after_seq: u64,
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+    pub limit: usize,
+}
+
+/// Visits the records of a snapshot from `HistoryStore::segments` in order,
+/// starting after `after_seq` and from `from` (Unix milliseconds), until `to`
+/// or until `visit` returns false. Segments deleted by retention since the
+/// snapshot are skipped.
+pub fn scan(segments: &[Segment], after_seq: u64, from: Option<u64>, to: Option<u64>, mut visit: impl FnMut(Record) -> bool) {
+    for segment in segments {
+        if segment.last_seq <= after_seq || from.is_some_and(|from| segment.last_time < from) {
+            continue;
+        }
+        let records = match read_segment(&segment.path) {
+            Ok(records) => records,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => {
+                log::warn!("failed to read history segment {}: {}", segment.path.display(), e);
+                continue;
+            }
+        };
+        for record in records {
+            if to.is_some_and(|to| record.time >= to) {
+                return;
+            }
+            if record.seq > after_seq && from.is_none_or(|from| record.time >= from) && !visit(record) {
+                return;
+            }
+        }
+    }
+}
+
+pub fn query(segments: &[Segment], query: &Query) -> Vec<Record> {
+    let mut records = Vec::new();
+    scan(segments, query.after_seq, query.from, query.to, |record| {
+        records.push(record);
+        records.len() < query.limit
+    });
+    records
+}
+
+/// Unix seconds (fractions allowed) or an RFC 3339 timestamp, as Unix milliseconds.
+pub fn parse_time(text: &str) -> Result<u64, String> {
+    if let Ok(secs) = text.parse::<f64>() {
+        if secs.is_finite() && secs >= 0.0 {
+            return Ok((secs * 1000.0) as u64);
+        }
+    }
+    chrono::DateTime::parse_from_rfc3339(text)
+        .map(|t| t.timestamp_millis().max(0) as u64)
+        .map_err(|_| format!("'{}' is neither Unix seconds nor an RFC 3339 time", text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(name: &str, segment_bytes: u64, max_rows: Option<u64>) -> HistoryConfig {
+        let dir = std::env::temp_dir().join(format!("history-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        HistoryConfig {
+            dir: dir.to_string_lossy().into_owned(),
+            segment_bytes,
+            retention: RetentionConfig { max_rows, max_age_secs: None, max_bytes: None },
+        }
+    }
+
+    fn append(store: &mut HistoryStore, value: &str) -> Option<u64> {
+        store.append(&["value".to_string()], &vec![value.to_string()])
+    }
+
+    fn seqs(records: &[Record]) -> Vec<u64> {
+        records.iter().map(|r| r.seq).collect()
+    }
+
+    #[test]
+    fn numbering_continues_after_a_restart() {
+        let config = config("restart", 1024, None);
+        let mut store = HistoryStore::open(&config).unwrap();
+        assert_eq!((append(&mut store, "a"), append(&mut store, "b"), append(&mut store, "c")), (Some(1), Some(2), Some(3)));
+        drop(store);
+
+        let mut store = HistoryStore::open(&config).unwrap();
+        assert_eq!(store.last_seq(), 3);
+        assert_eq!(seqs(&store.tail(2)), [2, 3]);
+        assert_eq!(store.tail(2)[1].row(&["value".to_string(), "gone".to_string()]), ["c", ""]);
+        assert_eq!(append(&mut store, "d"), Some(4));
+        let _ = fs::remove_dir_all(&config.dir);
+    }
+
+    #[test]
+    fn retention_drops_whole_segments_oldest_first() {
+        // Every record fills a segment of its own
+        let config = config("retention", 1, Some(2));
+        let mut store = HistoryStore::open(&config).unwrap();
+        for value in ["a", "b", "c", "d", "e"] {
+            append(&mut store, value);
+        }
+        assert_eq!(fs::read_dir(&config.dir).unwrap().count(), 2);
+        assert_eq!(seqs(&store.tail(10)), [4, 5]);
+        let _ = fs::remove_dir_all(&config.dir);
+    }
+
+    #[test]
+    fn damaged_lines_are_skipped_and_a_torn_tail_cut_off() {
+        let config = config("torn", 1024, None);
+        let mut store = HistoryStore::open(&config).unwrap();
+        append(&mut store, "a");
+        drop(store);
+        let path = fs::read_dir(&config.dir).unwrap().next().unwrap().unwrap().path();
+        OpenOptions::new().append(true).open(&path).unwrap().write_all(b"\xff\xfe\n{\"seq\":2,\"ti").unwrap();
+
+        let mut store = HistoryStore::open(&config).unwrap();
+        assert_eq!(append(&mut store, "b"), Some(2));
+        assert_eq!(seqs(&store.tail(10)), [1, 2]);
+        assert!(fs::read(&path).unwrap().ends_with(b"\n"));
+        let _ = fs::remove_dir_all(&config.dir);
+    }
+
+    #[test]
+    fn in_memory_store_numbers_rows_without_keeping_them() {
+        let config = config("memory", 1024, None);
+        let mut store = HistoryStore::in_memory(&config);
+        assert_eq!((append(&mut store, "a"), append(&mut store, "b")), (Some(1), Some(2)));
+        assert_eq!(store.last_seq(), 2);
+        assert!(store.tail(10).is_empty());
+        assert!(!Path::new(&config.dir).exists());
+    }
+
+    #[test]
+    fn scan_pages_by_sequence_and_time() {
+        let config = config("scan", 1, None);
+        let mut store = HistoryStore::open(&config).unwrap();
+        for value in ["a", "b", "c", "d"] {
+            append(&mut store, value);
+        }
+        let segments = store.segments();
+        let all = query(&segments, &Query { after_seq: 0, from: None, to: None, limit: 10 });
+        assert_eq!(seqs(&all), [1, 2, 3, 4]);
+        let page = query(&segments, &Query { after_seq: 1, from: None, to: None, limit: 2 });
+        assert_eq!(seqs(&page), [2, 3]);
+        let before = query(&segments, &Query { after_seq: 0, from: None, to: Some(all[0].time), limit: 10 });
+        assert!(before.is_empty());
+        let since = query(&segments, &Query { after_seq: 0, from: Some(all[3].time), to: None, limit: 10 });
+        assert_eq!(seqs(&since).last(), Some(&4));
+
+        // A segment deleted by retention after the snapshot is skipped
+        fs::remove_file(&segments[1].path).unwrap();
+        assert_eq!(seqs(&query(&segments, &Query { after_seq: 0, from: None, to: None, limit: 10 })), [1, 3, 4]);
+        let _ = fs::remove_dir_all(&config.dir);
+    }
+
+    #[test]
+    fn times_are_unix_seconds_or_rfc3339() {
+        assert_eq!(parse_time("1.5"), Ok(1500));
+        assert_eq!(parse_time("1970-01-01T00:00:02Z"), Ok(2000));
+        assert_eq!(parse_time("2026-01-01T01:00:00+01:00"), Ok(1_767_225_600_000));
+        assert!(parse_time("-1").is_err());
+        assert!(parse_time("yesterday").is_err());
+    }
+}
@@ -0,0 +1,493 @@
+use actix_web::web;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::mpsc;
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::backend::{unix_timestamp, BackendError, Row};
+use crate::commands::ResolvedCommand;
+use crate::safety::SafetyViolation;
+use crate::verify::Verification;
+use crate::AppState;
+
+// ========== Job Config ==========
+fn default_history() -> usize {
+    100
+}
+
+fn default_timeout_ms() -> u64 {
+    10_000
+}
+
+fn default_idempotency_window_secs() -> u64 {
+    600
+}
+
+#[derive(Deserialize, Clone)]
+pub struct JobsConfig {
+    /// Finished jobs kept for `GET /cmd/{id}`; older ones are forgotten.
+    #[serde(default = "default_history")]
+    pub history: usize,
+    /// Timeout for commands that do not declare their own `timeout_ms`.
+    #[serde(default = "default_timeout_ms")]
+    pub default_timeout_ms: u64,
+    /// How long an idempotency key keeps answering with its original job.
+    #[serde(default = "default_idempotency_window_secs")]
+    pub idempotency_window_secs: u64,
+}
+
+impl Default for JobsConfig {
+    fn default() -> Self {
+        JobsConfig {
+            history: default_history(),
+            default_timeout_ms: default_timeout_ms(),
+            idempotency_window_secs: default_idempotency_window_secs(),
+        }
+    }
+}
+
+// ========== Job Model ==========
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Pending,
+    Running,
+    Succeeded,
+    Failed,
+    TimedOut,
+    Cancelled,
+}
+
+impl JobStatus {
+    pub fn is_finished(self) -> bool {
+        !matches!(self, JobStatus::Pending | JobStatus::Running)
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub struct Job {
+    pub id: String,
+    pub command: String,
+    pub status: JobStatus,
+    pub submitted_at: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub started_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<String>,
+    /// Telemetry recorded by the command, keyed by column.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<serde_json::Value>,
+    /// Whether reading the device back confirmed the write.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verification: Option<Verification>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The safety rule the command was refused by.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub violation: Option<SafetyViolation>,
+    /// Failed because the request was rejected by the backend rather than the device.
+    #[serde(skip)]
+    pub rejected: bool,
+}
+
+/// What a finished job reports.
+#[derive(Default)]
+struct Completion {
+    result: Option<serde_json::Value>,
+    verification: Option<Verification>,
+    failure: Option<Failure>,
+}
+
+impl From<Failure> for Completion {
+    fn from(failure: Failure) -> Self {
+        Completion { failure: Some(failure), ..Completion::default() }
+    }
+}
+
+/// Why a job did not succeed.
+struct Failure {
+    message: String,
+    rejected: bool,
+    violation: Option<SafetyViolation>,
+}
+
+impl Failure {
+    fn timed_out(timeout: Duration) -> Self {
+        Failure {
+            message: format!("no response within {:?}; the command may still take effect, outcome unknown", timeout),
+            rejected: false,
+            violation: None,
+        }
+    }
+}
+
+impl From<BackendError> for Failure {
+    fn from(e: BackendError) -> Self {
+        let rejected = matches!(e, BackendError::UnknownCommand(_) | BackendError::InvalidParams(_) | BackendError::Unsafe(_));
+        let message = e.to_string();
+        let violation = match e {
+            BackendError::Unsafe(violation) => Some(violation),
+            _ => None,
+        };
+        Failure { message, rejected, violation }
+    }
+}
+
+struct QueuedJob {
+    id: String,
+    resolved: ResolvedCommand,
+    timeout: Duration,
+}
+
+/// A job remembered under an idempotency key, outliving the job history if needed.
+struct KeyedJob {
+    job_id: String,
+    fingerprint: String,
+    created: Instant,
+    retained: Option<Job>,
+}
+
+/// Outcome of submitting a command under an optional idempotency key.
+pub enum Submission {
+    Created(Job),
+    /// The key was seen before with the same request; this is the original job.
+    Replayed(Job),
+    /// The key was seen before with a different request.
+    Conflict,
+}
+
+struct Jobs {
+    next_id: u64,
+    jobs: HashMap<String, Job>,
+    finished: VecDeque<String>,
+    queue: VecDeque<QueuedJob>,
+    history: usize,
+    keys: HashMap<String, KeyedJob>,
+}
+
+// ========== Job Store ==========
+/// Queue and bounded history of command jobs, executed one at a time in
+/// submission order by the worker thread.
+pub struct JobStore {
+    inner: Mutex<Jobs>,
+    changed: Condvar,
+}
+
+impl JobStore {
+    pub fn new(history: usize) -> Self {
+        JobStore {
+            inner: Mutex::new(Jobs {
+                next_id: 1,
+                jobs: HashMap::new(),
+                finished: VecDeque::new(),
+                queue: VecDeque::new(),
+                history,
+                keys: HashMap::new(),
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    pub fn set_history(&self, history: usize) {
+        self.inner.lock().unwrap().history = history;
+    }
+
+    /// Queues a job, or returns the job already created for `key` within `window`.
+    /// `fingerprint` identifies the request so a reused key can be told apart.
+    pub fn submit(
+        &self,
+        command: &str,
+        resolved: ResolvedCommand,
+        timeout: Duration,
+        key: Option<(String, String)>,
+        window: Duration,
+    ) -> Submission {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(submission) = key.as_ref().and_then(|key| inner.replay(key, window)) {
+            return submission;
+        }
+        let id = inner.next_id.to_string();
+        inner.next_id += 1;
+        let job = Job {
+            id: id.clone(),
+            command: command.to_string(),
+            status: JobStatus::Pending,
+            submitted_at: unix_timestamp(),
+            started_at: None,
+            finished_at: None,
+            result: None,
+            verification: None,
+            error: None,
+            violation: None,
+            rejected: false,
+        };
+        inner.jobs.insert(id.clone(), job.clone());
+        if let Some((key, fingerprint)) = key {
+            let keyed = KeyedJob {
+                job_id: id.clone(),
+                fingerprint,
+                created: Instant::now(),
+                retained: None,
+            };
+            inner.keys.insert(key, keyed);
+        }
+        inner.queue.push_back(QueuedJob { id, resolved, timeout });
+        self.changed.notify_all();
+        Submission::Created(job)
+    }
+
+    /// What `submit` would answer for a key it has already seen, without queueing anything.
+    pub fn replay(&self, key: &(String, String), window: Duration) -> Option<Submission> {
+        self.inner.lock().unwrap().replay(key, window)
+    }
+
+    pub fn get(&self, id: &str) -> Option<Job> {
+        self.inner.lock().unwrap().lookup(id)
+    }
+
+    /// Cancels a pending job. A running command cannot be interrupted on the
+    /// device, so running and finished jobs are returned unchanged as the error.
+    pub fn cancel(&self, id: &str) -> Option<Result<Job, Job>> {
+        let mut inner = self.inner.lock().unwrap();
+        let job = inner.jobs.get(id)?.clone();
+        if job.status != JobStatus::Pending {
+            return Some(Err(job));
+        }
+        inner.queue.retain(|q| q.id != id);
+        let job = inner.finish(id, JobStatus::Cancelled, Completion::default());
+        self.changed.notify_all();
+        Some(Ok(job))
+    }
+
+    /// Blocks until the job has finished.
+    pub fn wait(&self, id: &str) -> Option<Job> {
+        let mut inner = self.inner.lock().unwrap();
+        loop {
+            match inner.lookup(id) {
+                Some(job) if job.status.is_finished() => return Some(job),
+                Some(_) => inner = self.changed.wait(inner).unwrap(),
+                None => return None,
+            }
+        }
+    }
+
+    fn next(&self) -> QueuedJob {
+        let mut inner = self.inner.lock().unwrap();
+        loop {
+            if let Some(queued) = inner.queue.pop_front() {
+                if let Some(job) = inner.jobs.get_mut(&queued.id) {
+                    job.status = JobStatus::Running;
+                    job.started_at = Some(unix_timestamp());
+                }
+                self.changed.notify_all();
+                return queued;
+            }
+            inner = self.changed.wait(inner).unwrap();
+        }
+    }
+
+    fn complete(&self, id: &str, status: JobStatus, completion: Completion) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.jobs.get(id).is_some_and(|j| !j.status.is_finished()) {
+            inner.finish(id, status, completion);
+            self.changed.notify_all();
+        }
+    }
+}
+
+impl Jobs {
+    fn replay(&mut self, (key, fingerprint): &(String, String), window: Duration) -> Option<Submission> {
+        self.keys.retain(|_, k| k.created.elapsed() < window);
+        let keyed = self.keys.get(key)?;
+        if &keyed.fingerprint != fingerprint {
+            return Some(Submission::Conflict);
+        }
+        self.lookup(&keyed.job_id).map(Submission::Replayed)
+    }
+
+    /// Finds a job in the history or, once evicted, under its idempotency key.
+    fn lookup(&self, id: &str) -> Option<Job> {
+        self.jobs
+            .get(id)
+            .or_else(|| self.keys.values().find_map(|k| k.retained.as_ref().filter(|j| j.id == id)))
+            .cloned()
+    }
+
+    fn finish(&mut self, id: &str, status: JobStatus, completion: Completion) -> Job {
+        let job = self.jobs.get_mut(id).expect("finishing a known job");
+        job.status = status;
+        job.finished_at = Some(unix_timestamp());
+        job.result = completion.result;
+        job.verification = completion.verification;
+        if let Some(failure) = completion.failure {
+            job.error = Some(failure.message);
+            job.rejected = failure.rejected;
+            job.violation = failure.violation;
+        }
+        let job = job.clone();
+        self.finished.push_back(id.to_string());
+        while self.finished.len() > self.history {
+            if let Some(old) = self.finished.pop_front().and_then(|id| self.jobs.remove(&id)) {
+                if let Some(keyed) = self.keys.values_mut().find(|k| k.job_id == old.id) {
+                    keyed.retained = Some(old);
+                }
+            }
+        }
+        job
+    }
+}
+
+// ========== Worker ==========
+pub fn row_object(headers: &[String], row: &Row) -> serde_json::Value {
+    serde_json::Value::Object(headers.iter().cloned().zip(row.iter().map(|c| c.clone().into())).collect())
+}
+
+/// Runs queued jobs against the backend. Each command executes on its own
+/// thread so the worker can report a timeout without waiting for the device.
+pub fn spawn_worker(state: web::Data<AppState>) {
+    thread::spawn(move || loop {
+        let queued = state.jobs.next();
+        let (tx, rx) = mpsc::channel();
+        let resolved = queued.resolved;
+        let exec_state = state.clone();
+        thread::spawn(move || {
+            let result = resolved.run(exec_state.backend.lock().unwrap().as_mut(), &exec_state);
+            let _ = tx.send(result);
+        });
+
+        match rx.recv_timeout(queued.timeout) {
+            Ok(Ok(execution)) => {
+                let mut csv_data = state.csv_data.lock().unwrap();
+                let completion = Completion {
+                    result: execution.row.as_ref().map(|r| row_object(&csv_data.headers, r)),
+                    verification: execution.verification,
+                    failure: None,
+                };
+                state.jobs.complete(&queued.id, JobStatus::Succeeded, completion);
+                if let Some(row) = execution.row {
+                    csv_data.push_row(row);
+                }
+            }
+            Ok(Err(e)) => {
+                state.jobs.complete(&queued.id, JobStatus::Failed, Failure::from(e).into());
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                state.jobs.complete(&queued.id, JobStatus::TimedOut, Failure::timed_out(queued.timeout).into());
+                // The command still holds the backend; let it finish before the next job
+                // starts so that one's timeout is not spent waiting for the lock
+                match rx.recv() {
+                    Ok(Ok(execution)) => {
+                        log::warn!("job {} completed after timing out", queued.id);
+                        // The reading is still the device's, even if the job reported no outcome
+                        if let Some(row) = execution.row {
+                            state.csv_data.lock().unwrap().push_row(row);
+                        }
+                    }
+                    Ok(Err(e)) => log::warn!("job {} failed after timing out: {}", queued.id, e),
+                    Err(_) => {}
+                }
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                let failure = Failure::from(BackendError::Protocol("command execution aborted".to_string()));
+                state.jobs.complete(&queued.id, JobStatus::Failed, failure.into());
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::CommandRegistry;
+    use crate::CommandRequest;
+
+    const WINDOW: Duration = Duration::from_secs(600);
+
+    fn resolved(command: &str) -> ResolvedCommand {
+        let request = CommandRequest { command: command.to_string(), params: None, request_id: None };
+        CommandRegistry::new(Vec::new()).unwrap().resolve(request).unwrap()
+    }
+
+    fn submit(store: &JobStore, command: &str, key: Option<(&str, &str)>) -> Submission {
+        let key = key.map(|(k, f)| (k.to_string(), f.to_string()));
+        store.submit(command, resolved(command), Duration::from_secs(1), key, WINDOW)
+    }
+
+    fn created(submission: Submission) -> Job {
+        match submission {
+            Submission::Created(job) => job,
+            _ => panic!("expected a new job"),
+        }
+    }
+
+    /// Runs the next queued job to success without a backend.
+    fn finish_next(store: &JobStore) {
+        let queued = store.next();
+        store.complete(&queued.id, JobStatus::Succeeded, Completion::default());
+    }
+
+    #[test]
+    fn replays_a_key_and_refuses_it_for_another_request() {
+        let store = JobStore::new(10);
+        let job = created(submit(&store, "reset", Some(("k1", "a"))));
+        assert!(matches!(submit(&store, "reset", Some(("k1", "a"))), Submission::Replayed(j) if j.id == job.id));
+        assert!(matches!(submit(&store, "reset", Some(("k1", "b"))), Submission::Conflict));
+        assert!(matches!(store.replay(&("k1".to_string(), "a".to_string()), WINDOW), Some(Submission::Replayed(_))));
+        assert!(store.replay(&("k2".to_string(), "a".to_string()), WINDOW).is_none());
+        let other = created(submit(&store, "reset", Some(("k2", "a"))));
+        assert_ne!(other.id, job.id);
+    }
+
+    #[test]
+    fn evicted_jobs_stay_reachable_through_their_key() {
+        let store = JobStore::new(1);
+        let keyed = created(submit(&store, "reset", Some(("k1", "a"))));
+        let plain = created(submit(&store, "reset", None));
+        let newest = created(submit(&store, "reset", None));
+        for _ in 0..3 {
+            finish_next(&store);
+        }
+        assert_eq!(store.get(&keyed.id).unwrap().status, JobStatus::Succeeded);
+        assert!(store.get(&plain.id).is_none());
+        assert!(store.get(&newest.id).is_some());
+        assert!(matches!(submit(&store, "reset", Some(("k1", "a"))), Submission::Replayed(j) if j.id == keyed.id));
+    }
+
+    #[test]
+    fn only_pending_jobs_can_be_cancelled() {
+        let store = JobStore::new(10);
+        let running = created(submit(&store, "reset", None));
+        let pending = created(submit(&store, "reset", None));
+        let started = store.next();
+        assert_eq!(started.id, running.id);
+
+        assert_eq!(store.cancel(&pending.id).unwrap().ok().unwrap().status, JobStatus::Cancelled);
+        assert_eq!(store.cancel(&running.id).unwrap().err().unwrap().status, JobStatus::Running);
+        assert!(store.cancel("missing").is_none());
+        // The cancelled job never reaches the worker
+        let next = created(submit(&store, "reset", None));
+        finish_next(&store);
+        store.complete(&running.id, JobStatus::Succeeded, Completion::default());
+        assert_eq!(store.get(&next.id).unwrap().status, JobStatus::Succeeded);
+    }
+
+    #[test]
+    fn timeout_reports_an_unknown_outcome() {
+        let state = AppState::for_test("commands: [{name: reset}]");
+        spawn_worker(state.clone());
+        // Holding the backend keeps the command from finishing in time
+        let backend = state.backend.lock().unwrap();
+        let submission = state.jobs.submit("reset", resolved("reset"), Duration::from_millis(50), None, WINDOW);
+        let job = state.jobs.wait(&created(submission).id).unwrap();
+        assert_eq!(job.status, JobStatus::TimedOut);
+        assert!(job.error.unwrap().contains("outcome unknown"));
+
+        // The late result does not overwrite what was reported
+        drop(backend);
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(state.jobs.get(&job.id).unwrap().status, JobStatus::TimedOut);
+        assert_eq!(state.csv_data.lock().unwrap().rows.len(), 1);
+    }
+}
@@ -0,0 +1,797 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serialport::{DataBits, Parity, SerialPort, StopBits};
+
+use crate::backend::{assemble_row, BackendError, DeviceBackend, Row};
+use crate::CommandRequest;
+
+const READ_HOLDING_REGISTERS: u8 = 0x03;
+const READ_INPUT_REGISTERS: u8 = 0x04;
+const WRITE_SINGLE_REGISTER: u8 = 0x06;
+const WRITE_MULTIPLE_REGISTERS: u8 = 0x10;
+
+// ========== Register Mapping ==========
+#[derive(Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum RegisterKind {
+    #[default]
+    Holding,
+    Input,
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum DataType {
+    #[default]
+    U16,
+    I16,
+    U32,
+    I32,
+    F32,
+    U64,
+    I64,
+    F64,
+}
+
+impl DataType {
+    fn words(self) -> u16 {
+        match self {
+            DataType::U16 | DataType::I16 => 1,
+            DataType::U32 | DataType::I32 | DataType::F32 => 2,
+            DataType::U64 | DataType::I64 | DataType::F64 => 4,
+        }
+    }
+
+    fn is_float(self) -> bool {
+        matches!(self, DataType::F32 | DataType::F64)
+    }
+}
+
+/// Order of the bytes on the wire, `ABCD` being plain big-endian.
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum ByteOrder {
+    #[default]
+    Abcd,
+    Badc,
+    Cdab,
+    Dcba,
+}
+
+impl ByteOrder {
+    fn swaps(self) -> (bool, bool) {
+        // (swap bytes within each word, reverse word order)
+        match self {
+            ByteOrder::Abcd => (false, false),
+            ByteOrder::Badc => (true, false),
+            ByteOrder::Cdab => (false, true),
+            ByteOrder::Dcba => (true, true),
+        }
+    }
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+#[derive(Deserialize, Clone)]
+pub struct RegisterColumn {
+    pub column: String,
+    pub address: u16,
+    #[serde(default)]
+    pub kind: RegisterKind,
+    #[serde(default)]
+    pub data_type: DataType,
+    #[serde(default)]
+    pub byte_order: ByteOrder,
+    #[serde(default = "default_scale")]
+    pub scale: f64,
+    #[serde(default)]
+    pub offset: f64,
+    pub precision: Option<usize>,
+}
+
+impl RegisterColumn {
+    /// Reorders the registers read from the device into big-endian bytes.
+    fn wire_to_be(&self, words: &[u16]) -> Vec<u8> {
+        let (swap_bytes, swap_words) = self.byte_order.swaps();
+        let mut ordered: Vec<u16> = words.to_vec();
+        if swap_words {
+            ordered.reverse();
+        }
+        ordered
+            .iter()
+            .flat_map(|w| if swap_bytes { w.to_le_bytes() } else { w.to_be_bytes() })
+            .collect()
+    }
+
+    /// Inverse of `wire_to_be`: splits big-endian bytes into wire-order registers.
+    fn be_to_wire(&self, bytes: &[u8]) -> Vec<u16> {
+        let (swap_bytes, swap_words) = self.byte_order.swaps();
+        let mut words: Vec<u16> = bytes
+            .chunks(2)
+            .map(|b| if swap_bytes { u16::from_le_bytes([b[0], b[1]]) } else { u16::from_be_bytes([b[0], b[1]]) })
+            .collect();
+        if swap_words {
+            words.reverse();
+        }
+        words
+    }
+
+    fn decode(&self, words: &[u16]) -> f64 {
+        let b = self.wire_to_be(words);
+        match self.data_type {
+            DataType::U16 => u16::from_be_bytes([b[0], b[1]]) as f64,
+            DataType::I16 => i16::from_be_bytes([b[0], b[1]]) as f64,
+            DataType::U32 => u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            DataType::I32 => i32::from_be_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            DataType::F32 => f32::from_be_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            DataType::U64 => u64::from_be_bytes(b[..8].try_into().unwrap()) as f64,
+            DataType::I64 => i64::from_be_bytes(b[..8].try_into().unwrap()) as f64,
+            DataType::F64 => f64::from_be_bytes(b[..8].try_into().unwrap()),
+        }
+    }
+
+    fn encode(&self, raw: f64) -> Result<Vec<u16>, BackendError> {
+        let out_of_range = || BackendError::InvalidParams(format!("value out of range for column {}", self.column));
+        let int = raw.round();
+        let bytes = match self.data_type {
+            DataType::U16 if (0.0..=u16::MAX as f64).contains(&int) => (int as u16).to_be_bytes().to_vec(),
+            DataType::I16 if (i16::MIN as f64..=i16::MAX as f64).contains(&int) => (int as i16).to_be_bytes().to_vec(),
+            DataType::U32 if (0.0..=u32::MAX as f64).contains(&int) => (int as u32).to_be_bytes().to_vec(),
+            DataType::I32 if (i32::MIN as f64..=i32::MAX as f64).contains(&int) => (int as i32).to_be_bytes().to_vec(),
+            DataType::U64 if int >= 0.0 => (int as u64).to_be_bytes().to_vec(),
+            DataType::I64 => (int as i64).to_be_bytes().to_vec(),
+            DataType::F32 => (raw as f32).to_be_bytes().to_vec(),
+            DataType::F64 => raw.to_be_bytes().to_vec(),
+            _ => return Err(out_of_range()),
+        };
+        Ok(self.be_to_wire(&bytes))
+    }
+
+    fn format(&self, value: f64) -> String {
+        match self.precision {
+            Some(p) => format!("{:.*}", p, value),
+            None if self.data_type.is_float() || self.scale != 1.0 || self.offset != 0.0 => format!("{:.2}", value),
+            None => format!("{}", value),
+        }
+    }
+}
+
+// ========== Command Mapping ==========
+/// Maps a `/cmd` command onto a write of a holding-register column.
+#[derive(Deserialize, Clone)]
+pub struct RegisterCommand {
+    pub name: String,
+    pub column: String,
+    /// Key in `CommandRequest.params` holding the engineering value to write.
+    pub param: Option<String>,
+    /// Fixed engineering value, used when `param` is not set.
+    pub value: Option<f64>,
+}
+
+// ========== Transport ==========
+/// Carries a Modbus PDU to a unit and returns the response PDU.
+pub trait ModbusTransport: Send {
+    fn connect(&mut self) -> Result<(), BackendError>;
+    fn transact(&mut self, unit_id: u8, pdu: &[u8]) -> Result<Vec<u8>, BackendError>;
+    fn disconnect(&mut self);
+}
+
+fn exception_message(code: u8) -> &'static str {
+    match code {
+        0x01 => "illegal function",
+        0x02 => "illegal data address",
+        0x03 => "illegal data value",
+        0x04 => "server device failure",
+        0x05 => "acknowledge",
+        0x06 => "server device busy",
+        0x0A => "gateway path unavailable",
+        0x0B => "gateway target device failed to respond",
+        _ => "unknown exception",
+    }
+}
+
+/// Checks a response PDU against the request's function code.
+fn check_response(function: u8, pdu: &[u8]) -> Result<(), BackendError> {
+    match pdu.first() {
+        Some(&f) if f == function => Ok(()),
+        Some(&f) if f == function | 0x80 => {
+            let code = pdu.get(1).copied().unwrap_or(0);
+            Err(BackendError::Protocol(format!("modbus exception {:#04x}: {}", code, exception_message(code))))
+        }
+        _ => Err(BackendError::Protocol("unexpected modbus function code in response".to_string())),
+    }
+}
+
+// ========== Modbus TCP Transport ==========
+fn default_unit_id() -> u8 {
+    1
+}
+
+fn default_timeout_ms() -> u64 {
+    1000
+}
+
+#[derive(Deserialize, Clone)]
+pub struct ModbusTcpConfig {
+    pub address: String,
+    #[serde(default = "default_unit_id")]
+    pub unit_id: u8,
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+    pub registers: Vec<RegisterColumn>,
+    #[serde(default)]
+    pub commands: Vec<RegisterCommand>,
+}
+
+pub struct TcpTransport {
+    address: String,
+    timeout: Duration,
+    stream: Option<TcpStream>,
+    transaction_id: u16,
+}
+
+impl TcpTransport {
+    pub fn new(address: String, timeout: Duration) -> Self {
+        TcpTransport {
+            address,
+            timeout,
+            stream: None,
+            transaction_id: 0,
+        }
+    }
+}
+
+impl ModbusTransport for TcpTransport {
+    fn connect(&mut self) -> Result<(), BackendError> {
+        let addr = self
+            .address
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| BackendError::Protocol(format!("cannot resolve {}", self.address)))?;
+        let stream = TcpStream::connect_timeout(&addr, self.timeout)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        stream.set_nodelay(true)?;
+        self.stream = Some(stream);
+        Ok(())
+    }
+
+    fn transact(&mut self, unit_id: u8, pdu: &[u8]) -> Result<Vec<u8>, BackendError> {
+        let stream = self.stream.as_mut().ok_or(BackendError::NotConnected)?;
+        self.transaction_id = self.transaction_id.wrapping_add(1);
+
+        // MBAP header: transaction id, protocol id (0), length, unit id
+        let mut frame = Vec::with_capacity(7 + pdu.len());
+        frame.extend_from_slice(&self.transaction_id.to_be_bytes());
+        frame.extend_from_slice(&0u16.to_be_bytes());
+        frame.extend_from_slice(&(pdu.len() as u16 + 1).to_be_bytes());
+        frame.push(unit_id);
+        frame.extend_from_slice(pdu);
+        stream.write_all(&frame)?;
+
+        loop {
+            let mut header = [0u8; 7];
+            stream.read_exact(&mut header)?;
+            let length = u16::from_be_bytes([header[4], header[5]]) as usize;
+            if length < 2 {
+                return Err(BackendError::Protocol("invalid MBAP length".to_string()));
+            }
+            let mut body = vec![0u8; length - 1];
+            stream.read_exact(&mut body)?;
+            // Skip stale responses left over from a previously timed-out request
+            if u16::from_be_bytes([header[0], header[1]]) == self.transaction_id {
+                return Ok(body);
+            }
+        }
+    }
+
+    fn disconnect(&mut self) {
+        self.stream = None;
+    }
+}
+
+// ========== Modbus RTU Transport ==========
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SerialParity {
+    #[default]
+    None,
+    Even,
+    Odd,
+}
+
+fn default_baud_rate() -> u32 {
+    9600
+}
+
+fn default_data_bits() -> u8 {
+    8
+}
+
+fn default_stop_bits() -> u8 {
+    1
+}
+
+fn default_check_crc() -> bool {
+    true
+}
+
+#[derive(Deserialize, Clone)]
+pub struct ModbusRtuConfig {
+    pub port: String,
+    #[serde(default = "default_baud_rate")]
+    pub baud_rate: u32,
+    #[serde(default = "default_data_bits")]
+    pub data_bits: u8,
+    #[serde(default)]
+    pub parity: SerialParity,
+    #[serde(default = "default_stop_bits")]
+    pub stop_bits: u8,
+    #[serde(default = "default_unit_id")]
+    pub unit_id: u8,
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+    /// Silent interval enforced between frames; defaults to 3.5 character times.
+    pub inter_frame_delay_us: Option<u64>,
+    #[serde(default = "default_check_crc")]
+    pub check_crc: bool,
+    pub registers: Vec<RegisterColumn>,
+    #[serde(default)]
+    pub commands: Vec<RegisterCommand>,
+}
+
+/// CRC-16/MODBUS (polynomial 0xA001 reflected, initial value 0xFFFF).
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xFFFFu16;
+    for &byte in data {
+        crc ^= byte as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xA001 } else { crc >> 1 };
+        }
+    }
+    crc
+}
+
+pub struct RtuTransport {
+    config: ModbusRtuConfig,
+    inter_frame_delay: Duration,
+    port: Option<Box<dyn SerialPort>>,
+    last_frame: Instant,
+}
+
+impl RtuTransport {
+    pub fn new(config: ModbusRtuConfig) -> Result<Self, String> {
+        if config.baud_rate == 0 {
+            return Err("modbus rtu baud_rate must be positive".to_string());
+        }
+        if !(5..=8).contains(&config.data_bits) {
+            return Err(format!("modbus rtu data_bits must be 5-8, got {}", config.data_bits));
+        }
+        if !(1..=2).contains(&config.stop_bits) {
+            return Err(format!("modbus rtu stop_bits must be 1 or 2, got {}", config.stop_bits));
+        }
+        let inter_frame_delay = match config.inter_frame_delay_us {
+            Some(us) => Duration::from_micros(us),
+            // Spec fixes t3.5 at 1750us above 19200 baud; below that, 3.5 chars of 11 bits
+            None if config.baud_rate > 19200 => Duration::from_micros(1750),
+            None => Duration::from_micros(38_500_000 / config.baud_rate as u64),
+        };
+        Ok(RtuTransport {
+            config,
+            inter_frame_delay,
+            port: None,
+            last_frame: Instant::now(),
+        })
+    }
+
+    fn read_exact(port: &mut Box<dyn SerialPort>, buf: &mut Vec<u8>, len: usize) -> Result<(), BackendError> {
+        let start = buf.len();
+        buf.resize(start + len, 0);
+        port.read_exact(&mut buf[start..])?;
+        Ok(())
+    }
+}
+
+impl ModbusTransport for RtuTransport {
+    fn connect(&mut self) -> Result<(), BackendError> {
+        let data_bits = match self.config.data_bits {
+            5 => DataBits::Five,
+            6 => DataBits::Six,
+            7 => DataBits::Seven,
+            _ => DataBits::Eight,
+        };
+        let parity = match self.config.parity {
+            SerialParity::None => Parity::None,
+            SerialParity::Even => Parity::Even,
+            SerialParity::Odd => Parity::Odd,
+        };
+        let stop_bits = if self.config.stop_bits == 2 { StopBits::Two } else { StopBits::One };
+        let port = serialport::new(&self.config.port, self.config.baud_rate)
+            .data_bits(data_bits)
+            .parity(parity)
+            .stop_bits(stop_bits)
+            .timeout(Duration::from_millis(self.config.timeout_ms))
+            .open()
+            .map_err(|e| BackendError::Protocol(format!("cannot open {}: {}", self.config.port, e)))?;
+        self.port = Some(port);
+        self.last_frame = Instant::now();
+        Ok(())
+    }
+
+    fn transact(&mut self, unit_id: u8, pdu: &[u8]) -> Result<Vec<u8>, BackendError> {
+        let port = self.port.as_mut().ok_or(BackendError::NotConnected)?;
+
+        let idle = self.last_frame.elapsed();
+        if idle < self.inter_frame_delay {
+            thread::sleep(self.inter_frame_delay - idle);
+        }
+        // Drop any late bytes from an earlier exchange so they are not read as this response
+        let _ = port.clear(serialport::ClearBuffer::Input);
+
+        let mut frame = Vec::with_capacity(pdu.len() + 3);
+        frame.push(unit_id);
+        frame.extend_from_slice(pdu);
+        frame.extend_from_slice(&crc16(&frame).to_le_bytes());
+        port.write_all(&frame)?;
+        port.flush()?;
+
+        // The response length follows from the function code, so no silence detection is needed
+        let mut response = Vec::new();
+        let result = (|| {
+            Self::read_exact(port, &mut response, 2)?;
+            let function = response[1];
+            if function & 0x80 != 0 {
+                Self::read_exact(port, &mut response, 1)?;
+            } else {
+                match function {
+                    READ_HOLDING_REGISTERS | READ_INPUT_REGISTERS => {
+                        Self::read_exact(port, &mut response, 1)?;
+                        let byte_count = response[2] as usize;
+                        Self::read_exact(port, &mut response, byte_count)?;
+                    }
+                    WRITE_SINGLE_REGISTER | WRITE_MULTIPLE_REGISTERS => Self::read_exact(port, &mut response, 4)?,
+                    other => return Err(BackendError::Protocol(format!("unsupported function {:#04x} in response", other))),
+                }
+            }
+            Self::read_exact(port, &mut response, 2)
+        })();
+        self.last_frame = Instant::now();
+        result?;
+
+        let (body, crc) = response.split_at(response.len() - 2);
+        if self.config.check_crc && crc16(body).to_le_bytes() != crc {
+            return Err(BackendError::Protocol("modbus rtu CRC mismatch".to_string()));
+        }
+        if body[0] != unit_id {
+            return Err(BackendError::Protocol(format!("response from unexpected slave id {}", body[0])));
+        }
+        Ok(body[1..].to_vec())
+    }
+
+    fn disconnect(&mut self) {
+        self.port = None;
+    }
+}
+
+// ========== Modbus Backend ==========
+pub struct ModbusBackend<T: ModbusTransport> {
+    transport: T,
+    unit_id: u8,
+    headers: Vec<String>,
+    registers: Vec<RegisterColumn>,
+    commands: Vec<RegisterCommand>,
+}
+
+impl ModbusBackend<TcpTransport> {
+    pub fn tcp(config: ModbusTcpConfig, headers: &[String]) -> Result<Self, String> {
+        let transport = TcpTransport::new(config.address, Duration::from_millis(config.timeout_ms));
+        ModbusBackend::new(transport, config.unit_id, headers, config.registers, config.commands)
+    }
+}
+
+impl ModbusBackend<RtuTransport> {
+    pub fn rtu(config: ModbusRtuConfig, headers: &[String]) -> Result<Self, String> {
+        let unit_id = config.unit_id;
+        let registers = config.registers.clone();
+        let commands = config.commands.clone();
+        ModbusBackend::new(RtuTransport::new(config)?, unit_id, headers, registers, commands)
+    }
+}
+
+impl<T: ModbusTransport> ModbusBackend<T> {
+    pub fn new(
+        transport: T,
+        unit_id: u8,
+        headers: &[String],
+        registers: Vec<RegisterColumn>,
+        commands: Vec<RegisterCommand>,
+    ) -> Result<Self, String> {
+        for register in &registers {
+            if !headers.contains(&register.column) {
+                return Err(format!("modbus register column '{}' is not a CSV header", register.column));
+            }
+            // Writes divide by the scale to recover the raw register value
+            if register.scale == 0.0 {
+                return Err(format!("modbus register column '{}' must have a non-zero scale", register.column));
+            }
+        }
+        for command in &commands {
+            match registers.iter().find(|r| r.column == command.column) {
+                Some(r) if r.kind == RegisterKind::Holding => {}
+                Some(_) => return Err(format!("modbus command '{}' targets a read-only input register", command.name)),
+                None => return Err(format!("modbus command '{}' targets unmapped column '{}'", command.name, command.column)),
+            }
+            if command.param.is_none() && command.value.is_none() {
+                return Err(format!("modbus command '{}' needs either 'param' or 'value'", command.name));
+            }
+        }
+        Ok(ModbusBackend {
+            transport,
+            unit_id,
+            headers: headers.to_vec(),
+            registers,
+            commands,
+        })
+    }
+
+    fn read_registers(&mut self, register: &RegisterColumn) -> Result<Vec<u16>, BackendError> {
+        let function = match register.kind {
+            RegisterKind::Holding => READ_HOLDING_REGISTERS,
+            RegisterKind::Input => READ_INPUT_REGISTERS,
+        };
+        let count = register.data_type.words();
+        let mut pdu = vec![function];
+        pdu.extend_from_slice(&register.address.to_be_bytes());
+        pdu.extend_from_slice(&count.to_be_bytes());
+
+        let response = self.transport.transact(self.unit_id, &pdu)?;
+        check_response(function, &response)?;
+        let byte_count = *response.get(1).unwrap_or(&0) as usize;
+        if byte_count != count as usize * 2 || response.len() < 2 + byte_count {
+            return Err(BackendError::Protocol("short modbus read response".to_string()));
+        }
+        Ok(response[2..2 + byte_count]
+            .chunks(2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]))
+            .collect())
+    }
+
+    fn write_registers(&mut self, address: u16, words: &[u16]) -> Result<(), BackendError> {
+        let (function, pdu) = if words.len() == 1 {
+            let mut pdu = vec![WRITE_SINGLE_REGISTER];
+            pdu.extend_from_slice(&address.to_be_bytes());
+            pdu.extend_from_slice(&words[0].to_be_bytes());
+            (WRITE_SINGLE_REGISTER, pdu)
+        } else {
+            let mut pdu = vec![WRITE_MULTIPLE_REGISTERS];
+            pdu.extend_from_slice(&address.to_be_bytes());
+            pdu.extend_from_slice(&(words.len() as u16).to_be_bytes());
+            pdu.push((words.len() * 2) as u8);
+            for w in words {
+                pdu.extend_from_slice(&w.to_be_bytes());
+            }
+            (WRITE_MULTIPLE_REGISTERS, pdu)
+        };
+        let response = self.transport.transact(self.unit_id, &pdu)?;
+        check_response(function, &response)
+    }
+}
+
+impl<T: ModbusTransport> DeviceBackend for ModbusBackend<T> {
+    fn connect(&mut self) -> Result<(), BackendError> {
+        self.transport.connect()
+    }
+
+    fn poll(&mut self) -> Result<Vec<Row>, BackendError> {
+        let mut values = HashMap::new();
+        for register in self.registers.clone() {
+            let words = self.read_registers(&register)?;
+            let value = register.decode(&words) * register.scale + register.offset;
+            values.insert(register.column.clone(), register.format(value));
+        }
+        Ok(vec![assemble_row(&self.headers, &values)])
+    }
+
+    fn execute(&mut self, command: &CommandRequest) -> Result<Option<Row>, BackendError> {
+        let mapping = self
+            .commands
+            .iter()
+            .find(|c| c.name == command.command)
+            .cloned()
+            .ok_or_else(|| BackendError::UnknownCommand(command.command.clone()))?;
+        let value = match &mapping.param {
+            Some(param) => command
+                .params
+                .as_ref()
+                .and_then(|p| p.get(param))
+                .and_then(|v| v.as_f64())
+                .ok_or_else(|| BackendError::InvalidParams(format!("Missing {} parameter", param)))?,
+            None => mapping.value.unwrap_or_default(),
+        };
+        let register = self.registers.iter().find(|r| r.column == mapping.column).cloned().unwrap();
+        let words = register.encode((value - register.offset) / register.scale)?;
+        self.write_registers(register.address, &words)?;
+        Ok(None)
+    }
+
+    fn disconnect(&mut self) {
+        self.transport.disconnect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serialport::TTYPort;
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    /// Answers a request PDU from a register bank, like a minimal Modbus slave.
+    fn respond(pdu: &[u8], memory: &mut [u16]) -> Vec<u8> {
+        let address = u16::from_be_bytes([pdu[1], pdu[2]]) as usize;
+        let arg = u16::from_be_bytes([pdu[3], pdu[4]]);
+        match pdu[0] {
+            READ_HOLDING_REGISTERS | READ_INPUT_REGISTERS if address + arg as usize <= memory.len() => {
+                let mut reply = vec![pdu[0], (arg * 2) as u8];
+                for word in &memory[address..address + arg as usize] {
+                    reply.extend_from_slice(&word.to_be_bytes());
+                }
+                reply
+            }
+            WRITE_SINGLE_REGISTER if address < memory.len() => {
+                memory[address] = arg;
+                pdu.to_vec()
+            }
+            WRITE_MULTIPLE_REGISTERS if address + arg as usize <= memory.len() => {
+                for (i, word) in pdu[6..].chunks(2).enumerate() {
+                    memory[address + i] = u16::from_be_bytes([word[0], word[1]]);
+                }
+                pdu[..5].to_vec()
+            }
+            function => vec![function | 0x80, 0x02],
+        }
+    }
+
+    fn serve_tcp(memory: Arc<Mutex<Vec<u16>>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut header = [0u8; 7];
+                while stream.read_exact(&mut header).is_ok() {
+                    let mut pdu = vec![0u8; u16::from_be_bytes([header[4], header[5]]) as usize - 1];
+                    stream.read_exact(&mut pdu).unwrap();
+                    let reply = respond(&pdu, &mut memory.lock().unwrap());
+                    let mut frame = header[..4].to_vec();
+                    frame.extend_from_slice(&(reply.len() as u16 + 1).to_be_bytes());
+                    frame.push(header[6]);
+                    frame.extend_from_slice(&reply);
+                    stream.write_all(&frame).unwrap();
+                }
+            }
+        });
+        address
+    }
+
+    /// Plays the slave on the master side of a pseudo-terminal, checking and appending CRCs.
+    fn serve_rtu(mut master: TTYPort, unit_id: u8, memory: Arc<Mutex<Vec<u16>>>) {
+        master.set_timeout(Duration::from_secs(10)).unwrap();
+        thread::spawn(move || loop {
+            let mut frame = vec![0u8; 6];
+            if master.read_exact(&mut frame).is_err() {
+                return;
+            }
+            let tail = if frame[1] == WRITE_MULTIPLE_REGISTERS { 1 + frame[5] as usize * 2 + 2 } else { 2 };
+            let start = frame.len();
+            frame.resize(start + tail, 0);
+            master.read_exact(&mut frame[start..]).unwrap();
+            let (body, crc) = frame.split_at(frame.len() - 2);
+            assert_eq!(crc16(body).to_le_bytes(), crc);
+            if body[0] != unit_id {
+                continue;
+            }
+            let mut reply = vec![unit_id];
+            reply.extend(respond(&body[1..], &mut memory.lock().unwrap()));
+            reply.extend_from_slice(&crc16(&reply).to_le_bytes());
+            master.write_all(&reply).unwrap();
+        });
+    }
+
+    fn registers() -> serde_json::Value {
+        serde_json::json!([
+            {"column": "temperature", "address": 0, "data_type": "f32", "byte_order": "CDAB"},
+            {"column": "setpoint", "address": 2, "scale": 0.1, "precision": 1},
+        ])
+    }
+
+    fn headers() -> Vec<String> {
+        vec!["temperature".to_string(), "setpoint".to_string()]
+    }
+
+    fn set_setpoint(value: f64) -> CommandRequest {
+        CommandRequest {
+            command: "set_setpoint".to_string(),
+            params: Some(serde_json::json!({"value": value})),
+            request_id: None,
+        }
+    }
+
+    #[test]
+    fn tcp_polls_and_writes_registers() {
+        // 21.5f32 is 0x41AC0000, stored word-swapped for CDAB
+        let memory = Arc::new(Mutex::new(vec![0x0000, 0x41AC, 205, 0]));
+        let config: ModbusTcpConfig = serde_json::from_value(serde_json::json!({
+            "address": serve_tcp(memory.clone()),
+            "registers": registers(),
+            "commands": [{"name": "set_setpoint", "column": "setpoint", "param": "value"}],
+        }))
+        .unwrap();
+        let mut backend = ModbusBackend::tcp(config, &headers()).unwrap();
+
+        backend.connect().unwrap();
+        assert_eq!(backend.poll().unwrap(), vec![vec!["21.50".to_string(), "20.5".to_string()]]);
+        backend.execute(&set_setpoint(22.3)).unwrap();
+        assert_eq!(memory.lock().unwrap()[2], 223);
+        assert_eq!(backend.poll().unwrap()[0][1], "22.3");
+    }
+
+    #[test]
+    fn tcp_reports_exceptions() {
+        let memory = Arc::new(Mutex::new(vec![0; 2]));
+        let config: ModbusTcpConfig = serde_json::from_value(serde_json::json!({
+            "address": serve_tcp(memory),
+            "registers": registers(),
+        }))
+        .unwrap();
+        let mut backend = ModbusBackend::tcp(config, &headers()).unwrap();
+
+        backend.connect().unwrap();
+        let err = backend.poll().unwrap_err().to_string();
+        assert!(err.contains("illegal data address"), "{}", err);
+    }
+
+    #[test]
+    fn crc_matches_reference_frame() {
+        assert_eq!(crc16(&[0x01, 0x03, 0x00, 0x00, 0x00, 0x0A]).to_le_bytes(), [0xC5, 0xCD]);
+    }
+
+    #[test]
+    fn rtu_polls_and_writes_over_pty() {
+        let (master, slave) = TTYPort::pair().unwrap();
+        let memory = Arc::new(Mutex::new(vec![0x0000, 0x41AC, 205, 0]));
+        serve_rtu(master, 7, memory.clone());
+        let config: ModbusRtuConfig = serde_json::from_value(serde_json::json!({
+            "port": slave.name().unwrap(),
+            "baud_rate": 115200,
+            "unit_id": 7,
+            "registers": registers(),
+            "commands": [{"name": "set_setpoint", "column": "setpoint", "param": "value"}],
+        }))
+        .unwrap();
+        let mut backend = ModbusBackend::rtu(config, &headers()).unwrap();
+
+        backend.connect().unwrap();
+        assert_eq!(backend.poll().unwrap(), vec![vec!["21.50".to_string(), "20.5".to_string()]]);
+        backend.execute(&set_setpoint(19.0)).unwrap();
+        assert_eq!(memory.lock().unwrap()[2], 190);
+        assert_eq!(backend.poll().unwrap()[0][1], "19.0");
+    }
+
+    #[test]
+    fn rejects_zero_scale() {
+        let config: ModbusTcpConfig = serde_json::from_value(serde_json::json!({
+            "address": "127.0.0.1:502",
+            "registers": [{"column": "setpoint", "address": 0, "scale": 0.0}],
+        }))
+        .unwrap();
+        assert!(ModbusBackend::tcp(config, &headers()).is_err());
+    }
+}
@@ -0,0 +1,332 @@
+use rumqttc::{Client, Event, MqttOptions, Packet, QoS, RecvTimeoutError};
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::backend::{decode_payload, BackendError, DeviceBackend, PayloadFormat, Row};
+use crate::CommandRequest;
+
+// Rows held between polls; the oldest are dropped once a chatty topic outpaces the poller
+const MAX_QUEUED_ROWS: usize = 1024;
+
+// ========== MQTT Config ==========
+fn default_port() -> u16 {
+    1883
+}
+
+fn default_client_id() -> String {
+    "iot-driver".to_string()
+}
+
+fn default_qos() -> u8 {
+    1
+}
+
+fn default_clean_session() -> bool {
+    true
+}
+
+fn default_keep_alive_secs() -> u64 {
+    30
+}
+
+fn default_reconnect_initial_ms() -> u64 {
+    500
+}
+
+fn default_reconnect_max_ms() -> u64 {
+    30_000
+}
+
+#[derive(Deserialize, Clone)]
+pub struct MqttConfig {
+    pub host: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    #[serde(default = "default_client_id")]
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub topic: String,
+    #[serde(default = "default_qos")]
+    pub qos: u8,
+    #[serde(default = "default_clean_session")]
+    pub clean_session: bool,
+    #[serde(default = "default_keep_alive_secs")]
+    pub keep_alive_secs: u64,
+    #[serde(default = "default_reconnect_initial_ms")]
+    pub reconnect_initial_ms: u64,
+    #[serde(default = "default_reconnect_max_ms")]
+    pub reconnect_max_ms: u64,
+    #[serde(default)]
+    pub payload_format: PayloadFormat,
+    /// Topic that `/cmd` requests are published to as JSON; commands are rejected when unset.
+    pub command_topic: Option<String>,
+}
+
+fn to_qos(level: u8) -> Result<QoS, String> {
+    match level {
+        0 => Ok(QoS::AtMostOnce),
+        1 => Ok(QoS::AtLeastOnce),
+        2 => Ok(QoS::ExactlyOnce),
+        other => Err(format!("invalid MQTT QoS {}", other)),
+    }
+}
+
+// ========== MQTT Backend ==========
+/// Subscribes to a topic and queues each message as a row until the next poll.
+pub struct MqttBackend {
+    config: MqttConfig,
+    qos: QoS,
+    headers: Vec<String>,
+    client: Option<Client>,
+    stop: Arc<AtomicBool>,
+    worker: Option<JoinHandle<()>>,
+    rows: Arc<Mutex<VecDeque<Row>>>,
+    dropped_rows: Arc<AtomicU64>,
+}
+
+/// Appends decoded rows, evicting the oldest beyond `MAX_QUEUED_ROWS`; returns how many were evicted.
+fn enqueue(rows: &mut VecDeque<Row>, decoded: Vec<Row>) -> u64 {
+    rows.extend(decoded);
+    let excess = rows.len().saturating_sub(MAX_QUEUED_ROWS);
+    rows.drain(..excess);
+    excess as u64
+}
+
+impl MqttBackend {
+    pub fn new(config: MqttConfig, headers: &[String]) -> Result<Self, String> {
+        let qos = to_qos(config.qos)?;
+        if !config.clean_session && config.client_id.is_empty() {
+            return Err("MQTT clean_session=false requires a client_id".to_string());
+        }
+        Ok(MqttBackend {
+            config,
+            qos,
+            headers: headers.to_vec(),
+            client: None,
+            stop: Arc::new(AtomicBool::new(false)),
+            worker: None,
+            rows: Arc::new(Mutex::new(VecDeque::new())),
+            dropped_rows: Arc::new(AtomicU64::new(0)),
+        })
+    }
+}
+
+impl DeviceBackend for MqttBackend {
+    fn connect(&mut self) -> Result<(), BackendError> {
+        let mut options = MqttOptions::new(&self.config.client_id, &self.config.host, self.config.port);
+        options
+            .set_keep_alive(Duration::from_secs(self.config.keep_alive_secs))
+            .set_clean_session(self.config.clean_session);
+        if let (Some(user), Some(pass)) = (&self.config.username, &self.config.password) {
+            options.set_credentials(user, pass);
+        }
+        let (client, mut connection) = Client::new(options, 16);
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let worker_stop = stop.clone();
+        let worker_client = client.clone();
+        let rows = self.rows.clone();
+        let dropped_rows = self.dropped_rows.clone();
+        let headers = self.headers.clone();
+        let topic = self.config.topic.clone();
+        let qos = self.qos;
+        let format = self.config.payload_format;
+        let initial_backoff = Duration::from_millis(self.config.reconnect_initial_ms);
+        let max_backoff = Duration::from_millis(self.config.reconnect_max_ms);
+
+        // rumqttc reconnects on the next recv after an error; we only pace the retries
+        let worker = thread::spawn(move || {
+            let mut backoff = initial_backoff;
+            while !worker_stop.load(Ordering::Relaxed) {
+                match connection.recv_timeout(Duration::from_millis(500)) {
+                    Ok(Ok(Event::Incoming(Packet::ConnAck(_)))) => {
+                        backoff = initial_backoff;
+                        if let Err(e) = worker_client.try_subscribe(topic.as_str(), qos) {
+                            log::warn!("mqtt subscribe to {} failed: {}", topic, e);
+                        }
+                    }
+                    Ok(Ok(Event::Incoming(Packet::Publish(publish)))) => {
+                        match decode_payload(&headers, &publish.payload, format) {
+                            Ok(decoded) => {
+                                let dropped = enqueue(&mut rows.lock().unwrap(), decoded);
+                                if dropped > 0 {
+                                    let total = dropped_rows.fetch_add(dropped, Ordering::Relaxed) + dropped;
+                                    log::warn!("mqtt row queue full, dropped {} oldest rows ({} so far)", dropped, total);
+                                }
+                            }
+                            Err(e) => log::warn!("dropping malformed mqtt payload on {}: {}", publish.topic, e),
+                        }
+                    }
+                    Ok(Ok(_)) | Err(RecvTimeoutError::Timeout) => {}
+                    Ok(Err(e)) => {
+                        log::warn!("mqtt connection error, retrying in {:?}: {}", backoff, e);
+                        thread::sleep(backoff);
+                        backoff = (backoff * 2).min(max_backoff);
+                    }
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+        });
+
+        self.client = Some(client);
+        self.stop = stop;
+        self.worker = Some(worker);
+        Ok(())
+    }
+
+    fn poll(&mut self) -> Result<Vec<Row>, BackendError> {
+        match &self.worker {
+            Some(worker) if !worker.is_finished() => Ok(self.rows.lock().unwrap().drain(..).collect()),
+            _ => Err(BackendError::NotConnected),
+        }
+    }
+
+    fn execute(&mut self, command: &CommandRequest) -> Result<Option<Row>, BackendError> {
+        let topic = self
+            .config
+            .command_topic
+            .as_ref()
+            .ok_or_else(|| BackendError::UnknownCommand(command.command.clone()))?;
+        let client = self.client.as_ref().ok_or(BackendError::NotConnected)?;
+        let payload = serde_json::json!({"command": command.command, "params": command.params});
+        client
+            .publish(topic.as_str(), self.qos, false, payload.to_string())
+            .map_err(|e| BackendError::Protocol(format!("mqtt publish failed: {}", e)))?;
+        Ok(None)
+    }
+
+    fn disconnect(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(client) = self.client.take() {
+            let _ = client.try_disconnect();
+        }
+        // The worker notices the stop flag within one recv timeout; no need to wait on it here
+        self.worker = None;
+    }
+
+    fn stats(&self) -> serde_json::Value {
+        serde_json::json!({
+            "queued_rows": self.rows.lock().unwrap().len(),
+            "dropped_rows": self.dropped_rows.load(Ordering::Relaxed),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::mpsc::{self, Receiver};
+    use std::time::Instant;
+
+    fn read_packet(stream: &mut TcpStream) -> Option<(u8, Vec<u8>)> {
+        let mut byte = [0u8; 1];
+        stream.read_exact(&mut byte).ok()?;
+        let kind = byte[0];
+        let (mut length, mut shift) = (0usize, 0);
+        loop {
+            stream.read_exact(&mut byte).ok()?;
+            length |= ((byte[0] & 0x7F) as usize) << shift;
+            shift += 7;
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut body = vec![0u8; length];
+        stream.read_exact(&mut body).ok()?;
+        Some((kind, body))
+    }
+
+    fn publish_packet(topic: &str, payload: &[u8]) -> Vec<u8> {
+        let length = 2 + topic.len() + payload.len();
+        let mut packet = vec![0x30, length as u8];
+        packet.extend_from_slice(&(topic.len() as u16).to_be_bytes());
+        packet.extend_from_slice(topic.as_bytes());
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    /// A single-client broker: acks the session, publishes `payload` once the
+    /// subscription arrives and forwards every client PUBLISH body to the test.
+    fn serve_broker(topic: &'static str, payload: &'static [u8]) -> (u16, Receiver<Vec<u8>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (published, received) = mpsc::channel();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            while let Some((kind, body)) = read_packet(&mut stream) {
+                match kind >> 4 {
+                    1 => stream.write_all(&[0x20, 0x02, 0x00, 0x00]).unwrap(),
+                    8 => {
+                        stream.write_all(&[0x90, 0x03, body[0], body[1], 0x01]).unwrap();
+                        stream.write_all(&publish_packet(topic, payload)).unwrap();
+                    }
+                    3 => {
+                        let topic_len = u16::from_be_bytes([body[0], body[1]]) as usize;
+                        let mut start = 2 + topic_len;
+                        if (kind >> 1) & 0x03 > 0 {
+                            stream.write_all(&[0x40, 0x02, body[start], body[start + 1]]).unwrap();
+                            start += 2;
+                        }
+                        let _ = published.send(body[start..].to_vec());
+                    }
+                    12 => stream.write_all(&[0xD0, 0x00]).unwrap(),
+                    _ => {}
+                }
+            }
+        });
+        (port, received)
+    }
+
+    fn headers() -> Vec<String> {
+        vec!["temperature".to_string(), "status".to_string()]
+    }
+
+    #[test]
+    fn receives_rows_and_publishes_commands() {
+        let (port, published) = serve_broker("plant/chiller", br#"{"temperature": 21.5, "status": "ok"}"#);
+        let config: MqttConfig = serde_json::from_value(serde_json::json!({
+            "host": "127.0.0.1",
+            "port": port,
+            "topic": "plant/chiller",
+            "command_topic": "plant/chiller/cmd",
+        }))
+        .unwrap();
+        let mut backend = MqttBackend::new(config, &headers()).unwrap();
+        backend.connect().unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut rows = Vec::new();
+        while rows.is_empty() && Instant::now() < deadline {
+            rows = backend.poll().unwrap();
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert_eq!(rows, vec![vec!["21.5".to_string(), "ok".to_string()]]);
+
+        let command = CommandRequest {
+            command: "start".to_string(),
+            params: Some(serde_json::json!({"speed": 3})),
+            request_id: None,
+        };
+        backend.execute(&command).unwrap();
+        let body = published.recv_timeout(Duration::from_secs(5)).unwrap();
+        let sent: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(sent, serde_json::json!({"command": "start", "params": {"speed": 3}}));
+        backend.disconnect();
+    }
+
+    #[test]
+    fn queue_drops_oldest_rows_beyond_capacity() {
+        let mut rows = VecDeque::new();
+        let decoded = (0..MAX_QUEUED_ROWS + 10).map(|i| vec![i.to_string()]).collect();
+        assert_eq!(enqueue(&mut rows, decoded), 10);
+        assert_eq!(rows.len(), MAX_QUEUED_ROWS);
+        assert_eq!(rows.front().unwrap()[0], "10");
+    }
+}
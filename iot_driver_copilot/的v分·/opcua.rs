@@ -0,0 +1,1321 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::backend::{assemble_row, BackendError, DeviceBackend, Row};
+use crate::CommandRequest;
+
+// OPC UA binary encoding ids (namespace 0) of the services we speak
+const SERVICE_FAULT: u32 = 397;
+const ANONYMOUS_IDENTITY_TOKEN: u32 = 321;
+const OPEN_SECURE_CHANNEL_REQUEST: u32 = 446;
+const OPEN_SECURE_CHANNEL_RESPONSE: u32 = 449;
+const CLOSE_SECURE_CHANNEL_REQUEST: u32 = 452;
+const CREATE_SESSION_REQUEST: u32 = 461;
+const CREATE_SESSION_RESPONSE: u32 = 464;
+const ACTIVATE_SESSION_REQUEST: u32 = 467;
+const ACTIVATE_SESSION_RESPONSE: u32 = 470;
+const READ_REQUEST: u32 = 631;
+const READ_RESPONSE: u32 = 634;
+const WRITE_REQUEST: u32 = 673;
+const WRITE_RESPONSE: u32 = 676;
+const CALL_REQUEST: u32 = 712;
+const CALL_RESPONSE: u32 = 715;
+const CREATE_MONITORED_ITEMS_REQUEST: u32 = 751;
+const CREATE_MONITORED_ITEMS_RESPONSE: u32 = 754;
+const CREATE_SUBSCRIPTION_REQUEST: u32 = 787;
+const CREATE_SUBSCRIPTION_RESPONSE: u32 = 790;
+const DATA_CHANGE_NOTIFICATION: u32 = 811;
+const PUBLISH_REQUEST: u32 = 826;
+const PUBLISH_RESPONSE: u32 = 829;
+
+const SECURITY_POLICY_NONE: &str = "http://opcfoundation.org/UA/SecurityPolicy#None";
+const ATTRIBUTE_VALUE: u32 = 13;
+const TIMESTAMPS_NEITHER: u32 = 3;
+const MONITORING_MODE_REPORTING: u32 = 2;
+const USER_TOKEN_ANONYMOUS: u32 = 0;
+// Largest chunk we accept, advertised to the server as our receive buffer size
+const RECEIVE_BUFFER_SIZE: u32 = 65_536;
+// Upper bound on a reassembled response, so a misbehaving server cannot exhaust memory
+const MAX_MESSAGE_SIZE: u32 = 16 * 1024 * 1024;
+// Seconds between 1601-01-01 (OPC UA DateTime epoch) and 1970-01-01
+const EPOCH_OFFSET_SECS: u64 = 11_644_473_600;
+
+// ========== OPC UA Config ==========
+fn default_timeout_ms() -> u64 {
+    5000
+}
+
+fn default_publishing_interval_ms() -> f64 {
+    1000.0
+}
+
+#[derive(Deserialize, Clone)]
+pub struct NodeColumn {
+    pub column: String,
+    pub node_id: String,
+    pub precision: Option<usize>,
+}
+
+/// Switches polling from periodic Read to a subscription with monitored items.
+#[derive(Deserialize, Clone)]
+pub struct SubscriptionConfig {
+    #[serde(default = "default_publishing_interval_ms")]
+    pub publishing_interval_ms: f64,
+    pub sampling_interval_ms: Option<f64>,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum VariantType {
+    Boolean,
+    Int16,
+    UInt16,
+    Int32,
+    UInt32,
+    Int64,
+    Float,
+    Double,
+    String,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct MethodArgument {
+    pub param: String,
+    pub data_type: VariantType,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum OpcUaAction {
+    /// Writes `params[param]` to the Value attribute of `node_id`.
+    Write {
+        node_id: String,
+        param: String,
+        data_type: VariantType,
+    },
+    /// Calls `method_id` on `object_id` with the listed params as input arguments.
+    Call {
+        object_id: String,
+        method_id: String,
+        #[serde(default)]
+        arguments: Vec<MethodArgument>,
+    },
+}
+
+#[derive(Deserialize, Clone)]
+pub struct OpcUaCommand {
+    pub name: String,
+    #[serde(flatten)]
+    pub action: OpcUaAction,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct OpcUaConfig {
+    /// `opc.tcp://host:port/path`; only SecurityPolicy None with anonymous login is supported.
+    pub endpoint_url: String,
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+    pub nodes: Vec<NodeColumn>,
+    pub subscription: Option<SubscriptionConfig>,
+    #[serde(default)]
+    pub commands: Vec<OpcUaCommand>,
+}
+
+// ========== Node Ids ==========
+/// Parses the standard string form (`i=2258`, `ns=2;s=Chiller.Temp`) into its binary encoding.
+fn encode_node_id(text: &str) -> Result<Vec<u8>, String> {
+    let (ns, id) = match text.strip_prefix("ns=") {
+        Some(rest) => {
+            let (ns, id) = rest.split_once(';').ok_or_else(|| format!("invalid node id '{}'", text))?;
+            (ns.parse::<u16>().map_err(|_| format!("invalid namespace in '{}'", text))?, id)
+        }
+        None => (0, text),
+    };
+    let mut out = Vec::new();
+    if let Some(numeric) = id.strip_prefix("i=") {
+        let value: u32 = numeric.parse().map_err(|_| format!("invalid numeric node id '{}'", text))?;
+        if ns == 0 && value <= 0xFF {
+            out.extend_from_slice(&[0x00, value as u8]);
+        } else if ns <= 0xFF && value <= 0xFFFF {
+            out.extend_from_slice(&[0x01, ns as u8]);
+            out.extend_from_slice(&(value as u16).to_le_bytes());
+        } else {
+            out.push(0x02);
+            out.extend_from_slice(&ns.to_le_bytes());
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+    } else if let Some(string) = id.strip_prefix("s=") {
+        out.push(0x03);
+        out.extend_from_slice(&ns.to_le_bytes());
+        out.extend_from_slice(&(string.len() as i32).to_le_bytes());
+        out.extend_from_slice(string.as_bytes());
+    } else {
+        return Err(format!("unsupported node id '{}' (expected i= or s=)", text));
+    }
+    Ok(out)
+}
+
+fn numeric_node_id(id: u32) -> Vec<u8> {
+    encode_node_id(&format!("i={}", id)).unwrap()
+}
+
+fn now_datetime() -> i64 {
+    let since_unix = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
+    ((since_unix.as_secs() + EPOCH_OFFSET_SECS) * 10_000_000 + since_unix.subsec_nanos() as u64 / 100) as i64
+}
+
+// ========== Binary Encoder ==========
+#[derive(Default)]
+struct Encoder(Vec<u8>);
+
+impl Encoder {
+    fn u8(&mut self, v: u8) -> &mut Self {
+        self.0.push(v);
+        self
+    }
+
+    fn u16(&mut self, v: u16) -> &mut Self {
+        self.0.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    fn u32(&mut self, v: u32) -> &mut Self {
+        self.0.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    fn i32(&mut self, v: i32) -> &mut Self {
+        self.0.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    fn i64(&mut self, v: i64) -> &mut Self {
+        self.0.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    fn f64(&mut self, v: f64) -> &mut Self {
+        self.0.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    fn bytes(&mut self, v: &[u8]) -> &mut Self {
+        self.0.extend_from_slice(v);
+        self
+    }
+
+    fn string(&mut self, v: Option<&str>) -> &mut Self {
+        match v {
+            Some(s) => self.i32(s.len() as i32).bytes(s.as_bytes()),
+            None => self.i32(-1),
+        }
+    }
+
+    fn byte_string(&mut self, v: Option<&[u8]>) -> &mut Self {
+        match v {
+            Some(b) => self.i32(b.len() as i32).bytes(b),
+            None => self.i32(-1),
+        }
+    }
+
+    /// An ExtensionObject with no body.
+    fn null_extension_object(&mut self) -> &mut Self {
+        self.u8(0x00).u8(0x00).u8(0x00)
+    }
+
+    fn request_header(&mut self, auth_token: &[u8], handle: u32, timeout_ms: u32) -> &mut Self {
+        self.bytes(auth_token)
+            .i64(now_datetime())
+            .u32(handle)
+            .u32(0) // return diagnostics
+            .string(None) // audit entry id
+            .u32(timeout_ms)
+            .null_extension_object()
+    }
+
+    fn read_value_id(&mut self, node_id: &[u8]) -> &mut Self {
+        self.bytes(node_id)
+            .u32(ATTRIBUTE_VALUE)
+            .string(None) // index range
+            .u16(0) // data encoding: null qualified name
+            .string(None)
+    }
+
+    fn variant(&mut self, data_type: VariantType, value: &serde_json::Value) -> Result<&mut Self, BackendError> {
+        let invalid = || BackendError::InvalidParams(format!("cannot encode {} as {:?}", value, data_type));
+        let number = || value.as_f64().ok_or_else(invalid);
+        // Rounded into [min, max]; `max + 1.0` keeps the bound exact for 64-bit types
+        let integer = |min: f64, max: f64| {
+            let int = number()?.round();
+            if (min..max + 1.0).contains(&int) {
+                Ok(int)
+            } else {
+                Err(BackendError::InvalidParams(format!("{} is out of range for {:?}", value, data_type)))
+            }
+        };
+        match data_type {
+            VariantType::Boolean => {
+                let b = value.as_bool().ok_or_else(invalid)?;
+                self.u8(1).u8(b as u8);
+            }
+            VariantType::Int16 => {
+                self.u8(4).bytes(&(integer(i16::MIN as f64, i16::MAX as f64)? as i16).to_le_bytes());
+            }
+            VariantType::UInt16 => {
+                self.u8(5).u16(integer(0.0, u16::MAX as f64)? as u16);
+            }
+            VariantType::Int32 => {
+                self.u8(6).i32(integer(i32::MIN as f64, i32::MAX as f64)? as i32);
+            }
+            VariantType::UInt32 => {
+                self.u8(7).u32(integer(0.0, u32::MAX as f64)? as u32);
+            }
+            VariantType::Int64 => {
+                self.u8(8).i64(integer(i64::MIN as f64, i64::MAX as f64)? as i64);
+            }
+            VariantType::Float => {
+                self.u8(10).bytes(&(number()? as f32).to_le_bytes());
+            }
+            VariantType::Double => {
+                self.u8(11).f64(number()?);
+            }
+            VariantType::String => {
+                let s = value.as_str().ok_or_else(invalid)?;
+                self.u8(12).string(Some(s));
+            }
+        }
+        Ok(self)
+    }
+}
+
+// ========== Binary Decoder ==========
+struct Decoder<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+fn truncated() -> BackendError {
+    BackendError::Protocol("truncated OPC UA message".to_string())
+}
+
+impl<'a> Decoder<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Decoder { buf, pos: 0 }
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], BackendError> {
+        let end = self.pos.checked_add(n).filter(|&e| e <= self.buf.len()).ok_or_else(truncated)?;
+        let slice = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8, BackendError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, BackendError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, BackendError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> Result<i32, BackendError> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, BackendError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn byte_string(&mut self) -> Result<Option<&'a [u8]>, BackendError> {
+        let len = self.i32()?;
+        if len < 0 {
+            return Ok(None);
+        }
+        Ok(Some(self.take(len as usize)?))
+    }
+
+    fn string(&mut self) -> Result<Option<String>, BackendError> {
+        Ok(self.byte_string()?.map(|b| String::from_utf8_lossy(b).into_owned()))
+    }
+
+    /// Array length, treating the null array (-1) as empty.
+    fn array_len(&mut self) -> Result<usize, BackendError> {
+        Ok(self.i32()?.max(0) as usize)
+    }
+
+    /// Returns the raw encoding of a NodeId so it can be echoed back unchanged.
+    fn node_id(&mut self) -> Result<&'a [u8], BackendError> {
+        let start = self.pos;
+        let mask = self.u8()?;
+        match mask & 0x0F {
+            0x00 => {
+                self.take(1)?;
+            }
+            0x01 => {
+                self.take(3)?;
+            }
+            0x02 => {
+                self.take(6)?;
+            }
+            0x03 | 0x05 => {
+                self.take(2)?;
+                self.byte_string()?;
+            }
+            0x04 => {
+                self.take(18)?;
+            }
+            other => return Err(BackendError::Protocol(format!("invalid node id encoding {:#x}", other))),
+        }
+        // ExpandedNodeId flags: namespace uri, server index
+        if mask & 0x80 != 0 {
+            self.string()?;
+        }
+        if mask & 0x40 != 0 {
+            self.u32()?;
+        }
+        Ok(&self.buf[start..self.pos])
+    }
+
+    fn numeric_type_id(&mut self) -> Result<u32, BackendError> {
+        let raw = self.node_id()?;
+        Ok(match raw[0] & 0x0F {
+            0x00 => raw[1] as u32,
+            0x01 => u16::from_le_bytes([raw[2], raw[3]]) as u32,
+            0x02 => u32::from_le_bytes([raw[3], raw[4], raw[5], raw[6]]),
+            _ => 0,
+        })
+    }
+
+    /// Returns the type id and binary body of an ExtensionObject.
+    fn extension_object(&mut self) -> Result<(u32, Option<&'a [u8]>), BackendError> {
+        let type_id = self.numeric_type_id()?;
+        match self.u8()? {
+            0x00 => Ok((type_id, None)),
+            0x01 => Ok((type_id, self.byte_string()?)),
+            0x02 => {
+                self.string()?;
+                Ok((type_id, None))
+            }
+            other => Err(BackendError::Protocol(format!("invalid extension object encoding {:#x}", other))),
+        }
+    }
+
+    fn diagnostic_info(&mut self) -> Result<(), BackendError> {
+        let mask = self.u8()?;
+        for bit in [0x01, 0x02, 0x04, 0x08] {
+            if mask & bit != 0 {
+                self.i32()?;
+            }
+        }
+        if mask & 0x10 != 0 {
+            self.string()?;
+        }
+        if mask & 0x20 != 0 {
+            self.u32()?;
+        }
+        if mask & 0x40 != 0 {
+            self.diagnostic_info()?;
+        }
+        Ok(())
+    }
+
+    /// Reads a ResponseHeader and fails on a bad service result.
+    fn response_header(&mut self) -> Result<(), BackendError> {
+        self.u64()?; // timestamp
+        self.u32()?; // request handle
+        let service_result = self.u32()?;
+        self.diagnostic_info()?;
+        for _ in 0..self.array_len()? {
+            self.string()?;
+        }
+        self.extension_object()?;
+        check_status(service_result, "service")
+    }
+
+    fn localized_text(&mut self) -> Result<String, BackendError> {
+        let mask = self.u8()?;
+        if mask & 0x01 != 0 {
+            self.string()?;
+        }
+        Ok(if mask & 0x02 != 0 { self.string()?.unwrap_or_default() } else { String::new() })
+    }
+
+    fn scalar(&mut self, type_id: u8, precision: Option<usize>) -> Result<String, BackendError> {
+        let float = |v: f64| match precision {
+            Some(p) => format!("{:.*}", p, v),
+            None => format!("{:.2}", v),
+        };
+        let int = |v: f64| match precision {
+            Some(p) => format!("{:.*}", p, v),
+            None => format!("{}", v),
+        };
+        Ok(match type_id {
+            1 => (self.u8()? != 0).to_string(),
+            2 => int(self.u8()? as i8 as f64),
+            3 => int(self.u8()? as f64),
+            4 => int(self.u16()? as i16 as f64),
+            5 => int(self.u16()? as f64),
+            6 => int(self.i32()? as f64),
+            7 => int(self.u32()? as f64),
+            8 => int(self.u64()? as i64 as f64),
+            9 => int(self.u64()? as f64),
+            10 => float(f32::from_le_bytes(self.take(4)?.try_into().unwrap()) as f64),
+            11 => float(f64::from_le_bytes(self.take(8)?.try_into().unwrap())),
+            12 => self.string()?.unwrap_or_default(),
+            13 => self.u64()?.to_string(),
+            14 => self.take(16)?.iter().map(|b| format!("{:02x}", b)).collect(),
+            15 => self.byte_string()?.unwrap_or_default().iter().map(|b| format!("{:02x}", b)).collect(),
+            19 => format!("{:#010x}", self.u32()?),
+            20 => {
+                self.u16()?;
+                self.string()?.unwrap_or_default()
+            }
+            21 => self.localized_text()?,
+            other => return Err(BackendError::Protocol(format!("unsupported OPC UA variant type {}", other))),
+        })
+    }
+
+    /// Renders a Variant as a CSV cell; array elements are joined with `;`.
+    fn variant(&mut self, precision: Option<usize>) -> Result<String, BackendError> {
+        let mask = self.u8()?;
+        let type_id = mask & 0x3F;
+        if type_id == 0 {
+            return Ok(String::new());
+        }
+        let cell = if mask & 0x80 != 0 {
+            // The length comes from the server, so grow as elements decode rather than preallocating
+            let mut items = Vec::new();
+            for _ in 0..self.array_len()? {
+                items.push(self.scalar(type_id, precision)?);
+            }
+            items.join(";")
+        } else {
+            self.scalar(type_id, precision)?
+        };
+        if mask & 0x40 != 0 {
+            for _ in 0..self.array_len()? {
+                self.i32()?;
+            }
+        }
+        Ok(cell)
+    }
+
+    /// Returns the rendered value, or the status code when the value is bad.
+    fn data_value(&mut self, precision: Option<usize>) -> Result<Result<String, u32>, BackendError> {
+        let mask = self.u8()?;
+        let value = if mask & 0x01 != 0 { self.variant(precision)? } else { String::new() };
+        let status = if mask & 0x02 != 0 { self.u32()? } else { 0 };
+        if mask & 0x04 != 0 {
+            self.u64()?;
+        }
+        if mask & 0x08 != 0 {
+            self.u64()?;
+        }
+        if mask & 0x10 != 0 {
+            self.u16()?;
+        }
+        if mask & 0x20 != 0 {
+            self.u16()?;
+        }
+        Ok(if status & 0x8000_0000 == 0 { Ok(value) } else { Err(status) })
+    }
+}
+
+fn check_status(status: u32, what: &str) -> Result<(), BackendError> {
+    if status & 0x8000_0000 != 0 {
+        return Err(BackendError::Protocol(format!("OPC UA {} failed with status {:#010x}", what, status)));
+    }
+    Ok(())
+}
+
+// ========== Secure Channel ==========
+struct Channel {
+    stream: TcpStream,
+    channel_id: u32,
+    token_id: u32,
+    token_renew_at: Instant,
+    sequence_number: u32,
+    request_id: u32,
+}
+
+impl Channel {
+    fn open(endpoint_url: &str, timeout: Duration) -> Result<Self, BackendError> {
+        let authority = endpoint_url
+            .strip_prefix("opc.tcp://")
+            .ok_or_else(|| BackendError::Protocol(format!("unsupported endpoint url {}", endpoint_url)))?;
+        let host_port = authority.split('/').next().unwrap_or_default();
+        let addr = host_port
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| BackendError::Protocol(format!("cannot resolve {}", host_port)))?;
+        let stream = TcpStream::connect_timeout(&addr, timeout)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        stream.set_nodelay(true)?;
+
+        let mut channel = Channel {
+            stream,
+            channel_id: 0,
+            token_id: 0,
+            token_renew_at: Instant::now(),
+            sequence_number: 0,
+            request_id: 0,
+        };
+
+        let mut hello = Encoder::default();
+        hello
+            .u32(0) // protocol version
+            .u32(RECEIVE_BUFFER_SIZE)
+            .u32(65_536) // send buffer size
+            .u32(MAX_MESSAGE_SIZE)
+            .u32(0) // max chunk count
+            .string(Some(endpoint_url));
+        channel.send_frame(b"HEL", &hello.0)?;
+        let (kind, _) = channel.read_frame()?;
+        if &kind != b"ACK" {
+            return Err(BackendError::Protocol("OPC UA server did not acknowledge hello".to_string()));
+        }
+
+        channel.open_secure_channel(0, timeout)?;
+        Ok(channel)
+    }
+
+    fn send_frame(&mut self, kind: &[u8; 3], body: &[u8]) -> Result<(), BackendError> {
+        let mut frame = Vec::with_capacity(8 + body.len());
+        frame.extend_from_slice(kind);
+        frame.push(b'F');
+        frame.extend_from_slice(&(8 + body.len() as u32).to_le_bytes());
+        frame.extend_from_slice(body);
+        self.stream.write_all(&frame)?;
+        Ok(())
+    }
+
+    fn read_frame(&mut self) -> Result<([u8; 3], Vec<u8>), BackendError> {
+        let (kind, _, body) = self.read_chunk()?;
+        Ok((kind, body))
+    }
+
+    /// Reads one frame, returning its message type, chunk type and body.
+    fn read_chunk(&mut self) -> Result<([u8; 3], u8, Vec<u8>), BackendError> {
+        let mut header = [0u8; 8];
+        self.stream.read_exact(&mut header)?;
+        let size = u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize;
+        if size < 8 {
+            return Err(BackendError::Protocol("invalid OPC UA frame size".to_string()));
+        }
+        if size > RECEIVE_BUFFER_SIZE as usize {
+            return Err(BackendError::Protocol(format!("OPC UA chunk of {} bytes exceeds the receive buffer", size)));
+        }
+        let mut body = vec![0u8; size - 8];
+        self.stream.read_exact(&mut body)?;
+        let kind = [header[0], header[1], header[2]];
+        if &kind == b"ERR" {
+            let mut d = Decoder::new(&body);
+            let code = d.u32()?;
+            let reason = d.string()?.unwrap_or_default();
+            return Err(BackendError::Protocol(format!("OPC UA error {:#010x}: {}", code, reason)));
+        }
+        Ok((kind, header[3], body))
+    }
+
+    fn next_ids(&mut self) -> (u32, u32) {
+        self.sequence_number = self.sequence_number.wrapping_add(1);
+        self.request_id = self.request_id.wrapping_add(1);
+        (self.sequence_number, self.request_id)
+    }
+
+    /// Issues (request_type 0) or renews (1) the channel's security token.
+    fn open_secure_channel(&mut self, request_type: u32, timeout: Duration) -> Result<(), BackendError> {
+        let (sequence_number, request_id) = self.next_ids();
+        let mut msg = Encoder::default();
+        msg.u32(self.channel_id)
+            .string(Some(SECURITY_POLICY_NONE))
+            .byte_string(None) // sender certificate
+            .byte_string(None) // receiver thumbprint
+            .u32(sequence_number)
+            .u32(request_id)
+            .bytes(&numeric_node_id(OPEN_SECURE_CHANNEL_REQUEST))
+            .request_header(&[0x00, 0x00], request_id, timeout.as_millis() as u32)
+            .u32(0) // client protocol version
+            .u32(request_type)
+            .u32(1) // message security mode None
+            .byte_string(None) // client nonce
+            .u32(3_600_000); // requested lifetime
+        self.send_frame(b"OPN", &msg.0)?;
+
+        let (kind, body) = self.read_frame()?;
+        if &kind != b"OPN" {
+            return Err(BackendError::Protocol("unexpected reply to OpenSecureChannel".to_string()));
+        }
+        let mut d = Decoder::new(&body);
+        d.u32()?; // channel id
+        d.string()?; // policy uri
+        d.byte_string()?;
+        d.byte_string()?;
+        d.u32()?; // sequence number
+        d.u32()?; // request id
+        expect_type(&mut d, OPEN_SECURE_CHANNEL_RESPONSE)?;
+        d.response_header()?;
+        d.u32()?; // server protocol version
+        self.channel_id = d.u32()?;
+        self.token_id = d.u32()?;
+        d.u64()?; // created at
+        let lifetime = Duration::from_millis(d.u32()? as u64);
+        self.token_renew_at = Instant::now() + lifetime * 3 / 4;
+        Ok(())
+    }
+
+    /// Sends a service request and returns the response body positioned after its type id.
+    fn request(&mut self, body: &[u8], timeout: Duration) -> Result<Vec<u8>, BackendError> {
+        if Instant::now() >= self.token_renew_at {
+            self.open_secure_channel(1, timeout)?;
+        }
+        let (sequence_number, request_id) = self.next_ids();
+        let mut msg = Encoder::default();
+        msg.u32(self.channel_id).u32(self.token_id).u32(sequence_number).u32(request_id).bytes(body);
+        self.send_frame(b"MSG", &msg.0)?;
+
+        // Reassemble chunks ('C' intermediate, 'F' final) of the matching response
+        let mut payload = Vec::new();
+        loop {
+            let (kind, chunk_type, chunk) = self.read_chunk()?;
+            if &kind != b"MSG" || chunk.len() < 16 {
+                return Err(BackendError::Protocol("invalid OPC UA message".to_string()));
+            }
+            let chunk_request_id = u32::from_le_bytes(chunk[12..16].try_into().unwrap());
+            if chunk_request_id != request_id {
+                continue;
+            }
+            if payload.len() + chunk.len() - 16 > MAX_MESSAGE_SIZE as usize {
+                return Err(BackendError::Protocol("OPC UA response exceeds the maximum message size".to_string()));
+            }
+            match chunk_type {
+                b'C' => payload.extend_from_slice(&chunk[16..]),
+                b'F' => {
+                    payload.extend_from_slice(&chunk[16..]);
+                    return Ok(payload);
+                }
+                _ => return Err(BackendError::Protocol("OPC UA response aborted by server".to_string())),
+            }
+        }
+    }
+
+    fn close(&mut self) {
+        let (sequence_number, request_id) = self.next_ids();
+        let mut msg = Encoder::default();
+        msg.u32(self.channel_id)
+            .u32(self.token_id)
+            .u32(sequence_number)
+            .u32(request_id)
+            .bytes(&numeric_node_id(CLOSE_SECURE_CHANNEL_REQUEST))
+            .request_header(&[0x00, 0x00], request_id, 0);
+        let _ = self.send_frame(b"CLO", &msg.0);
+    }
+}
+
+fn expect_type(d: &mut Decoder, expected: u32) -> Result<(), BackendError> {
+    match d.numeric_type_id()? {
+        t if t == expected => Ok(()),
+        SERVICE_FAULT => {
+            d.response_header()?;
+            Err(BackendError::Protocol("OPC UA service fault".to_string()))
+        }
+        other => Err(BackendError::Protocol(format!("unexpected OPC UA response type {}", other))),
+    }
+}
+
+// ========== OPC UA Backend ==========
+struct Session {
+    channel: Channel,
+    auth_token: Vec<u8>,
+    subscription_id: Option<u32>,
+    pending_acks: Vec<(u32, u32)>,
+}
+
+pub struct OpcUaBackend {
+    config: OpcUaConfig,
+    headers: Vec<String>,
+    node_ids: Vec<Vec<u8>>,
+    timeout: Duration,
+    session: Option<Session>,
+    request_handle: u32,
+    latest: HashMap<String, String>,
+}
+
+impl OpcUaBackend {
+    pub fn new(config: OpcUaConfig, headers: &[String]) -> Result<Self, String> {
+        let mut node_ids = Vec::new();
+        for node in &config.nodes {
+            if !headers.contains(&node.column) {
+                return Err(format!("opcua node column '{}' is not a CSV header", node.column));
+            }
+            node_ids.push(encode_node_id(&node.node_id)?);
+        }
+        for command in &config.commands {
+            match &command.action {
+                OpcUaAction::Write { node_id, .. } => {
+                    encode_node_id(node_id)?;
+                }
+                OpcUaAction::Call { object_id, method_id, .. } => {
+                    encode_node_id(object_id)?;
+                    encode_node_id(method_id)?;
+                }
+            }
+        }
+        Ok(OpcUaBackend {
+            timeout: Duration::from_millis(config.timeout_ms),
+            config,
+            headers: headers.to_vec(),
+            node_ids,
+            session: None,
+            request_handle: 0,
+            latest: HashMap::new(),
+        })
+    }
+
+    /// Encodes a service request: type id followed by a RequestHeader.
+    fn start_request(&mut self, type_id: u32) -> Result<Encoder, BackendError> {
+        let session = self.session.as_ref().ok_or(BackendError::NotConnected)?;
+        self.request_handle = self.request_handle.wrapping_add(1);
+        let mut e = Encoder::default();
+        e.bytes(&numeric_node_id(type_id))
+            .request_header(&session.auth_token, self.request_handle, self.timeout.as_millis() as u32);
+        Ok(e)
+    }
+
+    fn call_service(&mut self, request: Encoder, response_type: u32) -> Result<Vec<u8>, BackendError> {
+        let timeout = self.timeout;
+        let session = self.session.as_mut().ok_or(BackendError::NotConnected)?;
+        let body = session.channel.request(&request.0, timeout)?;
+        let mut d = Decoder::new(&body);
+        expect_type(&mut d, response_type)?;
+        d.response_header()?;
+        Ok(body[d.pos..].to_vec())
+    }
+
+    fn create_session(&mut self, channel: Channel) -> Result<(), BackendError> {
+        self.session = Some(Session {
+            channel,
+            auth_token: vec![0x00, 0x00],
+            subscription_id: None,
+            pending_acks: Vec::new(),
+        });
+        // Only used to satisfy servers that insist on a client nonce even without security
+        let nonce: Vec<u8> = now_datetime().to_le_bytes().iter().cycle().take(32).copied().collect();
+
+        let mut e = self.start_request(CREATE_SESSION_REQUEST)?;
+        e.string(Some("urn:iot-driver:client")) // application uri
+            .string(Some("urn:iot-driver")) // product uri
+            .u8(0x02)
+            .string(Some("IoT Driver")) // application name
+            .u32(1) // application type: client
+            .string(None)
+            .string(None)
+            .i32(-1) // discovery urls
+            .string(None) // server uri
+            .string(Some(&self.config.endpoint_url))
+            .string(Some("iot-driver"))
+            .byte_string(Some(&nonce))
+            .byte_string(None) // client certificate
+            .f64(60_000.0) // requested session timeout
+            .u32(0); // max response message size
+        let body = self.call_service(e, CREATE_SESSION_RESPONSE)?;
+
+        let mut d = Decoder::new(&body);
+        d.node_id()?; // session id
+        let auth_token = d.node_id()?.to_vec();
+        d.u64()?; // revised session timeout
+        d.byte_string()?; // server nonce
+        d.byte_string()?; // server certificate
+        let policy_id = anonymous_policy_id(&mut d)?;
+        self.session.as_mut().unwrap().auth_token = auth_token;
+
+        let mut token = Encoder::default();
+        token.string(Some(&policy_id));
+        let mut e = self.start_request(ACTIVATE_SESSION_REQUEST)?;
+        e.string(None)
+            .byte_string(None) // client signature
+            .i32(-1) // client software certificates
+            .i32(-1) // locale ids
+            .bytes(&numeric_node_id(ANONYMOUS_IDENTITY_TOKEN))
+            .u8(0x01)
+            .byte_string(Some(&token.0))
+            .string(None)
+            .byte_string(None); // user token signature
+        self.call_service(e, ACTIVATE_SESSION_RESPONSE)?;
+        Ok(())
+    }
+
+    fn create_subscription(&mut self, subscription: &SubscriptionConfig) -> Result<(), BackendError> {
+        let mut e = self.start_request(CREATE_SUBSCRIPTION_REQUEST)?;
+        e.f64(subscription.publishing_interval_ms)
+            .u32(30) // lifetime count
+            .u32(2) // max keep-alive count, keeps each Publish short
+            .u32(0) // max notifications per publish
+            .u8(1) // publishing enabled
+            .u8(0); // priority
+        let body = self.call_service(e, CREATE_SUBSCRIPTION_RESPONSE)?;
+        let subscription_id = Decoder::new(&body).u32()?;
+
+        let sampling = subscription.sampling_interval_ms.unwrap_or(subscription.publishing_interval_ms);
+        let mut e = self.start_request(CREATE_MONITORED_ITEMS_REQUEST)?;
+        e.u32(subscription_id).u32(TIMESTAMPS_NEITHER).i32(self.node_ids.len() as i32);
+        for (handle, node_id) in self.node_ids.iter().enumerate() {
+            e.read_value_id(node_id)
+                .u32(MONITORING_MODE_REPORTING)
+                .u32(handle as u32) // client handle indexes config.nodes
+                .f64(sampling)
+                .null_extension_object()
+                .u32(1) // queue size
+                .u8(1); // discard oldest
+        }
+        let body = self.call_service(e, CREATE_MONITORED_ITEMS_RESPONSE)?;
+        let mut d = Decoder::new(&body);
+        d.array_len()?;
+        for node in &self.config.nodes {
+            check_status(d.u32()?, &format!("monitoring {}", node.node_id))?;
+            d.u32()?; // monitored item id
+            d.u64()?; // revised sampling interval
+            d.u32()?; // revised queue size
+            d.extension_object()?;
+        }
+        self.session.as_mut().unwrap().subscription_id = Some(subscription_id);
+        Ok(())
+    }
+
+    fn read_nodes(&mut self) -> Result<Vec<Row>, BackendError> {
+        let mut e = self.start_request(READ_REQUEST)?;
+        e.f64(0.0).u32(TIMESTAMPS_NEITHER).i32(self.node_ids.len() as i32);
+        for node_id in &self.node_ids {
+            e.read_value_id(node_id);
+        }
+        let body = self.call_service(e, READ_RESPONSE)?;
+        let mut d = Decoder::new(&body);
+        if d.array_len()? != self.config.nodes.len() {
+            return Err(BackendError::Protocol("OPC UA read returned wrong result count".to_string()));
+        }
+        let mut values = HashMap::new();
+        for node in &self.config.nodes {
+            match d.data_value(node.precision)? {
+                Ok(value) => {
+                    values.insert(node.column.clone(), value);
+                }
+                Err(status) => log::warn!("opcua read of {} returned status {:#010x}", node.node_id, status),
+            }
+        }
+        Ok(vec![assemble_row(&self.headers, &values)])
+    }
+
+    fn publish(&mut self) -> Result<Vec<Row>, BackendError> {
+        let acks = std::mem::take(&mut self.session.as_mut().ok_or(BackendError::NotConnected)?.pending_acks);
+        let mut e = self.start_request(PUBLISH_REQUEST)?;
+        e.i32(acks.len() as i32);
+        for (subscription_id, sequence_number) in acks {
+            e.u32(subscription_id).u32(sequence_number);
+        }
+        let body = self.call_service(e, PUBLISH_RESPONSE)?;
+
+        let mut d = Decoder::new(&body);
+        let subscription_id = d.u32()?;
+        for _ in 0..d.array_len()? {
+            d.u32()?; // available sequence numbers
+        }
+        d.u8()?; // more notifications
+        let sequence_number = d.u32()?;
+        d.u64()?; // publish time
+        let mut changed = false;
+        let notifications = d.array_len()?;
+        for _ in 0..notifications {
+            let (type_id, notification) = d.extension_object()?;
+            let Some(notification) = notification.filter(|_| type_id == DATA_CHANGE_NOTIFICATION) else {
+                continue;
+            };
+            let mut n = Decoder::new(notification);
+            for _ in 0..n.array_len()? {
+                let handle = n.u32()? as usize;
+                let node = self
+                    .config
+                    .nodes
+                    .get(handle)
+                    .ok_or_else(|| BackendError::Protocol(format!("unknown monitored item handle {}", handle)))?;
+                if let Ok(value) = n.data_value(node.precision)? {
+                    self.latest.insert(node.column.clone(), value);
+                    changed = true;
+                }
+            }
+        }
+        let session = self.session.as_mut().unwrap();
+        // Keep-alive messages announce the next sequence number and must not be acknowledged
+        if notifications > 0 {
+            session.pending_acks.push((subscription_id, sequence_number));
+        }
+        // Keep-alives carry no data; a change emits a row with every column's latest value
+        Ok(if changed { vec![assemble_row(&self.headers, &self.latest)] } else { Vec::new() })
+    }
+
+    fn write(&mut self, node_id: &str, data_type: VariantType, value: &serde_json::Value) -> Result<(), BackendError> {
+        let node_id = encode_node_id(node_id).map_err(BackendError::Protocol)?;
+        let mut e = self.start_request(WRITE_REQUEST)?;
+        e.i32(1)
+            .bytes(&node_id)
+            .u32(ATTRIBUTE_VALUE)
+            .string(None) // index range
+            .u8(0x01) // data value with variant only
+            .variant(data_type, value)?;
+        let body = self.call_service(e, WRITE_RESPONSE)?;
+        let mut d = Decoder::new(&body);
+        d.array_len()?;
+        check_status(d.u32()?, "write")
+    }
+
+    fn call(&mut self, object_id: &str, method_id: &str, arguments: &[(VariantType, serde_json::Value)]) -> Result<(), BackendError> {
+        let object_id = encode_node_id(object_id).map_err(BackendError::Protocol)?;
+        let method_id = encode_node_id(method_id).map_err(BackendError::Protocol)?;
+        let mut e = self.start_request(CALL_REQUEST)?;
+        e.i32(1).bytes(&object_id).bytes(&method_id).i32(arguments.len() as i32);
+        for (data_type, value) in arguments {
+            e.variant(*data_type, value)?;
+        }
+        let body = self.call_service(e, CALL_RESPONSE)?;
+        let mut d = Decoder::new(&body);
+        d.array_len()?;
+        check_status(d.u32()?, "method call")
+    }
+}
+
+/// Picks the anonymous user token policy id from the server's endpoint list.
+fn anonymous_policy_id(d: &mut Decoder) -> Result<String, BackendError> {
+    let mut policy_id = None;
+    for _ in 0..d.array_len()? {
+        d.string()?; // endpoint url
+        d.string()?; // application uri
+        d.string()?; // product uri
+        d.localized_text()?;
+        d.u32()?; // application type
+        d.string()?;
+        d.string()?;
+        for _ in 0..d.array_len()? {
+            d.string()?; // discovery urls
+        }
+        d.byte_string()?; // server certificate
+        let security_mode = d.u32()?;
+        let security_policy = d.string()?;
+        for _ in 0..d.array_len()? {
+            let id = d.string()?;
+            let token_type = d.u32()?;
+            d.string()?;
+            d.string()?;
+            d.string()?;
+            let unsecured = security_mode == 1 && security_policy.as_deref() == Some(SECURITY_POLICY_NONE);
+            if token_type == USER_TOKEN_ANONYMOUS && unsecured && policy_id.is_none() {
+                policy_id = id;
+            }
+        }
+        d.string()?; // transport profile
+        d.u8()?; // security level
+    }
+    policy_id.ok_or_else(|| BackendError::Protocol("server offers no anonymous login without security".to_string()))
+}
+
+impl DeviceBackend for OpcUaBackend {
+    fn connect(&mut self) -> Result<(), BackendError> {
+        let channel = Channel::open(&self.config.endpoint_url, self.timeout)?;
+        let result = self.create_session(channel).and_then(|_| match self.config.subscription.clone() {
+            Some(subscription) => self.create_subscription(&subscription),
+            None => Ok(()),
+        });
+        if result.is_err() {
+            self.disconnect();
+        }
+        result
+    }
+
+    fn poll(&mut self) -> Result<Vec<Row>, BackendError> {
+        match self.session.as_ref().map(|s| s.subscription_id.is_some()) {
+            Some(true) => self.publish(),
+            Some(false) => self.read_nodes(),
+            None => Err(BackendError::NotConnected),
+        }
+    }
+
+    fn execute(&mut self, command: &CommandRequest) -> Result<Option<Row>, BackendError> {
+        let action = self
+            .config
+            .commands
+            .iter()
+            .find(|c| c.name == command.command)
+            .map(|c| c.action.clone())
+            .ok_or_else(|| BackendError::UnknownCommand(command.command.clone()))?;
+        let param = |name: &str| {
+            command
+                .params
+                .as_ref()
+                .and_then(|p| p.get(name))
+                .cloned()
+                .ok_or_else(|| BackendError::InvalidParams(format!("Missing {} parameter", name)))
+        };
+        match action {
+            OpcUaAction::Write { node_id, param: name, data_type } => {
+                let value = param(&name)?;
+                self.write(&node_id, data_type, &value)?;
+            }
+            OpcUaAction::Call { object_id, method_id, arguments } => {
+                let values = arguments
+                    .iter()
+                    .map(|a| Ok((a.data_type, param(&a.param)?)))
+                    .collect::<Result<Vec<_>, BackendError>>()?;
+                self.call(&object_id, &method_id, &values)?;
+            }
+        }
+        Ok(None)
+    }
+
+    fn disconnect(&mut self) {
+        if let Some(mut session) = self.session.take() {
+            session.channel.close();
+        }
+        self.latest.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    fn read_server_chunk(stream: &mut TcpStream) -> Option<([u8; 3], Vec<u8>)> {
+        let mut header = [0u8; 8];
+        stream.read_exact(&mut header).ok()?;
+        let mut body = vec![0u8; u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize - 8];
+        stream.read_exact(&mut body).ok()?;
+        Some(([header[0], header[1], header[2]], body))
+    }
+
+    fn write_server_frame(stream: &mut TcpStream, kind: &[u8; 3], body: &[u8]) {
+        let mut frame = kind.to_vec();
+        frame.push(b'F');
+        frame.extend_from_slice(&(8 + body.len() as u32).to_le_bytes());
+        frame.extend_from_slice(body);
+        stream.write_all(&frame).unwrap();
+    }
+
+    fn response_header(e: &mut Encoder, handle: u32) {
+        e.i64(now_datetime())
+            .u32(handle)
+            .u32(0) // service result
+            .u8(0) // diagnostic info
+            .i32(-1) // string table
+            .null_extension_object();
+    }
+
+    /// Skips a RequestHeader and returns its request handle.
+    fn request_header(d: &mut Decoder) -> u32 {
+        d.node_id().unwrap();
+        d.u64().unwrap();
+        let handle = d.u32().unwrap();
+        d.u32().unwrap();
+        d.string().unwrap();
+        d.u32().unwrap();
+        d.extension_object().unwrap();
+        handle
+    }
+
+    fn create_session_response(e: &mut Encoder) {
+        e.bytes(&numeric_node_id(1)) // session id
+            .bytes(&encode_node_id("ns=1;i=42").unwrap()) // auth token
+            .f64(60_000.0)
+            .byte_string(None)
+            .byte_string(None)
+            .i32(1) // endpoints
+            .string(Some("opc.tcp://localhost"))
+            .string(Some("urn:test-server"))
+            .string(None)
+            .u8(0x02)
+            .string(Some("Test Server"))
+            .u32(0) // application type: server
+            .string(None)
+            .string(None)
+            .i32(-1)
+            .byte_string(None)
+            .u32(1) // security mode None
+            .string(Some(SECURITY_POLICY_NONE))
+            .i32(1) // user identity tokens
+            .string(Some("anonymous"))
+            .u32(USER_TOKEN_ANONYMOUS)
+            .string(None)
+            .string(None)
+            .string(None)
+            .string(None) // transport profile
+            .u8(0); // security level
+    }
+
+    /// An OPC UA server speaking SecurityPolicy None that serves Read and Write
+    /// from a map of node encodings to Double values.
+    fn serve(values: Arc<Mutex<HashMap<Vec<u8>, f64>>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("opc.tcp://{}/test", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            while let Some((kind, body)) = read_server_chunk(&mut stream) {
+                let mut d = Decoder::new(&body);
+                match &kind {
+                    b"HEL" => {
+                        let mut ack = Encoder::default();
+                        ack.u32(0).u32(65_536).u32(65_536).u32(0).u32(0);
+                        write_server_frame(&mut stream, b"ACK", &ack.0);
+                    }
+                    b"OPN" => {
+                        d.u32().unwrap();
+                        d.string().unwrap();
+                        d.byte_string().unwrap();
+                        d.byte_string().unwrap();
+                        let sequence_number = d.u32().unwrap();
+                        let request_id = d.u32().unwrap();
+                        d.numeric_type_id().unwrap();
+                        let handle = request_header(&mut d);
+                        let mut e = Encoder::default();
+                        e.u32(7)
+                            .string(Some(SECURITY_POLICY_NONE))
+                            .byte_string(None)
+                            .byte_string(None)
+                            .u32(sequence_number)
+                            .u32(request_id)
+                            .bytes(&numeric_node_id(OPEN_SECURE_CHANNEL_RESPONSE));
+                        response_header(&mut e, handle);
+                        e.u32(0).u32(7).u32(1).i64(now_datetime()).u32(3_600_000).byte_string(None);
+                        write_server_frame(&mut stream, b"OPN", &e.0);
+                    }
+                    b"MSG" => {
+                        let channel_id = d.u32().unwrap();
+                        let token_id = d.u32().unwrap();
+                        let sequence_number = d.u32().unwrap();
+                        let request_id = d.u32().unwrap();
+                        let type_id = d.numeric_type_id().unwrap();
+                        let handle = request_header(&mut d);
+                        let mut e = Encoder::default();
+                        e.u32(channel_id).u32(token_id).u32(sequence_number).u32(request_id);
+                        match type_id {
+                            CREATE_SESSION_REQUEST => {
+                                e.bytes(&numeric_node_id(CREATE_SESSION_RESPONSE));
+                                response_header(&mut e, handle);
+                                create_session_response(&mut e);
+                            }
+                            ACTIVATE_SESSION_REQUEST => {
+                                e.bytes(&numeric_node_id(ACTIVATE_SESSION_RESPONSE));
+                                response_header(&mut e, handle);
+                                e.byte_string(None).i32(-1).i32(-1);
+                            }
+                            READ_REQUEST => {
+                                d.u64().unwrap();
+                                d.u32().unwrap();
+                                let count = d.array_len().unwrap();
+                                e.bytes(&numeric_node_id(READ_RESPONSE));
+                                response_header(&mut e, handle);
+                                e.i32(count as i32);
+                                for _ in 0..count {
+                                    let node_id = d.node_id().unwrap().to_vec();
+                                    d.u32().unwrap();
+                                    d.string().unwrap();
+                                    d.u16().unwrap();
+                                    d.string().unwrap();
+                                    match values.lock().unwrap().get(&node_id) {
+                                        Some(value) => e.u8(0x01).u8(11).f64(*value),
+                                        None => e.u8(0x02).u32(0x8034_0000), // BadNodeIdUnknown
+                                    };
+                                }
+                                e.i32(-1);
+                            }
+                            WRITE_REQUEST => {
+                                d.array_len().unwrap();
+                                let node_id = d.node_id().unwrap().to_vec();
+                                d.u32().unwrap();
+                                d.string().unwrap();
+                                let value = d.data_value(None).unwrap().unwrap();
+                                values.lock().unwrap().insert(node_id, value.parse().unwrap());
+                                e.bytes(&numeric_node_id(WRITE_RESPONSE));
+                                response_header(&mut e, handle);
+                                e.i32(1).u32(0).i32(-1);
+                            }
+                            other => panic!("unexpected service request {}", other),
+                        }
+                        write_server_frame(&mut stream, b"MSG", &e.0);
+                    }
+                    _ => return,
+                }
+            }
+        });
+        url
+    }
+
+    fn headers() -> Vec<String> {
+        vec!["temperature".to_string(), "setpoint".to_string()]
+    }
+
+    #[test]
+    fn reads_nodes_and_writes_values() {
+        let temperature = encode_node_id("ns=2;s=Chiller.Temp").unwrap();
+        let setpoint = encode_node_id("ns=2;i=1001").unwrap();
+        let values = Arc::new(Mutex::new(HashMap::from([(temperature, 21.5), (setpoint.clone(), 20.0)])));
+        let config: OpcUaConfig = serde_json::from_value(serde_json::json!({
+            "endpoint_url": serve(values.clone()),
+            "nodes": [
+                {"column": "temperature", "node_id": "ns=2;s=Chiller.Temp", "precision": 1},
+                {"column": "setpoint", "node_id": "ns=2;i=1001"},
+            ],
+            "commands": [{"name": "set_setpoint", "action": "write", "node_id": "ns=2;i=1001", "param": "value", "data_type": "double"}],
+        }))
+        .unwrap();
+        let mut backend = OpcUaBackend::new(config, &headers()).unwrap();
+
+        backend.connect().unwrap();
+        assert_eq!(backend.poll().unwrap(), vec![vec!["21.5".to_string(), "20.00".to_string()]]);
+        let command = CommandRequest {
+            command: "set_setpoint".to_string(),
+            params: Some(serde_json::json!({"value": 22.5})),
+            request_id: None,
+        };
+        backend.execute(&command).unwrap();
+        assert_eq!(values.lock().unwrap()[&setpoint], 22.5);
+        assert_eq!(backend.poll().unwrap()[0][1], "22.50");
+        backend.disconnect();
+    }
+
+    #[test]
+    fn rejects_chunks_beyond_the_receive_buffer() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("opc.tcp://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            read_server_chunk(&mut stream);
+            stream.write_all(b"ACKF\xf0\xff\xff\xff").unwrap();
+        });
+        let err = Channel::open(&url, Duration::from_secs(5)).err().unwrap().to_string();
+        assert!(err.contains("exceeds the receive buffer"), "{}", err);
+    }
+
+    #[test]
+    fn oversized_array_length_fails_without_allocating() {
+        let mut e = Encoder::default();
+        e.u8(0x80 | 11).i32(i32::MAX).f64(1.0);
+        assert!(Decoder::new(&e.0).variant(None).is_err());
+    }
+
+    #[test]
+    fn rejects_integers_outside_the_node_type() {
+        let encode = |data_type, value| Encoder::default().variant(data_type, &serde_json::json!(value)).map(|e| e.0.clone());
+        assert_eq!(encode(VariantType::Int16, 32767.0).unwrap(), [4, 0xff, 0x7f]);
+        assert!(matches!(encode(VariantType::Int16, 40000.0), Err(BackendError::InvalidParams(_))));
+        assert!(matches!(encode(VariantType::UInt16, -5.0), Err(BackendError::InvalidParams(_))));
+        assert_eq!(encode(VariantType::UInt32, 4294967295.4).unwrap(), [7, 0xff, 0xff, 0xff, 0xff]);
+        assert!(encode(VariantType::Int64, 9.3e18).is_err());
+    }
+}
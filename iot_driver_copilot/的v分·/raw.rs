@@ -0,0 +1,259 @@
+use serde::Deserialize;
+use std::io::{ErrorKind, Read};
+use std::net::{TcpStream, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
+
+use crate::backend::{parse_csv_line, BackendError, DeviceBackend, Row};
+use crate::CommandRequest;
+
+// Short socket timeout so a poll drains what has arrived without stalling the poller
+const READ_SLICE: Duration = Duration::from_millis(50);
+// Bounds one poll on a chatty device so the backend lock is released for commands
+const MAX_READS_PER_POLL: usize = 64;
+
+// ========== Raw Line Protocol Config ==========
+#[derive(Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RawTransport {
+    #[default]
+    Tcp,
+    Udp,
+}
+
+fn default_delimiter() -> String {
+    "\n".to_string()
+}
+
+fn default_connect_timeout_ms() -> u64 {
+    3000
+}
+
+fn default_idle_timeout_ms() -> u64 {
+    30_000
+}
+
+fn default_max_frame_bytes() -> usize {
+    4096
+}
+
+#[derive(Deserialize, Clone)]
+pub struct RawConfig {
+    #[serde(default)]
+    pub transport: RawTransport,
+    /// Device `host:port` to connect to over TCP, or local `ip:port` to listen on for UDP.
+    pub address: String,
+    #[serde(default = "default_delimiter")]
+    pub delimiter: String,
+    #[serde(default = "default_connect_timeout_ms")]
+    pub connect_timeout_ms: u64,
+    /// Reconnect when no bytes arrive for this long (TCP only).
+    #[serde(default = "default_idle_timeout_ms")]
+    pub idle_timeout_ms: u64,
+    #[serde(default = "default_max_frame_bytes")]
+    pub max_frame_bytes: usize,
+}
+
+enum Socket {
+    Tcp(TcpStream),
+    Udp(UdpSocket),
+}
+
+// ========== Raw Line Protocol Backend ==========
+/// Frames a byte stream into CSV lines, one row per line.
+pub struct RawBackend {
+    config: RawConfig,
+    headers: Vec<String>,
+    socket: Option<Socket>,
+    buffer: Vec<u8>,
+    last_data: Instant,
+    rows_received: u64,
+    malformed_lines: u64,
+    connects: u64,
+}
+
+impl RawBackend {
+    pub fn new(config: RawConfig, headers: &[String]) -> Result<Self, String> {
+        if config.delimiter.is_empty() {
+            return Err("raw backend delimiter must not be empty".to_string());
+        }
+        Ok(RawBackend {
+            config,
+            headers: headers.to_vec(),
+            socket: None,
+            buffer: Vec::new(),
+            last_data: Instant::now(),
+            rows_received: 0,
+            malformed_lines: 0,
+            connects: 0,
+        })
+    }
+
+    fn handle_frame(&mut self, frame: &[u8], rows: &mut Vec<Row>) {
+        let text = String::from_utf8_lossy(frame);
+        let line = text.trim();
+        // Devices commonly announce their header line on connect
+        if line.is_empty() || line == self.headers.join(",") {
+            return;
+        }
+        match parse_csv_line(&self.headers, line) {
+            Ok(row) => {
+                self.rows_received += 1;
+                rows.push(row);
+            }
+            Err(e) => {
+                self.malformed_lines += 1;
+                log::warn!("dropping malformed line '{}': {}", line, e);
+            }
+        }
+    }
+
+    /// Splits complete frames off the buffer, keeping any trailing partial frame.
+    fn drain_frames(&mut self, rows: &mut Vec<Row>) {
+        let delimiter = self.config.delimiter.clone().into_bytes();
+        while let Some(pos) = self.buffer.windows(delimiter.len()).position(|w| w == delimiter) {
+            let frame: Vec<u8> = self.buffer.drain(..pos + delimiter.len()).take(pos).collect();
+            self.handle_frame(&frame, rows);
+        }
+        if self.buffer.len() > self.config.max_frame_bytes {
+            self.malformed_lines += 1;
+            log::warn!("discarding {} bytes without a delimiter", self.buffer.len());
+            self.buffer.clear();
+        }
+    }
+}
+
+impl DeviceBackend for RawBackend {
+    fn connect(&mut self) -> Result<(), BackendError> {
+        let socket = match self.config.transport {
+            RawTransport::Tcp => {
+                let addr = self
+                    .config
+                    .address
+                    .to_socket_addrs()?
+                    .next()
+                    .ok_or_else(|| BackendError::Protocol(format!("cannot resolve {}", self.config.address)))?;
+                let stream = TcpStream::connect_timeout(&addr, Duration::from_millis(self.config.connect_timeout_ms))?;
+                stream.set_read_timeout(Some(READ_SLICE))?;
+                Socket::Tcp(stream)
+            }
+            RawTransport::Udp => {
+                let socket = UdpSocket::bind(&self.config.address)?;
+                socket.set_read_timeout(Some(READ_SLICE))?;
+                Socket::Udp(socket)
+            }
+        };
+        self.connects += 1;
+        self.socket = Some(socket);
+        self.buffer.clear();
+        self.last_data = Instant::now();
+        Ok(())
+    }
+
+    fn poll(&mut self) -> Result<Vec<Row>, BackendError> {
+        let mut rows = Vec::new();
+        let mut chunk = [0u8; 2048];
+        for _ in 0..MAX_READS_PER_POLL {
+            let read = match self.socket.as_mut().ok_or(BackendError::NotConnected)? {
+                Socket::Tcp(stream) => stream.read(&mut chunk),
+                Socket::Udp(socket) => socket.recv(&mut chunk),
+            };
+            match read {
+                Ok(0) if self.config.transport == RawTransport::Tcp => {
+                    return Err(BackendError::Io(ErrorKind::UnexpectedEof.into()));
+                }
+                Ok(n) => {
+                    self.last_data = Instant::now();
+                    self.buffer.extend_from_slice(&chunk[..n]);
+                    self.drain_frames(&mut rows);
+                    // A datagram is a complete message, so flush any unterminated tail
+                    if self.config.transport == RawTransport::Udp && !self.buffer.is_empty() {
+                        let tail = std::mem::take(&mut self.buffer);
+                        self.handle_frame(&tail, &mut rows);
+                    }
+                }
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => break,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        let idle = self.last_data.elapsed();
+        if self.config.transport == RawTransport::Tcp && idle > Duration::from_millis(self.config.idle_timeout_ms) {
+            return Err(BackendError::Io(std::io::Error::new(
+                ErrorKind::TimedOut,
+                format!("no data from {} for {:?}", self.config.address, idle),
+            )));
+        }
+        Ok(rows)
+    }
+
+    fn execute(&mut self, command: &CommandRequest) -> Result<Option<Row>, BackendError> {
+        Err(BackendError::UnknownCommand(command.command.clone()))
+    }
+
+    fn disconnect(&mut self) {
+        self.socket = None;
+    }
+
+    fn stats(&self) -> serde_json::Value {
+        serde_json::json!({
+            "rows_received": self.rows_received,
+            "malformed_lines": self.malformed_lines,
+            "reconnects": self.connects.saturating_sub(1),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn headers() -> Vec<String> {
+        vec!["temperature".to_string(), "status".to_string()]
+    }
+
+    fn poll_rows(backend: &mut RawBackend, count: usize) -> Vec<Row> {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut rows = Vec::new();
+        while rows.len() < count && Instant::now() < deadline {
+            rows.extend(backend.poll().unwrap());
+        }
+        rows
+    }
+
+    #[test]
+    fn tcp_frames_lines_across_reads() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"temperature,status\n21.5,ok\nbad line\n22.").unwrap();
+            thread::sleep(Duration::from_millis(100));
+            stream.write_all(b"0,alarm\n").unwrap();
+            thread::sleep(Duration::from_secs(1));
+        });
+        let config: RawConfig = serde_json::from_value(serde_json::json!({"address": address})).unwrap();
+        let mut backend = RawBackend::new(config, &headers()).unwrap();
+
+        backend.connect().unwrap();
+        let rows = poll_rows(&mut backend, 2);
+        assert_eq!(rows, vec![vec!["21.5".to_string(), "ok".to_string()], vec!["22.0".to_string(), "alarm".to_string()]]);
+        assert_eq!(backend.stats()["malformed_lines"], 1);
+    }
+
+    #[test]
+    fn udp_treats_each_datagram_as_complete() {
+        let address = UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+        let config: RawConfig = serde_json::from_value(serde_json::json!({"transport": "udp", "address": address})).unwrap();
+        let mut backend = RawBackend::new(config, &headers()).unwrap();
+        backend.connect().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sender.send_to(b"21.5,ok\n22.0,ok", &address).unwrap();
+        sender.send_to(b"23.0,alarm", &address).unwrap();
+        let rows = poll_rows(&mut backend, 3);
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[2], vec!["23.0".to_string(), "alarm".to_string()]);
+    }
+}
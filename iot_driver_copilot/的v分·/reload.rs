@@ -0,0 +1,140 @@
+use actix_web::web;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::config::{self, DriverConfig};
+use crate::{backend, shifu, AppState, Settings};
+
+// ========== Config Sources ==========
+/// Builds the driver config from the config file (or legacy env setup), the
+/// optional Shifu ConfigMap and the `SERVER_*` overrides.
+pub fn load_config() -> Result<DriverConfig, String> {
+    let mut config = match config::config_path() {
+        Some(path) => DriverConfig::load(&path)?,
+        None => DriverConfig::from_env()?,
+    };
+    if let Some(path) = shifu::shifu_config_path() {
+        shifu::ShifuConfig::load(&path)?.apply(&mut config)?;
+        // The overlay adds commands, telemetries and timeouts that need the same checks
+        config.validate().map_err(|e| format!("{}: {}", path, e))?;
+    }
+    config.apply_env_overrides()?;
+    config.fill_default_commands();
+    config.validate()?;
+    Ok(config)
+}
+
+/// Files whose changes trigger a reload; a mounted ConfigMap directory is
+/// watched through its per-key files, which Kubernetes swaps via symlinks.
+fn watched_files() -> Vec<String> {
+    let mut files: Vec<String> = config::config_path().or_else(|| std::env::var("BACKEND_CONFIG").ok()).into_iter().collect();
+    if let Some(path) = shifu::shifu_config_path() {
+        if Path::new(&path).is_dir() {
+            for key in ["driverProperties", "instructions", "telemetries"] {
+                files.push(Path::new(&path).join(key).to_string_lossy().into_owned());
+            }
+        } else {
+            files.push(path);
+        }
+    }
+    files
+}
+
+fn fingerprint(files: &[String]) -> Vec<Option<(SystemTime, u64)>> {
+    files
+        .iter()
+        .map(|f| std::fs::metadata(f).ok().and_then(|m| Some((m.modified().ok()?, m.len()))))
+        .collect()
+}
+
+// ========== Reload ==========
+/// Validates a new config and swaps it in: the backend is rebuilt with the new
+/// mappings, buffered rows are kept (re-laid out if the columns changed) and
+/// command, telemetry and poll settings take effect on the next request or tick.
+pub fn apply(state: &web::Data<AppState>, mut config: DriverConfig) -> Result<(), String> {
+    let headers = config.headers();
+    let max_rows = config.server.max_rows;
+    let history = config.history.clone();
+    let schedules_file = config.schedules.file.clone();
+    let new_backend = std::mem::take(&mut config.backend).build(&headers)?;
+    let current = state.settings();
+    let settings = Arc::new(Settings::from_config(config, current.generation + 1)?);
+    if settings.bind != current.bind {
+        log::warn!("server host/port changes take effect after a restart");
+    }
+    if schedules_file != state.schedules.file() {
+        log::warn!("schedules.file changes take effect after a restart");
+    }
+
+    let mut device = state.backend.lock().unwrap();
+    device.disconnect();
+    *device = new_backend;
+    let mut csv_data = state.csv_data.lock().unwrap();
+    csv_data.reconfigure(headers, max_rows);
+    csv_data.history.reconfigure(&history);
+    drop(csv_data);
+    state.jobs.set_history(settings.job_history);
+    *state.settings.write().unwrap() = settings.clone();
+    drop(device);
+
+    for telemetry in &settings.telemetries {
+        backend::spawn_telemetry(state.clone(), telemetry.clone(), settings.generation);
+    }
+    Ok(())
+}
+
+/// Re-reads the config whenever a watched file changes; an invalid config is
+/// logged and the running one kept.
+pub fn spawn_watcher(state: web::Data<AppState>, interval: Duration) {
+    let files = watched_files();
+    if files.is_empty() {
+        return;
+    }
+    thread::spawn(move || {
+        let mut last = fingerprint(&files);
+        loop {
+            thread::sleep(interval);
+            let current = fingerprint(&files);
+            if current == last {
+                continue;
+            }
+            last = current;
+            match load_config().and_then(|config| apply(&state, config)) {
+                Ok(()) => log::info!("configuration reloaded"),
+                Err(e) => log::error!("configuration reload rejected: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apply_swaps_settings_and_carries_rows_over_by_column() {
+        let state = AppState::for_test("columns: [{name: temperature}, {name: status}]\ncommands: [{name: reset}]");
+        state.csv_data.lock().unwrap().push_row(vec!["21.50".to_string(), "ok".to_string()]);
+
+        let config: DriverConfig = serde_yaml::from_str("columns: [{name: status}, {name: pressure}]\ncommands: [{name: purge}]").unwrap();
+        apply(&state, config).unwrap();
+
+        let settings = state.settings();
+        assert_eq!(settings.generation, 1);
+        assert!(settings.commands.contains("purge") && !settings.commands.contains("reset"));
+        let csv_data = state.csv_data.lock().unwrap();
+        assert_eq!(csv_data.headers, ["status", "pressure"]);
+        assert_eq!(csv_data.rows.back().unwrap(), &["ok", ""]);
+    }
+
+    #[test]
+    fn a_rejected_config_keeps_the_running_one() {
+        let state = AppState::for_test("commands: [{name: reset}]");
+        let config: DriverConfig = serde_yaml::from_str("commands: [{name: bad, params: [{name: x, min: 2, max: 1}]}]").unwrap();
+        assert!(apply(&state, config).is_err());
+        assert_eq!(state.settings().generation, 0);
+        assert!(state.settings().commands.contains("reset"));
+    }
+}
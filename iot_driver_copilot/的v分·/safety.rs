@@ -0,0 +1,316 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::CsvData;
+
+// ========== Safety Rules ==========
+/// Guards a command that drives the device: bounds on its setpoints, how fast
+/// they may move and how often the command may run, and conditions the latest
+/// readings must meet before it is sent.
+#[derive(Deserialize, Serialize, Clone, Default)]
+pub struct SafetyRules {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub setpoints: Vec<SetpointLimit>,
+    /// Minimum time between two successful runs of the command.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_interval_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub interlocks: Vec<Interlock>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct SetpointLimit {
+    /// Request parameter carrying the setpoint.
+    pub param: String,
+    /// Column reporting the current value, for `max_step`; defaults to `param`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub column: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+    /// Largest allowed change from the current value in one write.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_step: Option<f64>,
+}
+
+impl SetpointLimit {
+    fn column(&self) -> &str {
+        self.column.as_deref().unwrap_or(&self.param)
+    }
+}
+
+/// The command is refused unless `column` currently reads `equals`.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Interlock {
+    pub column: String,
+    pub equals: String,
+}
+
+impl SafetyRules {
+    pub fn validate(&self, command: &str) -> Result<(), String> {
+        for limit in &self.setpoints {
+            if let (Some(min), Some(max)) = (limit.min, limit.max) {
+                if min > max {
+                    return Err(format!("command '{}' setpoint '{}' has min above max", command, limit.param));
+                }
+            }
+            if limit.max_step.is_some_and(|step| step <= 0.0) {
+                return Err(format!("command '{}' setpoint '{}' max_step must be positive", command, limit.param));
+            }
+        }
+        if self.min_interval_ms == Some(0) {
+            return Err(format!("command '{}' min_interval_ms must be greater than zero", command));
+        }
+        Ok(())
+    }
+
+    /// Columns the rules read, so the config can check they exist.
+    pub fn columns(&self) -> impl Iterator<Item = &str> {
+        let steps = self.setpoints.iter().filter(|l| l.max_step.is_some()).map(SetpointLimit::column);
+        steps.chain(self.interlocks.iter().map(|i| i.column.as_str()))
+    }
+
+    /// Drops the interlocks and step limits on columns `known` rejects,
+    /// keeping the setpoint bounds.
+    pub fn retain_columns(&mut self, known: impl Fn(&str) -> bool) {
+        for limit in &mut self.setpoints {
+            if !known(limit.column()) {
+                limit.max_step = None;
+            }
+        }
+        self.interlocks.retain(|i| known(&i.column));
+    }
+
+    /// Applies the limits that depend only on the request, returning what is
+    /// left to check against the device state when the command runs.
+    pub fn check_request(
+        &self,
+        command: &str,
+        params: &serde_json::Map<String, serde_json::Value>,
+    ) -> Result<SafetyCheck, SafetyViolation> {
+        let mut setpoints = Vec::new();
+        for limit in &self.setpoints {
+            let Some(value) = params.get(&limit.param) else {
+                continue;
+            };
+            let violation = |rule, message| SafetyViolation::new(command, rule, message);
+            let number = value
+                .as_f64()
+                .filter(|n| n.is_finite())
+                .ok_or_else(|| violation(SafetyRule::NotANumber, format!("{} must be a finite number", limit.param)))?;
+            if let Some(min) = limit.min.filter(|&min| number < min) {
+                return Err(violation(SafetyRule::BelowMin, format!("{} {} is below the safe minimum {}", limit.param, number, min)));
+            }
+            if let Some(max) = limit.max.filter(|&max| number > max) {
+                return Err(violation(SafetyRule::AboveMax, format!("{} {} is above the safe maximum {}", limit.param, number, max)));
+            }
+            if limit.max_step.is_some() {
+                setpoints.push((limit.clone(), number));
+            }
+        }
+        Ok(SafetyCheck {
+            command: command.to_string(),
+            setpoints,
+            min_interval: self.min_interval_ms.map(Duration::from_millis),
+            interlocks: self.interlocks.clone(),
+        })
+    }
+}
+
+// ========== Violations ==========
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SafetyRule {
+    NotANumber,
+    BelowMin,
+    AboveMax,
+    MaxStep,
+    MinInterval,
+    Interlock,
+    /// A rule needs a reading the driver does not have yet.
+    UnknownState,
+}
+
+impl SafetyRule {
+    /// Whether the request could succeed later, once the device state allows it.
+    pub fn is_conflict(self) -> bool {
+        matches!(self, SafetyRule::MinInterval | SafetyRule::Interlock | SafetyRule::UnknownState)
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct SafetyViolation {
+    pub command: String,
+    pub rule: SafetyRule,
+    pub message: String,
+}
+
+impl SafetyViolation {
+    fn new(command: &str, rule: SafetyRule, message: String) -> Self {
+        log::warn!("safety: rejected {}: {}", command, message);
+        SafetyViolation {
+            command: command.to_string(),
+            rule,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for SafetyViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+// ========== Safety Guard ==========
+/// The state-dependent part of a command's rules, checked just before it runs.
+pub struct SafetyCheck {
+    command: String,
+    setpoints: Vec<(SetpointLimit, f64)>,
+    min_interval: Option<Duration>,
+    interlocks: Vec<Interlock>,
+}
+
+/// Remembers when guarded commands last ran; kept across config reloads.
+#[derive(Default)]
+pub struct SafetyGuard {
+    last_runs: Mutex<HashMap<String, Instant>>,
+}
+
+impl SafetyGuard {
+    pub fn check(&self, check: &SafetyCheck, csv_data: &CsvData) -> Result<(), SafetyViolation> {
+        let violation = |rule, message| SafetyViolation::new(&check.command, rule, message);
+        if let Some(min_interval) = check.min_interval {
+            if let Some(last) = self.last_runs.lock().unwrap().get(&check.command) {
+                let wait = min_interval.saturating_sub(last.elapsed());
+                if !wait.is_zero() {
+                    return Err(violation(
+                        SafetyRule::MinInterval,
+                        format!("{} may run again in {} ms", check.command, wait.as_millis()),
+                    ));
+                }
+            }
+        }
+        for interlock in &check.interlocks {
+            match csv_data.latest_value(&interlock.column) {
+                Some(value) if value == interlock.equals => {}
+                Some(value) => {
+                    return Err(violation(
+                        SafetyRule::Interlock,
+                        format!("{} is '{}', {} requires '{}'", interlock.column, value, check.command, interlock.equals),
+                    ))
+                }
+                None => return Err(violation(SafetyRule::UnknownState, format!("{} has not been read yet", interlock.column))),
+            }
+        }
+        for (limit, target) in &check.setpoints {
+            let column = limit.column();
+            let Some(current) = csv_data.latest_value(column).and_then(|v| v.trim().parse::<f64>().ok()) else {
+                return Err(violation(SafetyRule::UnknownState, format!("{} has no numeric reading yet", column)));
+            };
+            let max_step = limit.max_step.unwrap_or(f64::INFINITY);
+            if (target - current).abs() > max_step {
+                return Err(violation(
+                    SafetyRule::MaxStep,
+                    format!("{} {} is more than {} away from the current {}", limit.param, target, max_step, current),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn record(&self, check: &SafetyCheck) {
+        if check.min_interval.is_some() {
+            self.last_runs.lock().unwrap().insert(check.command.clone(), Instant::now());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::BackendError;
+    use crate::jobs::Submission;
+
+    fn rules() -> SafetyRules {
+        serde_json::from_value(serde_json::json!({
+            "setpoints": [{"param": "temperature", "min": -20, "max": 60, "max_step": 5}],
+            "min_interval_ms": 60_000,
+            "interlocks": [{"column": "status", "equals": "ok"}]
+        }))
+        .unwrap()
+    }
+
+    fn params(temperature: f64) -> serde_json::Map<String, serde_json::Value> {
+        serde_json::Map::from_iter([("temperature".to_string(), serde_json::json!(temperature))])
+    }
+
+    fn rule(result: Result<(), SafetyViolation>) -> SafetyRule {
+        result.expect_err("a violation").rule
+    }
+
+    #[test]
+    fn request_limits_are_not_conflicts() {
+        let rules = rules();
+        let below = rules.check_request("set_temp", &params(-25.0)).err().unwrap();
+        assert_eq!(below.rule, SafetyRule::BelowMin);
+        assert!(!below.rule.is_conflict());
+        let above = rules.check_request("set_temp", &params(61.0)).err().unwrap();
+        assert_eq!(above.rule, SafetyRule::AboveMax);
+        assert!(rules.check_request("set_temp", &params(30.0)).is_ok());
+    }
+
+    #[test]
+    fn state_rules_are_checked_against_the_latest_readings() {
+        let guard = SafetyGuard::default();
+        let check = rules().check_request("set_temp", &params(30.0)).unwrap();
+
+        let unread = CsvData::for_test(&["temperature", "status"], &[]);
+        assert_eq!(rule(guard.check(&check, &unread)), SafetyRule::UnknownState);
+        let alarm = CsvData::for_test(&["temperature", "status"], &[&["27.00", "alarm"]]);
+        let interlock = rule(guard.check(&check, &alarm));
+        assert_eq!(interlock, SafetyRule::Interlock);
+        assert!(interlock.is_conflict());
+        let far = CsvData::for_test(&["temperature", "status"], &[&["20.00", "ok"]]);
+        assert_eq!(rule(guard.check(&check, &far)), SafetyRule::MaxStep);
+
+        // An empty cell does not hide the last reading
+        let near = CsvData::for_test(&["temperature", "status"], &[&["27.00", "ok"], &["", "ok"]]);
+        assert!(guard.check(&check, &near).is_ok());
+        guard.record(&check);
+        assert_eq!(rule(guard.check(&check, &near)), SafetyRule::MinInterval);
+    }
+
+    #[test]
+    fn retain_columns_keeps_bounds_only() {
+        let mut rules = rules();
+        rules.retain_columns(|column| column == "temperature_pv");
+        assert!(rules.interlocks.is_empty());
+        assert_eq!(rules.setpoints[0].max_step, None);
+        assert_eq!(rules.setpoints[0].max, Some(60.0));
+    }
+
+    #[test]
+    fn duplicates_replay_instead_of_tripping_the_rules() {
+        let state = crate::AppState::for_test(
+            "commands: [{name: set_temp, params: [temperature], action: {command: write, params: {temperature: $temperature}}, safety: {min_interval_ms: 60000}}]",
+        );
+        crate::jobs::spawn_worker(state.clone());
+        let request = || crate::CommandRequest {
+            command: "set_temp".to_string(),
+            params: Some(serde_json::json!({"temperature": 30})),
+            request_id: Some("r1".to_string()),
+        };
+        let Ok(Submission::Created(job)) = crate::submit_command(&state, request(), None) else {
+            panic!("expected a new job");
+        };
+        state.jobs.wait(&job.id).unwrap();
+        assert!(matches!(crate::submit_command(&state, request(), None), Ok(Submission::Replayed(j)) if j.id == job.id));
+        let fresh = crate::CommandRequest { request_id: Some("r2".to_string()), ..request() };
+        assert!(matches!(crate::submit_command(&state, fresh, None), Err(BackendError::Unsafe(v)) if v.rule == SafetyRule::MinInterval));
+    }
+}
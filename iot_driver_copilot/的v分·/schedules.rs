@@ -0,0 +1,385 @@
+use actix_web::web;
+use chrono::{DateTime, Local, TimeZone};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::jobs::Submission;
+use crate::{AppState, CommandRequest};
+
+const TICK: Duration = Duration::from_millis(500);
+
+// ========== Schedule Config ==========
+fn default_file() -> String {
+    "data/schedules.json".to_string()
+}
+
+#[derive(Deserialize, Clone)]
+pub struct SchedulesConfig {
+    /// Where schedules are persisted so they survive restarts; read once at
+    /// startup, so a change takes effect after a restart rather than a reload.
+    #[serde(default = "default_file")]
+    pub file: String,
+}
+
+impl Default for SchedulesConfig {
+    fn default() -> Self {
+        SchedulesConfig { file: default_file() }
+    }
+}
+
+// ========== Schedule Model ==========
+/// `execute_at` alone runs once; with `interval_secs` it is the first run of a
+/// repeating schedule. `cron` takes a 5-field (minute first) or 6/7-field
+/// (seconds first) expression evaluated in local time.
+#[derive(Deserialize)]
+pub struct ScheduleRequest {
+    pub command: String,
+    pub params: Option<serde_json::Value>,
+    pub execute_at: Option<TimeSpec>,
+    pub interval_secs: Option<u64>,
+    pub cron: Option<String>,
+}
+
+/// Unix seconds or an RFC 3339 timestamp.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum TimeSpec {
+    Unix(u64),
+    Rfc3339(String),
+}
+
+impl TimeSpec {
+    fn to_unix(&self) -> Result<u64, String> {
+        match self {
+            TimeSpec::Unix(secs) => Ok(*secs),
+            TimeSpec::Rfc3339(text) => DateTime::parse_from_rfc3339(text)
+                .map(|t| t.timestamp().max(0) as u64)
+                .map_err(|e| format!("execute_at '{}': {}", text, e)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Trigger {
+    Once { at: u64 },
+    Interval { every_secs: u64 },
+    Cron { expression: String },
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Schedule {
+    pub id: String,
+    pub command: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub params: Option<serde_json::Value>,
+    pub trigger: Trigger,
+    /// Unix seconds of the next run; absent once a one-shot schedule has fired.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_run: Option<u64>,
+    pub created_at: u64,
+    #[serde(default)]
+    pub runs: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_run: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_job_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs()
+}
+
+/// Standard cron numbers Sunday 0 (or 7) and Monday 1; spell days out so the
+/// parser, which starts the week at Sunday = 1, reads them the same way.
+fn day_of_week_names(field: &str) -> String {
+    const DAYS: [&str; 8] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+    field
+        .split(',')
+        .map(|part| {
+            let (range, step) = match part.split_once('/') {
+                Some((range, step)) => (range, Some(step)),
+                None => (part, None),
+            };
+            let range = range
+                .split('-')
+                .map(|d| d.parse::<usize>().ok().and_then(|n| DAYS.get(n)).map(|s| s.to_string()).unwrap_or_else(|| d.to_string()))
+                .collect::<Vec<_>>()
+                .join("-");
+            match step {
+                Some(step) => format!("{}/{}", range, step),
+                None => range,
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn parse_cron(expression: &str) -> Result<cron::Schedule, String> {
+    let fields: Vec<&str> = expression.split_whitespace().collect();
+    let normalized = if fields.len() == 5 {
+        format!("0 {} {} {} {} {}", fields[0], fields[1], fields[2], fields[3], day_of_week_names(fields[4]))
+    } else {
+        expression.to_string()
+    };
+    cron::Schedule::from_str(&normalized).map_err(|e| format!("cron '{}': {}", expression, e))
+}
+
+impl Trigger {
+    fn next_after(&self, after: u64) -> Option<u64> {
+        match self {
+            Trigger::Once { .. } => None,
+            // An interval beyond the representable time simply ends the schedule
+            Trigger::Interval { every_secs } => after.checked_add(*every_secs),
+            Trigger::Cron { expression } => {
+                let from = Local.timestamp_opt(after as i64, 0).single()?;
+                let next = parse_cron(expression).ok()?.after(&from).next()?;
+                Some(next.timestamp().max(0) as u64)
+            }
+        }
+    }
+}
+
+// ========== Schedule Store ==========
+pub struct ScheduleStore {
+    file: String,
+    inner: Mutex<ScheduleFile>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct ScheduleFile {
+    next_id: u64,
+    schedules: BTreeMap<u64, Schedule>,
+}
+
+impl ScheduleStore {
+    /// Loads persisted schedules; runs missed while the driver was down fire on the first tick.
+    pub fn open(file: &str) -> Result<Self, String> {
+        let contents = match std::fs::read_to_string(file) {
+            Ok(raw) => serde_json::from_str(&raw).map_err(|e| format!("{}: {}", file, e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => ScheduleFile::default(),
+            Err(e) => return Err(format!("{}: {}", file, e)),
+        };
+        Ok(ScheduleStore {
+            file: file.to_string(),
+            inner: Mutex::new(contents),
+        })
+    }
+
+    pub fn file(&self) -> &str {
+        &self.file
+    }
+
+    fn save(&self, contents: &ScheduleFile) {
+        let result = (|| {
+            if let Some(dir) = Path::new(&self.file).parent().filter(|d| !d.as_os_str().is_empty()) {
+                std::fs::create_dir_all(dir)?;
+            }
+            // Write then rename so a crash never leaves a truncated file
+            let tmp = format!("{}.tmp", self.file);
+            std::fs::write(&tmp, serde_json::to_vec_pretty(contents)?)?;
+            std::fs::rename(&tmp, &self.file)
+        })();
+        if let Err(e) = result {
+            log::error!("failed to persist schedules to {}: {}", self.file, e);
+        }
+    }
+
+    pub fn create(&self, request: ScheduleRequest) -> Result<Schedule, String> {
+        let now = now();
+        let first = request.execute_at.as_ref().map(TimeSpec::to_unix).transpose()?;
+        let (trigger, next_run) = match (first, request.interval_secs, request.cron) {
+            (_, Some(_), Some(_)) => return Err("use either interval_secs or cron, not both".to_string()),
+            (Some(_), None, Some(_)) => return Err("execute_at cannot be combined with cron".to_string()),
+            (_, Some(0), None) => return Err("interval_secs must be greater than zero".to_string()),
+            (first, Some(every_secs), None) => {
+                let next = match first {
+                    Some(first) => first,
+                    None => now.checked_add(every_secs).ok_or("interval_secs is too large")?,
+                };
+                (Trigger::Interval { every_secs }, next)
+            }
+            (None, None, Some(expression)) => {
+                parse_cron(&expression)?;
+                let trigger = Trigger::Cron { expression };
+                let next = trigger.next_after(now).ok_or("cron expression never fires")?;
+                (trigger, next)
+            }
+            (Some(at), None, None) => (Trigger::Once { at }, at),
+            (None, None, None) => return Err("one of execute_at, interval_secs or cron is required".to_string()),
+        };
+
+        let mut inner = self.inner.lock().unwrap();
+        inner.next_id += 1;
+        let id = inner.next_id;
+        let schedule = Schedule {
+            id: id.to_string(),
+            command: request.command,
+            params: request.params,
+            trigger,
+            next_run: Some(next_run),
+            created_at: now,
+            runs: 0,
+            last_run: None,
+            last_job_id: None,
+            last_error: None,
+        };
+        inner.schedules.insert(id, schedule.clone());
+        self.save(&inner);
+        Ok(schedule)
+    }
+
+    pub fn list(&self) -> Vec<Schedule> {
+        self.inner.lock().unwrap().schedules.values().cloned().collect()
+    }
+
+    pub fn get(&self, id: &str) -> Option<Schedule> {
+        let id = id.parse().ok()?;
+        self.inner.lock().unwrap().schedules.get(&id).cloned()
+    }
+
+    pub fn remove(&self, id: &str) -> Option<Schedule> {
+        let id = id.parse().ok()?;
+        let mut inner = self.inner.lock().unwrap();
+        let removed = inner.schedules.remove(&id)?;
+        self.save(&inner);
+        Some(removed)
+    }
+
+    /// Takes the schedules that are due, advancing each to its next run.
+    fn take_due(&self, now: u64) -> Vec<Schedule> {
+        let mut inner = self.inner.lock().unwrap();
+        let mut due = Vec::new();
+        for schedule in inner.schedules.values_mut() {
+            if schedule.next_run.is_some_and(|t| t <= now) {
+                due.push(schedule.clone());
+                schedule.next_run = schedule.trigger.next_after(now);
+            }
+        }
+        due
+    }
+
+    fn record(&self, id: &str, now: u64, outcome: Result<String, String>) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(schedule) = id.parse().ok().and_then(|id: u64| inner.schedules.get_mut(&id)) {
+            schedule.runs += 1;
+            schedule.last_run = Some(now);
+            match outcome {
+                Ok(job_id) => {
+                    schedule.last_job_id = Some(job_id);
+                    schedule.last_error = None;
+                }
+                Err(e) => schedule.last_error = Some(e),
+            }
+        }
+        self.save(&inner);
+    }
+}
+
+// ========== Scheduler ==========
+/// Submits due schedules as ordinary jobs, validated against the current registry.
+pub fn spawn_scheduler(state: web::Data<AppState>) {
+    thread::spawn(move || loop {
+        let now = now();
+        for schedule in state.schedules.take_due(now) {
+            let request = CommandRequest {
+                command: schedule.command.clone(),
+                params: schedule.params.clone(),
+                request_id: None,
+            };
+            let outcome = match crate::submit_command(&state, request, None) {
+                Ok(Submission::Created(job)) | Ok(Submission::Replayed(job)) => Ok(job.id),
+                Ok(Submission::Conflict) => Err("idempotency conflict".to_string()),
+                Err(e) => Err(e.to_string()),
+            };
+            if let Err(e) = &outcome {
+                log::warn!("schedule {} ({}) failed: {}", schedule.id, schedule.command, e);
+            }
+            state.schedules.record(&schedule.id, now, outcome);
+        }
+        thread::sleep(TICK);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Datelike, Timelike};
+
+    fn store(name: &str) -> ScheduleStore {
+        let file = std::env::temp_dir().join(format!("schedules-test-{}-{}.json", std::process::id(), name));
+        let _ = std::fs::remove_file(&file);
+        ScheduleStore::open(file.to_str().unwrap()).unwrap()
+    }
+
+    fn request(execute_at: Option<u64>, interval_secs: Option<u64>, cron: Option<&str>) -> ScheduleRequest {
+        ScheduleRequest {
+            command: "reset".to_string(),
+            params: None,
+            execute_at: execute_at.map(TimeSpec::Unix),
+            interval_secs,
+            cron: cron.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn day_numbers_follow_standard_cron() {
+        assert_eq!(day_of_week_names("0"), "Sun");
+        assert_eq!(day_of_week_names("1-5"), "Mon-Fri");
+        assert_eq!(day_of_week_names("0,6/2,7"), "Sun,Sat/2,Sun");
+        assert_eq!(day_of_week_names("MON-FRI"), "MON-FRI");
+    }
+
+    #[test]
+    fn five_field_cron_fires_on_the_named_days() {
+        let schedule = parse_cron("30 8 * * 1").unwrap();
+        let from = Local.with_ymd_and_hms(2026, 1, 1, 0, 0, 0).unwrap();
+        for next in schedule.after(&from).take(3) {
+            assert_eq!(next.weekday(), chrono::Weekday::Mon);
+            assert_eq!((next.hour(), next.minute(), next.second()), (8, 30, 0));
+        }
+        assert!(parse_cron("61 * * * *").is_err());
+    }
+
+    #[test]
+    fn create_checks_the_trigger() {
+        let store = store("create");
+        let once = store.create(request(Some(4_000_000_000), None, None)).unwrap();
+        assert_eq!(once.next_run, Some(4_000_000_000));
+        let every = store.create(request(None, Some(60), None)).unwrap();
+        assert!(every.next_run.unwrap() >= every.created_at + 60);
+        assert!(store.create(request(None, Some(0), None)).is_err());
+        assert!(store.create(request(None, Some(u64::MAX), None)).is_err());
+        assert!(store.create(request(None, Some(60), Some("* * * * *"))).is_err());
+        assert!(store.create(request(Some(1), None, Some("* * * * *"))).is_err());
+        assert!(store.create(request(None, None, None)).is_err());
+        assert!(store.create(request(None, None, Some("not cron"))).is_err());
+        assert_eq!(store.list().len(), 2);
+
+        // Persisted and read back by the next start
+        let reopened = ScheduleStore::open(store.file()).unwrap();
+        assert_eq!(reopened.list().len(), 2);
+        assert!(reopened.remove(&once.id).is_some());
+        let _ = std::fs::remove_file(store.file());
+    }
+
+    #[test]
+    fn due_schedules_advance_and_one_shots_finish() {
+        let store = store("due");
+        let once = store.create(request(Some(100), None, None)).unwrap();
+        let every = store.create(request(Some(100), Some(u64::MAX - 50), None)).unwrap();
+        assert_eq!(store.take_due(150).len(), 2);
+        assert_eq!(store.get(&once.id).unwrap().next_run, None);
+        // The next run cannot be represented, so the schedule ends instead of wrapping
+        assert_eq!(store.get(&every.id).unwrap().next_run, None);
+        assert!(store.take_due(u64::MAX).is_empty());
+        let _ = std::fs::remove_file(store.file());
+    }
+}
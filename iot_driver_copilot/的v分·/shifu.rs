@@ -0,0 +1,269 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::commands::{self, CommandDefinition};
+use crate::config::{DriverConfig, TelemetryDefinition};
+
+// Routes served by the driver itself, which commands must not shadow
+pub(crate) const RESERVED_ROUTES: &[&str] = &["info", "data", "cmd", "commands", "schedules", "stream", "stats", "ws"];
+
+// ========== Shifu ConfigMap Layout ==========
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct DriverProperties {
+    driver_sku: Option<String>,
+    driver_image: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct InstructionsDocument {
+    instruction_settings: Option<InstructionSettings>,
+    #[serde(default)]
+    instructions: BTreeMap<String, Option<Instruction>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct InstructionSettings {
+    default_timeout_seconds: Option<u64>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct Instruction {
+    /// Fixed protocol properties, passed to the backend as command parameters.
+    #[serde(default)]
+    protocol_property_list: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct TelemetriesDocument {
+    telemetry_settings: Option<TelemetrySettings>,
+    #[serde(default)]
+    telemetries: BTreeMap<String, Telemetry>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TelemetrySettings {
+    telemetry_update_interval_in_milliseconds: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct Telemetry {
+    properties: TelemetryProperties,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TelemetryProperties {
+    instruction: String,
+    #[serde(default)]
+    initial_delay_ms: u64,
+    interval_ms: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct ConfigMap {
+    data: BTreeMap<String, String>,
+}
+
+/// The three ConfigMap entries, each holding a YAML document.
+pub struct ShifuConfig {
+    driver_properties: DriverProperties,
+    instructions: InstructionsDocument,
+    telemetries: TelemetriesDocument,
+}
+
+fn parse_entry<T: Default + for<'de> Deserialize<'de>>(key: &str, raw: Option<&str>) -> Result<T, String> {
+    match raw {
+        Some(raw) if !raw.trim().is_empty() => serde_yaml::from_str(raw).map_err(|e| format!("{}: {}", key, e)),
+        _ => Ok(T::default()),
+    }
+}
+
+impl ShifuConfig {
+    /// Loads a mounted ConfigMap directory (one file per key) or a ConfigMap manifest file.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let mut entries = BTreeMap::new();
+        if Path::new(path).is_dir() {
+            for key in ["driverProperties", "instructions", "telemetries"] {
+                let file = Path::new(path).join(key);
+                if file.exists() {
+                    let raw = std::fs::read_to_string(&file).map_err(|e| format!("{}: {}", file.display(), e))?;
+                    entries.insert(key.to_string(), raw);
+                }
+            }
+        } else {
+            let raw = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+            let config_map: ConfigMap = serde_yaml::from_str(&raw).map_err(|e| format!("{}: {}", path, e))?;
+            entries = config_map.data;
+        }
+        let config = ShifuConfig {
+            driver_properties: parse_entry("driverProperties", entries.get("driverProperties").map(String::as_str))?,
+            instructions: parse_entry("instructions", entries.get("instructions").map(String::as_str))?,
+            telemetries: parse_entry("telemetries", entries.get("telemetries").map(String::as_str))?,
+        };
+        config.validate().map_err(|e| format!("{}: {}", path, e))?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        for name in self.instructions.instructions.keys() {
+            if !commands::is_routable(name) {
+                return Err(format!("instruction '{}' cannot be served as a route", name));
+            }
+        }
+        for (name, telemetry) in &self.telemetries.telemetries {
+            if !self.instructions.instructions.contains_key(&telemetry.properties.instruction) {
+                return Err(format!(
+                    "telemetry '{}' refers to unknown instruction '{}'",
+                    name, telemetry.properties.instruction
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Overlays instructions, telemetries and driver properties onto a driver config.
+    pub fn apply(self, config: &mut DriverConfig) -> Result<(), String> {
+        if let Some(sku) = self.driver_properties.driver_sku {
+            config.device.device_model = sku;
+        }
+        if let Some(image) = self.driver_properties.driver_image {
+            log::info!("shifu driver image {}", image);
+        }
+        if let Some(seconds) = self.instructions.instruction_settings.and_then(|s| s.default_timeout_seconds) {
+            config.jobs.default_timeout_ms = seconds.max(1) * 1000;
+        }
+        for (name, instruction) in self.instructions.instructions {
+            if config.commands.iter().any(|c| c.name == name) {
+                return Err(format!("instruction '{}' is also declared as a command", name));
+            }
+            config.commands.push(CommandDefinition {
+                defaults: instruction.unwrap_or_default().protocol_property_list,
+                ..CommandDefinition::named(name)
+            });
+        }
+        let default_interval = self
+            .telemetries
+            .telemetry_settings
+            .and_then(|s| s.telemetry_update_interval_in_milliseconds)
+            .unwrap_or(config.server.poll_interval_ms);
+        for (name, telemetry) in self.telemetries.telemetries {
+            config.telemetries.push(TelemetryDefinition {
+                name,
+                instruction: telemetry.properties.instruction,
+                initial_delay_ms: telemetry.properties.initial_delay_ms,
+                interval_ms: telemetry.properties.interval_ms.unwrap_or(default_interval),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Shifu config location from `--shifu-config <path>` or `SHIFU_CONFIG`.
+pub fn shifu_config_path() -> Option<String> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--shifu-config" {
+            return args.next();
+        }
+        if let Some(path) = arg.strip_prefix("--shifu-config=") {
+            return Some(path.to_string());
+        }
+    }
+    std::env::var("SHIFU_CONFIG").ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG_MAP: &str = r#"
+apiVersion: v1
+kind: ConfigMap
+data:
+  driverProperties: |
+    driverSku: chiller-x2
+    driverImage: edgenesis/chiller:v1
+  instructions: |
+    instructionSettings:
+      defaultTimeoutSeconds: 3
+    instructions:
+      get_status:
+      set_mode:
+        protocolPropertyList:
+          register: 40010
+  telemetries: |
+    telemetrySettings:
+      telemetryUpdateIntervalInMilliseconds: 5000
+    telemetries:
+      status:
+        properties:
+          instruction: get_status
+      mode:
+        properties:
+          instruction: set_mode
+          initialDelayMs: 100
+          intervalMs: 250
+"#;
+
+    fn write(name: &str, contents: &str) -> String {
+        let path = std::env::temp_dir().join(format!("shifu-test-{}-{}.yaml", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn overlays_a_config_map_manifest() {
+        let path = write("manifest", CONFIG_MAP);
+        let mut config = DriverConfig::default();
+        ShifuConfig::load(&path).unwrap().apply(&mut config).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(config.device.device_model, "chiller-x2");
+        assert_eq!(config.jobs.default_timeout_ms, 3000);
+        let names: Vec<&str> = config.commands.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["get_status", "set_mode"]);
+        assert_eq!(config.commands[1].defaults["register"], 40010);
+        let telemetries: Vec<(&str, u64, u64)> =
+            config.telemetries.iter().map(|t| (t.name.as_str(), t.initial_delay_ms, t.interval_ms)).collect();
+        assert_eq!(telemetries, [("mode", 100, 250), ("status", 0, 5000)]);
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn reads_a_mounted_directory() {
+        let dir = std::env::temp_dir().join(format!("shifu-test-{}-dir", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("instructions"), "instructions:\n  reset:\n").unwrap();
+        let mut config = DriverConfig::default();
+        ShifuConfig::load(dir.to_str().unwrap()).unwrap().apply(&mut config).unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+        assert_eq!(config.commands.len(), 1);
+        assert!(config.telemetries.is_empty());
+    }
+
+    #[test]
+    fn rejects_unroutable_or_dangling_entries() {
+        let cases = [
+            ("reserved", "data:\n  instructions: |\n    instructions:\n      info:\n"),
+            ("slash", "data:\n  instructions: |\n    instructions:\n      a/b:\n"),
+            ("dangling", "data:\n  telemetries: |\n    telemetries:\n      t:\n        properties:\n          instruction: nope\n"),
+        ];
+        for (name, contents) in cases {
+            let path = write(name, contents);
+            assert!(ShifuConfig::load(&path).is_err(), "{} was accepted", name);
+            let _ = std::fs::remove_file(&path);
+        }
+
+        let path = write("duplicate", "data:\n  instructions: |\n    instructions:\n      reset:\n");
+        let mut config = DriverConfig { commands: vec![CommandDefinition::named("reset".to_string())], ..DriverConfig::default() };
+        assert!(ShifuConfig::load(&path).unwrap().apply(&mut config).is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,366 @@
+use actix_web::body::{BodySize, MessageBody};
+use actix_web::rt::time::{sleep, Instant, Sleep};
+use actix_web::web::Bytes;
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+use crate::history::Record;
+use crate::jobs::row_object;
+
+// Rows a subscriber may fall behind by before it is disconnected
+pub const SUBSCRIBER_CAPACITY: usize = 1024;
+// Missed rows replayed on `Last-Event-ID` resume; older ones are announced
+// with a `gap` event and left for `/data/history`
+pub const MAX_RESUME: usize = 10_000;
+const HEARTBEAT: Duration = Duration::from_secs(15);
+const RETRY_MS: u64 = 3000;
+
+// ========== Live Rows ==========
+/// A row as it is recorded, numbered by its history sequence if it was persisted.
+pub struct LiveRow {
+    pub seq: Option<u64>,
+    pub values: serde_json::Value,
+}
+
+impl LiveRow {
+    pub fn from_record(record: &Record, headers: &[String]) -> Self {
+        LiveRow {
+            seq: Some(record.seq),
+            values: row_object(headers, &record.row(headers)),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Queue {
+    rows: VecDeque<Arc<LiveRow>>,
+    waker: Option<Waker>,
+    /// Fell more than `SUBSCRIBER_CAPACITY` rows behind.
+    lagged: bool,
+    /// Holds just the newest row, so the consumer can never lag.
+    newest_only: bool,
+}
+
+/// One consumer's bounded queue of new rows.
+#[derive(Default)]
+pub struct Subscriber {
+    queue: Mutex<Queue>,
+}
+
+impl Subscriber {
+    /// The next row, or `None` once the subscriber lagged and was cut off.
+    pub fn poll_row(&self, cx: &mut Context<'_>) -> Poll<Option<Arc<LiveRow>>> {
+        let mut queue = self.queue.lock().unwrap();
+        if let Some(row) = queue.rows.pop_front() {
+            return Poll::Ready(Some(row));
+        }
+        if queue.lagged {
+            return Poll::Ready(None);
+        }
+        queue.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    /// Drops everything queued but the newest row, for rate-limited consumers.
+    pub fn latest(&self) -> Option<Arc<LiveRow>> {
+        self.queue.lock().unwrap().rows.drain(..).next_back()
+    }
+
+    /// Switches between queueing every row and keeping only the newest one.
+    pub fn keep_newest(&self, newest_only: bool) {
+        self.queue.lock().unwrap().newest_only = newest_only;
+    }
+
+    fn push(&self, row: &Arc<LiveRow>) {
+        let mut queue = self.queue.lock().unwrap();
+        if queue.lagged {
+            return;
+        }
+        if queue.newest_only {
+            queue.rows.clear();
+            queue.rows.push_back(row.clone());
+        } else if queue.rows.len() >= SUBSCRIBER_CAPACITY {
+            // Slow consumers are dropped rather than buffered without bound;
+            // they resume from the history with Last-Event-ID
+            queue.rows.clear();
+            queue.lagged = true;
+        } else {
+            queue.rows.push_back(row.clone());
+        }
+        if let Some(waker) = queue.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// Fans recorded rows out to live subscribers.
+#[derive(Default)]
+pub struct Broadcaster {
+    subscribers: Vec<Arc<Subscriber>>,
+}
+
+impl Broadcaster {
+    pub fn subscribe(&mut self) -> Arc<Subscriber> {
+        let subscriber = Arc::new(Subscriber::default());
+        self.subscribers.push(subscriber.clone());
+        subscriber
+    }
+
+    pub fn publish(&mut self, row: LiveRow) {
+        // Subscribers whose consumer has gone away are only referenced here
+        self.subscribers.retain(|s| Arc::strong_count(s) > 1);
+        if self.subscribers.is_empty() {
+            return;
+        }
+        let row = Arc::new(row);
+        for subscriber in &self.subscribers {
+            subscriber.push(&row);
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.subscribers.iter().filter(|s| Arc::strong_count(s) > 1).count()
+    }
+}
+
+// ========== Server-Sent Events ==========
+fn event(row: &LiveRow) -> Bytes {
+    let id = row.seq.map(|seq| format!("id: {}\n", seq)).unwrap_or_default();
+    Bytes::from(format!("{}event: row\ndata: {}\n\n", id, row.values))
+}
+
+/// Tells a resuming client that rows after `after` up to `resumes_at` were not
+/// replayed; `cursor` continues `/data/history` from where it left off.
+fn gap(after: u64, resumes_at: u64) -> Bytes {
+    let data = serde_json::json!({"cursor": after.to_string(), "resumes_at": resumes_at});
+    Bytes::from(format!("event: gap\ndata: {}\n\n", data))
+}
+
+/// `text/event-stream` body: replayed rows first, then live ones, with a
+/// comment line as heartbeat whenever the stream has been idle.
+pub struct EventStream {
+    backlog: VecDeque<Bytes>,
+    subscriber: Arc<Subscriber>,
+    heartbeat: Pin<Box<Sleep>>,
+}
+
+impl EventStream {
+    /// `missed` are the rows replayed after the client's `Last-Event-ID`,
+    /// `resumed_after`, up to `newest`, the last row recorded before subscribing.
+    pub fn new(subscriber: Arc<Subscriber>, resumed_after: Option<u64>, newest: u64, missed: Vec<LiveRow>) -> Self {
+        let mut backlog: VecDeque<Bytes> = missed.iter().map(event).collect();
+        // Rows may be missing because there were too many to replay, or because
+        // retention (or a history kept only in memory) no longer has them
+        let resumes_at = missed.first().map_or(Some(newest + 1), |row| row.seq);
+        if let Some((after, resumes_at)) = resumed_after.zip(resumes_at).filter(|&(after, resumes_at)| resumes_at > after + 1) {
+            backlog.push_front(gap(after, resumes_at));
+        }
+        backlog.push_front(Bytes::from(format!("retry: {}\n\n", RETRY_MS)));
+        EventStream {
+            backlog,
+            subscriber,
+            heartbeat: Box::pin(sleep(HEARTBEAT)),
+        }
+    }
+}
+
+impl MessageBody for EventStream {
+    type Error = Infallible;
+
+    fn size(&self) -> BodySize {
+        BodySize::Stream
+    }
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        let this = self.get_mut();
+        let chunk = match this.backlog.pop_front() {
+            Some(chunk) => chunk,
+            None => match this.subscriber.poll_row(cx) {
+                Poll::Ready(Some(row)) => event(&row),
+                Poll::Ready(None) => {
+                    log::warn!("closing a /stream/sse client that fell {} rows behind", SUBSCRIBER_CAPACITY);
+                    return Poll::Ready(None);
+                }
+                Poll::Pending => {
+                    if this.heartbeat.as_mut().poll(cx).is_pending() {
+                        return Poll::Pending;
+                    }
+                    Bytes::from_static(b": heartbeat\n\n")
+                }
+            },
+        };
+        this.heartbeat.as_mut().reset(Instant::now() + HEARTBEAT);
+        Poll::Ready(Some(Ok(chunk)))
+    }
+}
+
+// ========== Followed CSV ==========
+/// Chunked `text/csv` body: the header line and buffered rows once, then each
+/// new row as a CSV line in the header order of when the stream started.
+pub struct CsvStream {
+    snapshot: Option<Bytes>,
+    subscriber: Arc<Subscriber>,
+    headers: Vec<String>,
+    /// Rows still to append before ending, if limited.
+    remaining: Option<u64>,
+    /// Ends the stream once no row arrived for this long.
+    idle: Option<(Duration, Pin<Box<Sleep>>)>,
+}
+
+impl CsvStream {
+    pub fn new(subscriber: Arc<Subscriber>, headers: Vec<String>, snapshot: String, limit: Option<u64>, idle_timeout: Option<Duration>) -> Self {
+        CsvStream {
+            snapshot: Some(Bytes::from(snapshot)),
+            subscriber,
+            headers,
+            remaining: limit,
+            idle: idle_timeout.map(|timeout| (timeout, Box::pin(sleep(timeout)))),
+        }
+    }
+
+    fn line(&self, row: &LiveRow) -> Bytes {
+        let cell = |header: &String| match row.values.get(header) {
+            Some(serde_json::Value::String(value)) => value.clone(),
+            Some(serde_json::Value::Null) | None => String::new(),
+            Some(value) => value.to_string(),
+        };
+        let cells: Vec<String> = self.headers.iter().map(cell).collect();
+        Bytes::from(cells.join(",") + "\n")
+    }
+}
+
+impl MessageBody for CsvStream {
+    type Error = Infallible;
+
+    fn size(&self) -> BodySize {
+        BodySize::Stream
+    }
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        let this = self.get_mut();
+        if let Some(snapshot) = this.snapshot.take() {
+            return Poll::Ready(Some(Ok(snapshot)));
+        }
+        if this.remaining == Some(0) {
+            return Poll::Ready(None);
+        }
+        match this.subscriber.poll_row(cx) {
+            Poll::Ready(Some(row)) => {
+                if let Some(remaining) = this.remaining.as_mut() {
+                    *remaining -= 1;
+                }
+                if let Some((timeout, sleep)) = this.idle.as_mut() {
+                    sleep.as_mut().reset(Instant::now() + *timeout);
+                }
+                Poll::Ready(Some(Ok(this.line(&row))))
+            }
+            Poll::Ready(None) => {
+                log::warn!("closing a /stream follower that fell {} rows behind", SUBSCRIBER_CAPACITY);
+                Poll::Ready(None)
+            }
+            Poll::Pending => match this.idle.as_mut() {
+                Some((_, sleep)) => sleep.as_mut().poll(cx).map(|()| None),
+                None => Poll::Pending,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(seq: u64, temperature: &str) -> LiveRow {
+        LiveRow { seq: Some(seq), values: serde_json::json!({"temperature": temperature}) }
+    }
+
+    async fn next(body: &mut (impl MessageBody<Error = Infallible> + Unpin)) -> Option<String> {
+        let chunk = std::future::poll_fn(|cx| Pin::new(&mut *body).poll_next(cx)).await?;
+        Some(String::from_utf8(chunk.unwrap().to_vec()).unwrap())
+    }
+
+    fn gap_data(after: u64, resumes_at: u64) -> String {
+        format!("event: gap\ndata: {}\n\n", serde_json::json!({"cursor": after.to_string(), "resumes_at": resumes_at}))
+    }
+
+    #[actix_web::test]
+    async fn resume_replays_missed_rows_after_the_retry_hint() {
+        let mut broadcaster = Broadcaster::default();
+        let mut stream = EventStream::new(broadcaster.subscribe(), Some(3), 5, vec![row(4, "20"), row(5, "21")]);
+        assert_eq!(next(&mut stream).await.unwrap(), "retry: 3000\n\n");
+        assert_eq!(next(&mut stream).await.unwrap(), "id: 4\nevent: row\ndata: {\"temperature\":\"20\"}\n\n");
+        assert!(next(&mut stream).await.unwrap().starts_with("id: 5\n"));
+        broadcaster.publish(row(6, "22"));
+        assert!(next(&mut stream).await.unwrap().starts_with("id: 6\n"));
+    }
+
+    #[actix_web::test]
+    async fn unreplayed_rows_are_announced_as_a_gap() {
+        let mut broadcaster = Broadcaster::default();
+        // Too many to replay: only the newest were kept
+        let mut stream = EventStream::new(broadcaster.subscribe(), Some(3), 9, vec![row(8, "20"), row(9, "21")]);
+        next(&mut stream).await;
+        assert_eq!(next(&mut stream).await.unwrap(), gap_data(3, 8));
+        assert!(next(&mut stream).await.unwrap().starts_with("id: 8\n"));
+
+        // Nothing left to replay, as after retention or with an in-memory history
+        let mut stream = EventStream::new(broadcaster.subscribe(), Some(3), 9, Vec::new());
+        next(&mut stream).await;
+        assert_eq!(next(&mut stream).await.unwrap(), gap_data(3, 10));
+
+        // Up to date, or a fresh connection
+        for resumed_after in [Some(9), None] {
+            let mut stream = EventStream::new(broadcaster.subscribe(), resumed_after, 9, Vec::new());
+            next(&mut stream).await;
+            broadcaster.publish(row(10, "22"));
+            assert!(next(&mut stream).await.unwrap().starts_with("id: 10\n"));
+        }
+    }
+
+    #[actix_web::test]
+    async fn lagging_subscribers_are_cut_off() {
+        let mut broadcaster = Broadcaster::default();
+        let mut stream = EventStream::new(broadcaster.subscribe(), None, 0, Vec::new());
+        next(&mut stream).await;
+        for seq in 0..=SUBSCRIBER_CAPACITY as u64 {
+            broadcaster.publish(row(seq, "20"));
+        }
+        assert_eq!(next(&mut stream).await, None);
+        drop(stream);
+        assert_eq!(broadcaster.count(), 0);
+    }
+
+    #[test]
+    fn newest_only_subscribers_never_lag() {
+        let mut broadcaster = Broadcaster::default();
+        let subscriber = broadcaster.subscribe();
+        subscriber.keep_newest(true);
+        for seq in 0..=2 * SUBSCRIBER_CAPACITY as u64 {
+            broadcaster.publish(row(seq, "20"));
+        }
+        assert_eq!(subscriber.latest().unwrap().seq, Some(2 * SUBSCRIBER_CAPACITY as u64));
+        assert!(subscriber.latest().is_none());
+        assert!(!subscriber.queue.lock().unwrap().lagged);
+    }
+
+    #[actix_web::test]
+    async fn follow_ends_after_the_row_limit_or_when_idle() {
+        let headers = vec!["timestamp".to_string(), "temperature".to_string()];
+        let mut broadcaster = Broadcaster::default();
+        let mut limited = CsvStream::new(broadcaster.subscribe(), headers.clone(), "timestamp,temperature\n".to_string(), Some(1), None);
+        assert_eq!(next(&mut limited).await.unwrap(), "timestamp,temperature\n");
+        broadcaster.publish(row(1, "20"));
+        assert_eq!(next(&mut limited).await.unwrap(), ",20\n");
+        assert_eq!(next(&mut limited).await, None);
+
+        let idle = Some(Duration::from_millis(20));
+        let mut idle = CsvStream::new(broadcaster.subscribe(), headers, String::new(), None, idle);
+        next(&mut idle).await;
+        assert_eq!(next(&mut idle).await, None);
+    }
+}
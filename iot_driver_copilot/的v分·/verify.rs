@@ -0,0 +1,188 @@
+use serde::{Deserialize, Serialize};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::backend::DeviceBackend;
+use crate::AppState;
+
+// ========== Verify Config ==========
+fn default_timeout_ms() -> u64 {
+    3000
+}
+
+fn default_interval_ms() -> u64 {
+    200
+}
+
+/// Read-back check after a write: the backend is polled until `column`
+/// reports `value`, or the timeout passes.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct VerifyRule {
+    pub column: String,
+    /// Expected reading, as a literal or `$param`.
+    pub value: serde_json::Value,
+    /// Allowed difference for numeric readings.
+    #[serde(default)]
+    pub tolerance: f64,
+    /// Should stay below the command's job timeout.
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+    #[serde(default = "default_interval_ms")]
+    pub interval_ms: u64,
+}
+
+impl VerifyRule {
+    pub fn validate(&self, command: &str) -> Result<(), String> {
+        if self.tolerance.is_nan() || self.tolerance < 0.0 {
+            return Err(format!("command '{}' verify tolerance must not be negative", command));
+        }
+        if self.timeout_ms == 0 || self.interval_ms == 0 {
+            return Err(format!("command '{}' verify timeout_ms and interval_ms must be greater than zero", command));
+        }
+        Ok(())
+    }
+
+    /// Binds the rule to the value a request expects to read back.
+    pub fn expect(&self, expected: serde_json::Value) -> ReadBack {
+        ReadBack {
+            column: self.column.clone(),
+            expected,
+            tolerance: self.tolerance,
+            timeout: Duration::from_millis(self.timeout_ms),
+            interval: Duration::from_millis(self.interval_ms),
+        }
+    }
+}
+
+pub struct ReadBack {
+    column: String,
+    expected: serde_json::Value,
+    tolerance: f64,
+    timeout: Duration,
+    interval: Duration,
+}
+
+impl ReadBack {
+    fn matches(&self, observed: &str) -> bool {
+        let expected = match &self.expected {
+            serde_json::Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        match (expected.trim().parse::<f64>(), observed.trim().parse::<f64>()) {
+            (Ok(expected), Ok(observed)) => (expected - observed).abs() <= self.tolerance,
+            _ => expected == observed,
+        }
+    }
+}
+
+// ========== Verification ==========
+#[derive(Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum VerifyStatus {
+    Verified,
+    Unverified,
+}
+
+#[derive(Serialize, Clone)]
+pub struct Verification {
+    pub status: VerifyStatus,
+    pub column: String,
+    pub expected: serde_json::Value,
+    /// Last reading of the column seen while verifying.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub observed: Option<String>,
+    pub elapsed_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Polls the backend until the column matches; polled rows are recorded like
+/// the poller's. Blocking, and holds the backend for the duration.
+pub fn read_back(check: &ReadBack, backend: &mut dyn DeviceBackend, state: &AppState) -> Verification {
+    let started = Instant::now();
+    let mut observed = None;
+    let mut error;
+    let status = loop {
+        error = match backend.poll() {
+            Ok(rows) => {
+                let mut csv_data = state.csv_data.lock().unwrap();
+                let index = csv_data.headers.iter().position(|h| *h == check.column);
+                for row in rows {
+                    if let Some(cell) = index.and_then(|i| row.get(i)).filter(|c| !c.is_empty()) {
+                        observed = Some(cell.clone());
+                    }
+                    csv_data.push_row(row);
+                }
+                None
+            }
+            Err(e) => Some(e.to_string()),
+        };
+        if observed.as_deref().is_some_and(|o| check.matches(o)) {
+            break VerifyStatus::Verified;
+        }
+        let remaining = check.timeout.saturating_sub(started.elapsed());
+        if remaining.is_zero() {
+            break VerifyStatus::Unverified;
+        }
+        thread::sleep(remaining.min(check.interval));
+    };
+    if status == VerifyStatus::Unverified {
+        log::warn!(
+            "read-back of {} did not confirm {} (last reading {:?})",
+            check.column,
+            check.expected,
+            observed
+        );
+    }
+    Verification {
+        status,
+        column: check.column.clone(),
+        expected: check.expected.clone(),
+        observed,
+        elapsed_ms: started.elapsed().as_millis() as u64,
+        error,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::SimulatedBackend;
+
+    fn rule(value: serde_json::Value, tolerance: f64, timeout_ms: u64) -> VerifyRule {
+        VerifyRule { column: "temperature".to_string(), value, tolerance, timeout_ms, interval_ms: 10 }
+    }
+
+    #[test]
+    fn numbers_match_within_the_tolerance() {
+        let check = rule(serde_json::json!(30), 0.5, 100).expect(serde_json::json!("30"));
+        assert!(check.matches("30.00"));
+        assert!(check.matches(" 29.6"));
+        assert!(!check.matches("29.4"));
+        let exact = rule(serde_json::json!("ok"), 0.0, 100).expect(serde_json::json!("ok"));
+        assert!(exact.matches("ok") && !exact.matches("ok2"));
+    }
+
+    #[test]
+    fn reads_back_through_the_backend() {
+        let state = AppState::for_test("columns: [{name: temperature}, {name: status}]");
+        let mut backend = SimulatedBackend::new(&["temperature".to_string(), "status".to_string()], 30.0);
+
+        let verified = read_back(&rule(serde_json::json!(30), 0.0, 100).expect(serde_json::json!(30)), &mut backend, &state);
+        assert!(verified.status == VerifyStatus::Verified);
+        assert_eq!(verified.observed.as_deref(), Some("30.00"));
+
+        let unverified = read_back(&rule(serde_json::json!(40), 1.0, 50).expect(serde_json::json!(40)), &mut backend, &state);
+        assert!(unverified.status == VerifyStatus::Unverified);
+        assert!(unverified.elapsed_ms >= 50);
+        // Every poll while verifying is recorded like the poller's
+        assert!(state.csv_data.lock().unwrap().rows.len() >= 2);
+    }
+
+    #[test]
+    fn rejects_negative_tolerance_and_zero_timing() {
+        assert!(rule(serde_json::json!(1), -0.1, 100).validate("c").is_err());
+        assert!(rule(serde_json::json!(1), 0.0, 0).validate("c").is_err());
+        assert!(rule(serde_json::json!(1), 0.0, 100).validate("c").is_ok());
+    }
+}
@@ -0,0 +1,222 @@
+use actix_web::rt::time::{sleep, timeout};
+use actix_web::{rt, web};
+use actix_ws::{CloseCode, CloseReason, Message, MessageStream, Session};
+use serde::Deserialize;
+use std::future::poll_fn;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::backend::BackendError;
+use crate::jobs::Submission;
+use crate::stream::{LiveRow, Subscriber};
+use crate::{AppState, CommandRequest};
+
+const HEARTBEAT: Duration = Duration::from_secs(15);
+
+// ========== Frames ==========
+/// Client frames, JSON text tagged by `type`.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientFrame {
+    /// Starts (or changes) the live row feed; no columns means all of them.
+    Subscribe {
+        #[serde(default)]
+        columns: Vec<String>,
+        /// At most one row per interval, the newest; 0 sends every row.
+        #[serde(default)]
+        interval_ms: u64,
+    },
+    Unsubscribe,
+    /// Runs like `POST /cmd`; the reply carries the same `request_id`.
+    Command(CommandRequest),
+}
+
+#[derive(Clone)]
+struct Subscription {
+    columns: Vec<String>,
+    interval: Duration,
+}
+
+fn error_frame(message: String) -> serde_json::Value {
+    serde_json::json!({"type": "error", "message": message})
+}
+
+fn row_frame(row: &LiveRow, columns: &[String]) -> serde_json::Value {
+    let values = match &row.values {
+        serde_json::Value::Object(values) if !columns.is_empty() => {
+            serde_json::Value::Object(values.iter().filter(|(k, _)| columns.contains(k)).map(|(k, v)| (k.clone(), v.clone())).collect())
+        }
+        values => values.clone(),
+    };
+    serde_json::json!({"type": "row", "seq": row.seq, "values": values})
+}
+
+// ========== Session ==========
+/// Serves one socket: a task reading client frames and one pushing rows.
+pub fn serve(data: web::Data<AppState>, session: Session, messages: MessageStream) {
+    let subscriber = data.csv_data.lock().unwrap().live.subscribe();
+    let subscription = Arc::new(Mutex::new(None));
+    rt::spawn(send_rows(session.clone(), subscriber.clone(), subscription.clone()));
+    rt::spawn(receive(data, session, messages, subscriber, subscription));
+}
+
+async fn receive(
+    data: web::Data<AppState>,
+    mut session: Session,
+    mut messages: MessageStream,
+    subscriber: Arc<Subscriber>,
+    subscription: Arc<Mutex<Option<Subscription>>>,
+) {
+    while let Some(Ok(message)) = messages.recv().await {
+        let reply = match message {
+            Message::Text(text) => match serde_json::from_str(&text) {
+                Ok(frame) => handle_frame(&data, &session, &subscriber, &subscription, frame),
+                Err(e) => Some(error_frame(format!("invalid frame: {}", e))),
+            },
+            Message::Ping(bytes) => {
+                if session.pong(&bytes).await.is_err() {
+                    return;
+                }
+                None
+            }
+            Message::Close(reason) => {
+                let _ = session.close(reason).await;
+                return;
+            }
+            _ => None,
+        };
+        if let Some(reply) = reply {
+            if session.text(reply.to_string()).await.is_err() {
+                return;
+            }
+        }
+    }
+    let _ = session.close(None).await;
+}
+
+fn handle_frame(
+    data: &web::Data<AppState>,
+    session: &Session,
+    subscriber: &Subscriber,
+    subscription: &Mutex<Option<Subscription>>,
+    frame: ClientFrame,
+) -> Option<serde_json::Value> {
+    match frame {
+        ClientFrame::Subscribe { columns, interval_ms } => {
+            let headers = data.csv_data.lock().unwrap().headers.clone();
+            if let Some(unknown) = columns.iter().find(|c| !headers.contains(c)) {
+                return Some(error_frame(format!("Unknown column {}", unknown)));
+            }
+            let reply = serde_json::json!({"type": "subscribed", "columns": columns, "interval_ms": interval_ms});
+            // Rows arriving while a rate-limited feed waits replace each other
+            subscriber.keep_newest(interval_ms > 0);
+            *subscription.lock().unwrap() = Some(Subscription {
+                columns,
+                interval: Duration::from_millis(interval_ms),
+            });
+            Some(reply)
+        }
+        ClientFrame::Unsubscribe => {
+            *subscription.lock().unwrap() = None;
+            Some(serde_json::json!({"type": "unsubscribed"}))
+        }
+        ClientFrame::Command(request) => {
+            // Commands run alongside the row feed and answer when their job finishes
+            let (data, mut session) = (data.clone(), session.clone());
+            rt::spawn(async move {
+                let request_id = request.request_id.clone();
+                let mut reply = run_command(&data, request).await;
+                reply["request_id"] = serde_json::json!(request_id);
+                let _ = session.text(reply.to_string()).await;
+            });
+            None
+        }
+    }
+}
+
+async fn run_command(data: &web::Data<AppState>, request: CommandRequest) -> serde_json::Value {
+    let job = match crate::submit_command(data, request, None) {
+        Ok(Submission::Created(job)) | Ok(Submission::Replayed(job)) => job,
+        Ok(Submission::Conflict) => return error_frame("request_id was already used for a different request".to_string()),
+        Err(BackendError::Unsafe(violation)) => {
+            return serde_json::json!({"type": "error", "message": violation.message, "violation": violation})
+        }
+        Err(e) => return error_frame(e.to_string()),
+    };
+    let state = data.clone();
+    match web::block(move || state.jobs.wait(&job.id)).await {
+        Ok(Some(job)) => serde_json::json!({"type": "result", "job": job}),
+        _ => error_frame("Command execution aborted".to_string()),
+    }
+}
+
+/// Pushes rows for the current subscription, pinging while idle so dead
+/// sockets are noticed. Rate-limited subscriptions get the newest row of
+/// each interval; their subscriber holds only that row, so waiting out the
+/// interval never counts as falling behind.
+async fn send_rows(mut session: Session, subscriber: Arc<Subscriber>, subscription: Arc<Mutex<Option<Subscription>>>) {
+    let mut last_sent: Option<Instant> = None;
+    loop {
+        let mut row = match timeout(HEARTBEAT, poll_fn(|cx| subscriber.poll_row(cx))).await {
+            Ok(Some(row)) => row,
+            Ok(None) => {
+                let reason = CloseReason {
+                    code: CloseCode::Policy,
+                    description: Some("fell too far behind the live rows".to_string()),
+                };
+                let _ = session.close(Some(reason)).await;
+                return;
+            }
+            Err(_) => {
+                if session.ping(b"").await.is_err() {
+                    return;
+                }
+                continue;
+            }
+        };
+        // The receiving task holds the only other handle until the socket closes
+        if Arc::strong_count(&subscription) == 1 {
+            return;
+        }
+        let Some(mut current) = subscription.lock().unwrap().clone() else {
+            continue;
+        };
+        if let Some(wait) = last_sent.map(|t| current.interval.saturating_sub(t.elapsed())).filter(|w| !w.is_zero()) {
+            sleep(wait).await;
+            if let Some(newer) = subscriber.latest() {
+                row = newer;
+            }
+            // The client may have changed or dropped its subscription meanwhile
+            match subscription.lock().unwrap().clone() {
+                Some(changed) => current = changed,
+                None => continue,
+            }
+        }
+        last_sent = Some(Instant::now());
+        if session.text(row_frame(&row, &current.columns).to_string()).await.is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn row_frames_carry_the_subscribed_columns() {
+        let row = LiveRow { seq: Some(7), values: serde_json::json!({"temperature": "21.50", "status": "ok"}) };
+        let frame = row_frame(&row, &["status".to_string()]);
+        assert_eq!(frame, serde_json::json!({"type": "row", "seq": 7, "values": {"status": "ok"}}));
+        assert_eq!(row_frame(&row, &[])["values"], row.values);
+    }
+
+    #[test]
+    fn client_frames_are_tagged_by_type() {
+        let subscribe: ClientFrame = serde_json::from_str(r#"{"type": "subscribe", "columns": ["status"], "interval_ms": 500}"#).unwrap();
+        assert!(matches!(subscribe, ClientFrame::Subscribe { columns, interval_ms: 500 } if columns == ["status"]));
+        let command: ClientFrame = serde_json::from_str(r#"{"type": "command", "command": "reset", "request_id": "a"}"#).unwrap();
+        assert!(matches!(command, ClientFrame::Command(r) if r.command == "reset" && r.request_id.as_deref() == Some("a")));
+        assert!(serde_json::from_str::<ClientFrame>(r#"{"type": "shout"}"#).is_err());
+    }
+}